@@ -0,0 +1,211 @@
+use std::f32::consts::PI;
+use std::hint::black_box;
+use std::sync::Arc;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use glam::Vec3;
+use nebula::camera::{Camera, Tile};
+use nebula::material::Material;
+use nebula::ray::Ray;
+use nebula::render;
+use nebula::scene::primitive::{MovingSphere, Triangle};
+use nebula::scene::{Hittable, Scene};
+
+// 内嵌的测试场景：由四边形拼成的 Cornell Box，中间放一个细分的三角面球体，不依赖外部 OBJ 文件
+
+fn add_quad(scene: &mut Scene, vertices: [Vec3; 4], material: &Arc<Material>) {
+    let [a, b, c, d] = vertices;
+    scene.add(Box::new(Triangle::new(vec![a, b, c], vec![], vec![], material.clone())));
+    scene.add(Box::new(Triangle::new(vec![a, c, d], vec![], vec![], material.clone())));
+}
+
+fn sphere_triangles(center: Vec3, radius: f32, segments: u32, material: &Arc<Material>) -> Vec<Triangle> {
+    let point = |i: u32, j: u32| {
+        let theta = PI * i as f32 / segments as f32;
+        let phi = 2.0 * PI * j as f32 / segments as f32;
+        let normal = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        (center + radius * normal, normal)
+    };
+
+    let mut triangles = vec![];
+    for i in 0..segments {
+        for j in 0..segments {
+            let (p0, n0) = point(i, j);
+            let (p1, n1) = point(i + 1, j);
+            let (p2, n2) = point(i + 1, j + 1);
+            let (p3, n3) = point(i, j + 1);
+            if i != 0 {
+                triangles.push(Triangle::new(vec![p0, p2, p3], vec![n0, n2, n3], vec![], material.clone()));
+            }
+            if i != segments - 1 {
+                triangles.push(Triangle::new(vec![p0, p1, p2], vec![n0, n1, n2], vec![], material.clone()));
+            }
+        }
+    }
+    triangles
+}
+
+fn test_scene() -> Scene {
+    let plaster = Arc::new(Material::PLASTER);
+    let luminous = Arc::new(Material::LUMINOUS);
+    let glass = Arc::new(Material::GLASS);
+
+    let mut scene = Scene::new();
+    let corner = |x: f32, y: f32, z: f32| Vec3::new(x, y, z);
+    // 地面、天花板、后墙、左墙、右墙
+    add_quad(&mut scene, [corner(-1.0, 0.0, 1.0), corner(1.0, 0.0, 1.0), corner(1.0, 0.0, -1.0), corner(-1.0, 0.0, -1.0)], &plaster);
+    add_quad(&mut scene, [corner(-1.0, 2.0, 1.0), corner(-1.0, 2.0, -1.0), corner(1.0, 2.0, -1.0), corner(1.0, 2.0, 1.0)], &plaster);
+    add_quad(&mut scene, [corner(-1.0, 0.0, -1.0), corner(1.0, 0.0, -1.0), corner(1.0, 2.0, -1.0), corner(-1.0, 2.0, -1.0)], &plaster);
+    add_quad(&mut scene, [corner(-1.0, 0.0, 1.0), corner(-1.0, 0.0, -1.0), corner(-1.0, 2.0, -1.0), corner(-1.0, 2.0, 1.0)], &plaster);
+    add_quad(&mut scene, [corner(1.0, 0.0, -1.0), corner(1.0, 0.0, 1.0), corner(1.0, 2.0, 1.0), corner(1.0, 2.0, -1.0)], &plaster);
+    // 光源
+    add_quad(&mut scene, [corner(-0.25, 1.99, 0.25), corner(-0.25, 1.99, -0.25), corner(0.25, 1.99, -0.25), corner(0.25, 1.99, 0.25)], &luminous);
+
+    for triangle in sphere_triangles(Vec3::new(0.0, 0.6, 0.0), 0.5, 32, &glass) {
+        scene.add(Box::new(triangle));
+    }
+    scene
+}
+
+fn test_camera() -> Camera {
+    Camera::new(
+        Vec3::new(0.0, 1.0, 3.0),
+        Vec3::new(0.0, 1.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        60.0,
+        1.0,
+        4.0,
+        0.0,
+    )
+}
+
+fn primary_rays(camera: &Camera, resolution: u32) -> Vec<Ray> {
+    (0..resolution * resolution).map(|index| {
+        let u = (index % resolution) as f32 / resolution as f32;
+        let v = (index / resolution) as f32 / resolution as f32;
+        camera.get_ray(u, v)
+    }).collect()
+}
+
+fn triangle_intersection(c: &mut Criterion) {
+    let material = Arc::new(Material::PLASTER);
+    let triangles = sphere_triangles(Vec3::new(0.0, 1.0, 0.0), 0.5, 16, &material);
+    let rays = primary_rays(&test_camera(), 32);
+
+    let mut group = c.benchmark_group("triangle_intersection");
+    group.throughput(Throughput::Elements((triangles.len() * rays.len()) as u64));
+    group.bench_function("brute_force", |b| b.iter(|| {
+        for ray in &rays {
+            for triangle in &triangles {
+                black_box(triangle.hit(ray, 0.001, f32::MAX));
+            }
+        }
+    }));
+    group.finish();
+}
+
+// 构造次级光线的开销：方向已是单位向量时 new_unchecked 省去一次归一化
+fn ray_construction(c: &mut Criterion) {
+    let rays = primary_rays(&test_camera(), 64);
+
+    let mut group = c.benchmark_group("ray_construction");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    group.bench_function("new", |b| b.iter(|| {
+        for ray in &rays {
+            black_box(Ray::new(black_box(ray.origin), black_box(ray.direction)));
+        }
+    }));
+    group.bench_function("new_unchecked", |b| b.iter(|| {
+        for ray in &rays {
+            black_box(Ray::new_unchecked(black_box(ray.origin), black_box(ray.direction)));
+        }
+    }));
+    group.finish();
+}
+
+fn bvh_build(c: &mut Criterion) {
+    c.bench_function("bvh_build", |b| {
+        b.iter_batched(test_scene, |mut scene| scene.build_bvh(), BatchSize::SmallInput)
+    });
+}
+
+fn scene_intersection(c: &mut Criterion) {
+    let mut scene = test_scene();
+    scene.build_bvh();
+    let rays = primary_rays(&test_camera(), 64);
+
+    let mut group = c.benchmark_group("scene_intersection");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    group.bench_function("primary_rays", |b| b.iter(|| {
+        for ray in &rays {
+            black_box(scene.hit(ray, 0.001, f32::MAX));
+        }
+    }));
+    // 同样数量的主光线按 16x16 的图块成组生成并求交，包含生成光线组的开销
+    let camera = test_camera();
+    group.bench_function("primary_packets", |b| b.iter(|| {
+        for tile in 0..16 {
+            let tile = Tile { x: tile % 4 * 16, y: tile / 4 * 16, width: 16, height: 16, image_width: 64, image_height: 64 };
+            black_box(scene.hit_packet(&camera.get_rays(tile)));
+        }
+    }));
+    group.finish();
+}
+
+// 随机分布在 Cornell Box 内的大量小球，用固定种子的线性同余生成器保证每次相同
+fn sphere_field(count: usize) -> Vec<(Vec3, f32)> {
+    let mut state = 1u32;
+    let mut random = move || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    (0..count).map(|_| {
+        let center = Vec3::new(random() * 2.0 - 1.0, random() * 2.0, random() * 2.0 - 1.0);
+        (center, 0.01 + random() * 0.03)
+    }).collect()
+}
+
+fn sphere_intersection(c: &mut Criterion) {
+    let material = Arc::new(Material::PLASTER);
+    let spheres = sphere_field(5000);
+    let rays = primary_rays(&test_camera(), 64);
+
+    // 静止球体在 BVH 叶子中批量求交，球心不动的 MovingSphere 走逐个求交的通用路径作为对照
+    let mut batched = Scene::new();
+    let mut generic = Scene::new();
+    for &(center, radius) in &spheres {
+        batched.add_sphere(center, radius, material.clone());
+        generic.add(Box::new(MovingSphere::new(center, center, 0.0, 1.0, radius, material.clone())));
+    }
+    batched.build_bvh();
+    generic.build_bvh();
+
+    let mut group = c.benchmark_group("sphere_intersection");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    for (name, scene) in [("batched", &batched), ("generic", &generic)] {
+        group.bench_function(name, |b| b.iter(|| {
+            for ray in &rays {
+                black_box(scene.hit(ray, 0.001, f32::MAX));
+            }
+        }));
+    }
+    group.finish();
+}
+
+fn full_frame(c: &mut Criterion) {
+    let mut scene = test_scene();
+    scene.build_bvh();
+    let scene = Arc::new(scene);
+    let camera = Arc::new(test_camera());
+    let (width, height, samples_per_pixel) = (32, 32, 4);
+
+    let mut group = c.benchmark_group("full_frame");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((width * height * samples_per_pixel) as u64));
+    group.bench_function("32x32_4spp", |b| b.iter(|| {
+        render::render(scene.clone(), camera.clone(), width, height, 5, samples_per_pixel)
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, triangle_intersection, ray_construction, bvh_build, scene_intersection, sphere_intersection, full_frame);
+criterion_main!(benches);
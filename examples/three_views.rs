@@ -0,0 +1,41 @@
+// 从三个角度渲染同一个场景：BVH 只在开始时构建一次，之后每次渲染只更换摄像机，
+// 场景以 Arc 共享，不会被复制或重新处理
+//
+// 在仓库根目录运行 `cargo run --release --example three_views`
+
+use std::sync::Arc;
+use std::time::Instant;
+use glam::{Mat4, Vec3};
+use nebula::camera::Camera;
+use nebula::render;
+use nebula::scene::Scene;
+
+fn main() {
+    let image_width = 320;
+    let image_height = 200;
+    let max_depth = 5;
+    let samples_per_pixel = 64;
+
+    let mut scene = Scene::new();
+    scene.add_obj("scenes/CornellBoxSphere.obj", Mat4::IDENTITY);
+
+    // 唯一开销较大的一次性步骤
+    let start = Instant::now();
+    scene.build_bvh();
+    println!("{:?} for building BVH of {} objects.", start.elapsed(), scene.objects.len());
+    let scene = Arc::new(scene);
+
+    // 摄像机绕场景中心水平旋转，分别位于左侧、正前方与右侧
+    let look_at = Vec3::new(0.0, 1.0, 0.0);
+    let aspect_ratio = image_width as f32 / image_height as f32;
+    for (index, yaw) in [-30.0f32, 0.0, 30.0].into_iter().enumerate() {
+        let look_from = look_at + Mat4::from_rotation_y(yaw.to_radians()).transform_vector3(Vec3::new(0.0, 0.0, 3.0));
+        let camera = Arc::new(Camera::new(look_from, look_at, Vec3::Y, 60.0, aspect_ratio, 3.0, 0.0));
+
+        let start = Instant::now();
+        let image = render::render(scene.clone(), camera, image_width, image_height, max_depth, samples_per_pixel);
+        let filename = format!("three_views_{index}.png");
+        println!("{:?} for rendering {filename}.", start.elapsed());
+        render::save_image_as_png(image, image_width, image_height, &filename);
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+const MAGIC: &str = "NEBULA-ACCUMULATION 1";
+
+/// HDR 累积缓冲区，记录每个像素所有样本颜色之和与已完成的每像素采样数，
+/// 可以保存到文件，之后载入继续渲染，例如分多次完成 10000 spp 的渲染
+///
+/// 文件格式为两行文本头 `NEBULA-ACCUMULATION 1` 与 `宽 高 采样数`，
+/// 之后是按行优先顺序排列的 RGB 颜色之和，每个分量为小端序的 f32
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accumulation {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32, // 已完成的每像素采样数
+    pub sum: Vec<f32>, // 每个像素样本颜色之和，与 render_hdr 的输出排列相同
+}
+
+impl Accumulation {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, samples: 0, sum: vec![0.0; width as usize * height as usize * 3] }
+    }
+
+    /// 加入一次渲染得到的平均颜色，`samples` 为这次渲染的每像素采样数
+    pub fn add(&mut self, image_data: &[f32], samples: u32) {
+        assert_eq!(image_data.len(), self.sum.len(), "Image data does not match the buffer size");
+        for (sum, color) in self.sum.iter_mut().zip(image_data) {
+            *sum += color * samples as f32;
+        }
+        self.samples += samples;
+    }
+
+    /// 当前的平均颜色，即未经截断的线性浮点 RGB 数据，还没有样本时为全黑
+    pub fn image(&self) -> Vec<f32> {
+        let samples = self.samples.max(1) as f32;
+        self.sum.iter().map(|sum| sum / samples).collect()
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(filename: &str) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(filename)?))
+    }
+
+    /// 写入任意 writer
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{MAGIC}\n{} {} {}", self.width, self.height, self.samples)?;
+        for value in &self.sum {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// 从任意 reader 读取
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim_end() != MAGIC {
+            return Err(invalid("Not an accumulation buffer"));
+        }
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.split_whitespace()
+            .map(|x| x.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("Invalid accumulation buffer header"))?;
+        let [width, height, samples] = header[..] else {
+            return Err(invalid("Invalid accumulation buffer header"));
+        };
+
+        let length = (width as usize).checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(3 * 4))
+            .ok_or_else(|| invalid("Accumulation buffer is too large"))?;
+        // 按实际读到的数据增长缓冲区，损坏的文件头不会一次分配巨大的内存
+        let mut bytes = Vec::new();
+        reader.take(length as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Accumulation buffer is truncated"));
+        }
+        let sum = bytes.chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        Ok(Self { width, height, samples, sum })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut accumulation = Accumulation::new(2, 1);
+        accumulation.add(&[0.5, 1.0, 2.0, 0.0, 0.25, 4.0], 3);
+        let mut bytes = vec![];
+        accumulation.write(&mut bytes).unwrap();
+        assert_eq!(Accumulation::read(&mut bytes.as_slice()).unwrap(), accumulation);
+    }
+
+    #[test]
+    fn oversized_header_is_invalid_data() {
+        let mut bytes = format!("{MAGIC}\n{} {} 1\n", u32::MAX, u32::MAX).into_bytes();
+        bytes.extend_from_slice(&[0; 12]);
+        let error = Accumulation::read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let mut bytes = format!("{MAGIC}\n1000 1000 1\n").into_bytes();
+        bytes.extend_from_slice(&[0; 12]);
+        let error = Accumulation::read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+use glam::{Mat4, Vec2, Vec3};
+use rand::Rng;
+use crate::material::luminance;
+use crate::rand_util::Distribution2D;
+use crate::ray::Ray;
+use crate::texture::{self, Texture};
+
+// 环境贴图重要性采样的网格宽度上限，较大的贴图按 mipmap 的平均颜色构建分布
+const ENVIRONMENT_GRID_WIDTH: u32 = 512;
+
+/// 光线未击中任何物体时返回的背景颜色
+#[derive(Debug, Clone, Default)]
+pub enum Background {
+    /// 纯黑背景，适用于 Cornell Box 这类封闭场景
+    #[default]
+    Black,
+    /// 竖直方向的渐变，按光线方向的 y 分量在 `bottom` 与 `top` 之间线性插值
+    Gradient { top: Vec3, bottom: Vec3 },
+    /// 等距柱状投影（equirectangular）的环境贴图，查询前先用 `rotation` 变换光线方向，
+    /// 不需要重新制作贴图就可以转动环境，例如调整太阳所在的方位
+    Environment { texture: Texture, rotation: Mat4, intensity: f32 },
+}
+
+impl Background {
+    // 经典的蓝天渐变
+    pub const SKY: Self = Background::Gradient {
+        top: Vec3::new(0.5, 0.7, 1.0),
+        bottom: Vec3::ONE,
+    };
+
+    /// 使用环境贴图作为背景，`yaw` 为绕竖直轴旋转的角度，角度制
+    pub fn environment(texture: Texture, yaw: f32) -> Self {
+        Background::Environment {
+            texture,
+            rotation: Mat4::from_rotation_y(yaw.to_radians()),
+            intensity: 1.0,
+        }
+    }
+
+    /// 计算沿光线方向看到的背景颜色
+    pub fn color(&self, ray: &Ray) -> Vec3 {
+        match self {
+            Background::Black => Vec3::ZERO,
+            Background::Gradient { top, bottom } => {
+                let t = 0.5 * (ray.direction.y + 1.0);
+                bottom.lerp(*top, t)
+            }
+            Background::Environment { texture, rotation, intensity } => {
+                let direction = rotation.transform_vector3(ray.direction).normalize();
+                // 从球内看贴图，沿 z 轴镜像后 -z 方向位于贴图中央，向 +x 增大，贴图同样正立、不镜像
+                let uv = texture::spherical_uv(direction * Vec3::new(1.0, 1.0, -1.0));
+                texture.sample(uv.x, uv.y) * *intensity
+            }
+        }
+    }
+}
+
+/// 对背景直接采样（作为光源）时的方向范围：以 `axis` 为轴、半顶角为 `half_angle`（角度制）的圆锥，
+/// 默认为整个球面。室内场景只有开口一侧能看到环境时，把范围限制在开口方向，
+/// 阴影光线不会浪费在被墙壁挡住的方向上；范围之外的环境光仍由散射光线得到，结果不变、只是噪声不同
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvironmentCone {
+    pub axis: Vec3,
+    pub half_angle: f32, // 属于 (0, 180]，180 为整个球面，90 为以 axis 为中心的半球
+}
+
+impl Default for EnvironmentCone {
+    fn default() -> Self {
+        Self { axis: Vec3::Y, half_angle: 180.0 }
+    }
+}
+
+impl EnvironmentCone {
+    /// 以 `axis` 为中心的半球
+    pub fn hemisphere(axis: Vec3) -> Self {
+        Self { axis, half_angle: 90.0 }
+    }
+
+    fn cos_max(&self) -> f32 {
+        self.half_angle.clamp(0.0, 180.0).to_radians().cos()
+    }
+
+    /// 方向是否在圆锥内
+    pub fn contains(&self, direction: Vec3) -> bool {
+        direction.normalize().dot(self.axis.normalize()) >= self.cos_max()
+    }
+
+    /// 在圆锥内按立体角均匀采样一个单位方向，同时返回概率密度（立体角），即圆锥立体角的倒数
+    pub fn sample(&self, rng: &mut impl Rng) -> (Vec3, f32) {
+        let cos_max = self.cos_max();
+        let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+        let axis = self.axis.normalize();
+        let (tangent, bitangent) = axis.any_orthonormal_pair();
+        let direction = sin_theta * (phi.cos() * tangent + phi.sin() * bitangent) + cos_theta * axis;
+        (direction, 1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_max)))
+    }
+}
+
+/// 按亮度对环境贴图进行重要性采样的分布，方向被选中的概率与该方向的背景亮度
+/// （加上散射后逃逸的光线额外得到的环境辐亮度）成正比，太阳等明亮的小区域可以用很少的阴影光线找到
+#[derive(Debug, Clone)]
+pub struct EnvironmentDistribution {
+    distribution: Distribution2D,
+    inverse_rotation: Mat4, // 将贴图空间的方向变换回世界空间
+}
+
+impl EnvironmentDistribution {
+    /// 背景为环境贴图时构建分布，否则返回 None；`environment_radiance` 与 `RenderOptions` 中的相同
+    pub fn new(background: &Background, environment_radiance: Vec3) -> Option<Self> {
+        let Background::Environment { texture, rotation, intensity } = background else {
+            return None;
+        };
+        let (texture_width, texture_height) = texture.dimensions();
+        let width = texture_width.clamp(1, ENVIRONMENT_GRID_WIDTH);
+        let height = (texture_height * width / texture_width.max(1)).max(1);
+        let footprint = 1.0 / width as f32;
+        let mut weights = Vec::with_capacity((width * height) as usize);
+        for row in 0..height {
+            let v = (row as f32 + 0.5) / height as f32;
+            // 等距柱状投影中每一行对应的立体角与纬度的余弦成正比
+            let cos_latitude = ((v - 0.5) * PI).cos();
+            for column in 0..width {
+                let u = (column as f32 + 0.5) / width as f32;
+                let radiance = texture.sample_filtered(u, v, footprint) * *intensity + environment_radiance;
+                weights.push(luminance(radiance) * cos_latitude);
+            }
+        }
+        // 每个单元至少保留平均权重的千分之一，mipmap 平均时被低估的暗处仍然可能被采样到，估计保持无偏
+        let floor = weights.iter().sum::<f32>() / weights.len() as f32 * 1e-3;
+        weights.iter_mut().for_each(|weight| *weight = weight.max(floor));
+        let distribution = Distribution2D::new(width as usize, height as usize, &weights)?;
+        Some(Self { distribution, inverse_rotation: rotation.inverse() })
+    }
+
+    /// 按分布采样一个世界空间的单位方向，同时返回概率密度（立体角）
+    pub fn sample(&self, rng: &mut impl Rng) -> (Vec3, f32) {
+        let (uv, pdf) = self.distribution.sample(Vec2::new(rng.gen(), rng.gen()));
+        // spherical_uv 的逆映射，再撤销 Background::color 中沿 z 轴的镜像与旋转
+        let longitude = (uv.x - 0.5) * 2.0 * PI;
+        let latitude = (uv.y - 0.5) * PI;
+        let local = Vec3::new(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            -latitude.cos() * longitude.cos(),
+        );
+        let direction = self.inverse_rotation.transform_vector3(local).normalize();
+        // 贴图上单位面积对应的立体角为 2π · π · cos(纬度)
+        let jacobian = 2.0 * PI * PI * latitude.cos();
+        let pdf = if jacobian > 0.0 { pdf / jacobian } else { 0.0 };
+        (direction, pdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // 64x32 的暗色环境贴图，左上方有一块 2x2 的亮斑，绕竖直轴旋转 90°
+    fn sunny_background() -> Background {
+        let mut image = image::RgbImage::from_pixel(64, 32, image::Rgb([2, 2, 2]));
+        for (x, y) in [(20, 8), (21, 8), (20, 9), (21, 9)] {
+            image.put_pixel(x, y, image::Rgb([255, 255, 255]));
+        }
+        let mut bytes = Cursor::new(vec![]);
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        Background::environment(Texture::load_from_memory(bytes.get_ref()).unwrap(), 90.0)
+    }
+
+    fn brightness(background: &Background, direction: Vec3) -> f32 {
+        luminance(background.color(&Ray::new(Vec3::ZERO, direction)))
+    }
+
+    #[test]
+    fn environment_sampling_matches_uniform_sampling() {
+        let background = sunny_background();
+        let environment = EnvironmentDistribution::new(&background, Vec3::ZERO).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // 均匀采样整个球面估计的总亮度作为参考
+        let uniform = (0..200_000).map(|_| {
+            let z = rng.gen::<f32>() * 2.0 - 1.0;
+            let phi = 2.0 * PI * rng.gen::<f32>();
+            let r = (1.0 - z * z).sqrt();
+            brightness(&background, Vec3::new(r * phi.cos(), r * phi.sin(), z)) * 4.0 * PI
+        }).sum::<f32>() / 200_000.0;
+
+        let mut bright = 0;
+        let importance = (0..4_000).map(|_| {
+            let (direction, pdf) = environment.sample(&mut rng);
+            assert!(direction.is_normalized() && pdf > 0.0);
+            let value = brightness(&background, direction);
+            if value > 0.5 {
+                bright += 1;
+            }
+            value / pdf
+        }).sum::<f32>() / 4_000.0;
+
+        assert!((importance - uniform).abs() < 0.03 * uniform, "{importance} {uniform}");
+        // 亮斑只占贴图的千分之二，却贡献了约四分之一的亮度，样本落在亮斑上的比例应与之相当
+        assert!(bright > 800, "{bright}");
+    }
+
+    #[test]
+    fn only_environment_maps_are_importance_sampled() {
+        assert!(EnvironmentDistribution::new(&Background::SKY, Vec3::ONE).is_none());
+    }
+
+    #[test]
+    fn cone_samples_come_from_the_given_generator() {
+        let cone = EnvironmentCone { axis: Vec3::Y, half_angle: 30.0 };
+        let samples = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..100).map(|_| cone.sample(&mut rng)).collect::<Vec<_>>()
+        };
+        let first = samples(3);
+        assert_eq!(first, samples(3));
+        assert_ne!(first, samples(4));
+        for (direction, pdf) in first {
+            assert!(cone.contains(direction));
+            assert!(pdf > 0.0);
+        }
+    }
+}
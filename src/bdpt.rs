@@ -0,0 +1,316 @@
+use std::f32::consts::PI;
+use glam::Vec3;
+use crate::material::{luminance, Material, ScatterKind, ScatteredRay};
+use crate::rand_util;
+use crate::ray::Ray;
+use crate::render::{self, RenderOptions, T_MAX, T_MIN};
+use crate::scene::{HitRecord, Scene};
+
+// 子路径顶点的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VertexKind {
+    Camera,  // 摄像机
+    Light,   // 光源上的采样点
+    Surface, // 光线与物体表面的交点
+}
+
+// 子路径上的顶点
+#[derive(Clone, Copy)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    point: Vec3,
+    normal: Vec3, // 表面顶点的法向朝向到达该顶点的光线一侧，光源顶点为外法向
+    hit: Option<HitRecord<'a>>, // 表面顶点的碰撞信息
+    emissive: Vec3, // 顶点处的自发光
+    beta: Vec3, // 到达该顶点时子路径的吞吐量
+    diffuse_probability: f32, // 在该顶点选择漫反射分量继续采样的概率
+    delta: bool, // 是否沿镜面反射或透射继续，这两个分量视为 delta 分布，不能用于连接
+    emitting_sides: f32, // 顶点作为光源时发光的面数
+    pdf_fwd: f32, // 沿子路径方向生成该顶点的面积概率密度
+    pdf_rev: f32, // 沿相反方向生成该顶点的面积概率密度
+}
+
+impl<'a> Vertex<'a> {
+    fn new(kind: VertexKind, point: Vec3, normal: Vec3, beta: Vec3) -> Self {
+        Self {
+            kind,
+            point,
+            normal,
+            hit: None,
+            emissive: Vec3::ZERO,
+            beta,
+            diffuse_probability: 0.0,
+            delta: false,
+            emitting_sides: 2.0,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    // 将在本顶点处采样方向的立体角概率密度转换为 next 处的面积概率密度
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let w = next.point - self.point;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.kind != VertexKind::Camera {
+            pdf *= next.normal.dot(w / distance_squared.sqrt()).abs();
+        }
+        pdf
+    }
+
+    // 从本顶点出发采样到 next 的面积概率密度，表面顶点只考虑漫反射分量
+    fn pdf(&self, next: &Vertex) -> f32 {
+        match (self.kind, self.hit) {
+            (VertexKind::Light, _) => self.pdf_light(next),
+            (VertexKind::Surface, Some(hit)) => {
+                let pdf = hit.material.pdf(&hit, (next.point - self.point).normalize());
+                self.convert_density(pdf * self.diffuse_probability, next)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // 把本顶点当作光源，向 next 发射光线的面积概率密度，双面发光时两面各占一半，方向按余弦分布
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let cos = self.normal.dot((next.point - self.point).normalize()).abs();
+        self.convert_density(cos / (self.emitting_sides * PI), next)
+    }
+
+    // 从 prev 到达本顶点、再散射向 next 的 BRDF
+    fn f(&self, prev: &Vertex, next: &Vertex) -> Vec3 {
+        match self.hit {
+            Some(hit) => hit.material.eval(
+                &hit,
+                (prev.point - self.point).normalize(),
+                (next.point - self.point).normalize(),
+            ),
+            None => Vec3::ZERO,
+        }
+    }
+}
+
+/// 双向路径追踪：从摄像机与光源各追踪一条子路径，连接两条子路径上的顶点，
+/// 并使用多重重要性采样（平衡启发式）合并各种连接方式的结果。
+///
+/// 不实现直接连接到摄像机的光线追踪（t = 1），镜面反射与透射视为 delta 分布，不参与连接。
+/// 路径上最多发生 `max_depth` 次散射，与 `render::ray_color` 一致。
+/// 同时返回主光线是否击中物体，即摄像机子路径是否有表面顶点
+pub(crate) fn radiance(ray: &Ray, scene: &Scene, max_depth: u32, options: &RenderOptions) -> (Vec3, bool) {
+    let max_vertices = max_depth as usize + 2; // 摄像机顶点与最多 max_depth + 1 个表面顶点
+    let mut radiance = Vec3::ZERO;
+    let clay = options.clay.map(Material::clay);
+
+    let mut camera_path = vec![Vertex::new(VertexKind::Camera, ray.origin, Vec3::ZERO, Vec3::ONE)];
+    if let Some((ray, beta)) = random_walk(scene, clay.as_ref(), *ray, Vec3::ONE, 1.0, max_vertices, &mut camera_path) {
+        radiance += beta * render::miss_color(&ray, options, camera_path.len() as u32 - 1);
+    }
+    // 环境光只能由摄像机子路径得到，与路径追踪一样乘以环境光遮蔽
+    for vertex in &camera_path {
+        let Some(hit) = vertex.hit else {
+            continue;
+        };
+        let ambient = hit.material.ambient_color(hit.uv);
+        if ambient.max_element() > 0.0 {
+            let occlusion = render::ambient_occlusion(scene, &hit, options.ambient_occlusion, ray.time);
+            radiance += vertex.beta * ambient * occlusion;
+        }
+    }
+
+    let mut light_path = vec![];
+    if let Some((light, _)) = scene.pick_light() {
+        let sample = light.sample_point().unwrap();
+        let material = light.material().unwrap();
+        let emissive = material.emissive_color();
+        let sides = material.emitting_sides();
+        let pdf_position = scene.light_pdf(material);
+        let mut vertex = Vertex::new(VertexKind::Light, sample.point, sample.normal, emissive / pdf_position);
+        vertex.emissive = emissive;
+        vertex.emitting_sides = sides;
+        vertex.pdf_fwd = pdf_position;
+        light_path.push(vertex);
+
+        let normal = if sides > 1.0 && rand_util::random::<bool>() { -sample.normal } else { sample.normal };
+        let (direction, pdf_direction) = rand_util::random_unit_vector_cosine_pdf(normal);
+        let pdf_direction = pdf_direction / sides;
+        // 吞吐量为 辐亮度 * cos / (位置概率密度 * 方向概率密度)
+        let beta = emissive * sides * PI / pdf_position;
+        let ray = Ray::new_unchecked(sample.point, direction).with_time(ray.time);
+        random_walk(scene, clay.as_ref(), ray, beta, pdf_direction, max_vertices - 1, &mut light_path);
+    }
+
+    for t in 2..=camera_path.len() {
+        for s in 0..=light_path.len().min(max_vertices - t) {
+            radiance += connect(scene, &light_path, &camera_path, s, t, ray.time);
+        }
+    }
+    (radiance, camera_path.len() > 1)
+}
+
+// 从 path 的最后一个顶点出发沿 ray 追踪子路径，直到顶点数达到 max_vertices，
+// 光线逃出场景时返回该光线与此时的吞吐量；白模渲染时表面材质换成 `clay`
+fn random_walk<'a>(
+    scene: &'a Scene,
+    clay: Option<&'a Material>,
+    mut ray: Ray,
+    mut beta: Vec3,
+    mut pdf_direction: f32,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) -> Option<(Ray, Vec3)> {
+    while path.len() < max_vertices {
+        let Some(hit) = scene.hit(&ray, T_MIN, T_MAX) else {
+            return Some((ray, beta));
+        };
+        let hit = render::with_clay(hit, clay);
+        let m = hit.material;
+        let prev = path.len() - 1;
+        let mut vertex = Vertex::new(VertexKind::Surface, hit.point, hit.normal, beta);
+        vertex.hit = Some(hit);
+        vertex.emissive = m.emitted(&hit);
+        vertex.emitting_sides = m.emitting_sides();
+        vertex.pdf_fwd = path[prev].convert_density(pdf_direction, &vertex);
+
+        // 按各分量系数的亮度随机选择一个分量继续（俄罗斯轮盘），与 PhotonMap 相同
+        let mut scattered_rays = m.scatter(&ray, hit, &mut rand_util::LocalRng);
+        render::project_subsurface_exits(scene, &hit, &mut scattered_rays);
+        let total = scattered_rays.iter().map(|s| luminance(s.coefficient)).sum::<f32>().max(1.0);
+        vertex.diffuse_probability = scattered_rays.iter()
+            .filter(|s| s.kind == ScatterKind::Diffuse)
+            .map(|s| luminance(s.coefficient))
+            .sum::<f32>() / total;
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let mut pick = rand_util::random::<f32>() * total;
+        let Some(&ScatteredRay { ray: scattered, coefficient, kind, pdf }) = scattered_rays.iter().find(|s| {
+            pick -= luminance(s.coefficient);
+            pick < 0.0
+        }) else {
+            break;
+        };
+        beta *= coefficient * total / luminance(coefficient);
+
+        let (pdf_fwd, pdf_rev) = if kind == ScatterKind::Diffuse {
+            let probability = vertex.diffuse_probability;
+            (
+                pdf * probability,
+                m.pdf(&hit, -ray.direction) * probability,
+            )
+        } else {
+            path.last_mut().unwrap().delta = true;
+            (0.0, 0.0)
+        };
+        path[prev].pdf_rev = vertex.convert_density(pdf_rev, &path[prev]);
+        pdf_direction = pdf_fwd;
+        ray = scattered;
+    }
+    None
+}
+
+// 使用光源子路径的前 s 个顶点与摄像机子路径的前 t 个顶点构成完整路径，返回加权后的贡献，
+// 两条子路径都处于时刻 time
+fn connect(
+    scene: &Scene,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+    time: f32,
+) -> Vec3 {
+    let pt = &camera_path[t - 1];
+    if pt.kind != VertexKind::Surface {
+        return Vec3::ZERO;
+    }
+
+    let contribution = if s == 0 {
+        // 摄像机子路径直接击中光源
+        pt.beta * pt.emissive
+    } else {
+        let qs = &light_path[s - 1];
+        let f_camera = pt.f(&camera_path[t - 2], qs);
+        let f_light = if s == 1 {
+            // 单面光源只向外法向一侧发光
+            let front = qs.normal.dot(pt.point - qs.point) > 0.0;
+            if qs.emitting_sides > 1.0 || front { Vec3::ONE } else { Vec3::ZERO }
+        } else {
+            qs.f(&light_path[s - 2], pt)
+        };
+        if f_camera.max_element() <= 0.0 || f_light.max_element() <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let w = qs.point - pt.point;
+        let distance_squared = w.length_squared();
+        let w = w / distance_squared.sqrt();
+        let g = pt.normal.dot(w).abs() * qs.normal.dot(w).abs() / distance_squared;
+        if g <= 0.0 || scene.occluded(pt.point, qs.point, time) {
+            return Vec3::ZERO;
+        }
+        // s 为 1 时 qs.beta 为 辐亮度 / 位置概率密度
+        pt.beta * f_camera * g * f_light * qs.beta
+    };
+
+    if contribution.max_element() <= 0.0 {
+        return Vec3::ZERO;
+    }
+    contribution * mis_weight(scene, light_path, camera_path, s, t)
+}
+
+// 平衡启发式的多重重要性采样权重，即本连接方式的概率密度占所有可行连接方式概率密度之和的比例，
+// 通过沿路径逐个顶点计算相邻连接方式之间的概率密度比得到
+fn mis_weight(scene: &Scene, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize) -> f32 {
+    if s + t == 2 {
+        return 1.0;
+    }
+    let mut light = light_path[..s].to_vec();
+    let mut camera = camera_path[..t].to_vec();
+
+    // 更新连接处两端顶点及其前一个顶点的反向概率密度，连接处的顶点不是 delta 分布
+    camera[t - 1].delta = false;
+    camera[t - 1].pdf_rev = if s > 0 {
+        light[s - 1].pdf(&camera[t - 1])
+    } else {
+        camera[t - 1].hit.map_or(0.0, |hit| scene.light_pdf(hit.material))
+    };
+    camera[t - 2].pdf_rev = if s > 0 {
+        camera[t - 1].pdf(&camera[t - 2])
+    } else {
+        camera[t - 1].pdf_light(&camera[t - 2])
+    };
+    if s > 0 {
+        light[s - 1].delta = false;
+        light[s - 1].pdf_rev = camera[t - 1].pdf(&light[s - 1]);
+    }
+    if s > 1 {
+        light[s - 2].pdf_rev = light[s - 1].pdf(&light[s - 2]);
+    }
+
+    // delta 分布的概率密度记为 0，计算比值时按 1 处理
+    let remap = |pdf: f32| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut sum = 0.0;
+
+    // 将连接点沿摄像机子路径向前移动，不考虑 t = 1 的连接方式
+    let mut ratio = 1.0;
+    for i in (2..t).rev() {
+        ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta {
+            sum += ratio;
+        }
+    }
+
+    // 将连接点沿光源子路径向前移动
+    let mut ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let delta_prev = i > 0 && light[i - 1].delta;
+        if !light[i].delta && !delta_prev {
+            sum += ratio;
+        }
+    }
+
+    1.0 / (1.0 + sum)
+}
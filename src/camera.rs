@@ -1,9 +1,122 @@
-use glam::{Vec3};
-use crate::ray::Ray;
-use crate::rand_util;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use glam::{Quat, Vec2, Vec3};
+use image::{DynamicImage, ImageError, ImageResult};
+use image::error::{ParameterError, ParameterErrorKind};
+use crate::ray::{Ray, RayDifferentials, RayPacket};
+use crate::rand_util::{self, Distribution2D};
+use crate::scene::AABB;
+
+/// 光圈形状，决定焦外高光（散景）的形状
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Aperture {
+    /// 圆形光圈
+    #[default]
+    Circle,
+    /// 由 `blades` 片光圈叶片构成的正多边形，`rotation` 为多边形的旋转角度，弧度制
+    Polygon { blades: u32, rotation: f32 },
+    /// 透过率从中心向边缘按高斯分布衰减的圆形光圈（切趾光圈），散景边缘柔和，更接近真实镜头；
+    /// `sigma` 为高斯分布的标准差与光圈半径之比，分布在光圈边缘处截断
+    Gaussian { sigma: f32 },
+    /// 由灰度图像给出透过率的光圈，可以得到心形、星形等任意形状的散景，见 `ApertureMask`
+    Mask(Arc<ApertureMask>),
+}
+
+/// 光圈遮罩，像素亮度为该处的透过率，图像较长的一边对应光圈的直径 2 * lens_radius，
+/// 图像上方为摄像机的上方向；透镜采样点按透过率分布，画面亮度与圆形光圈相同
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApertureMask {
+    distribution: Distribution2D,
+}
+
+impl ApertureMask {
+    /// 由图像构建，图像全黑时返回 None
+    pub fn from_image(image: &DynamicImage) -> Option<Self> {
+        let luma = image.to_luma32f();
+        let (width, height) = luma.dimensions();
+        Distribution2D::new(width as usize, height as usize, luma.as_raw())
+            .map(|distribution| Self { distribution })
+    }
+
+    /// 从图像文件构建，无法读取或图像全黑时返回错误
+    pub fn load_from_file(file_path: &str) -> ImageResult<Self> {
+        Self::from_image(&image::open(file_path)?).ok_or_else(|| {
+            ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(
+                format!("aperture mask {file_path} is completely black"),
+            )))
+        })
+    }
+
+    // 将 [0, 1)² 内均匀分布的点映射为光圈内按透过率分布的点，光圈内切于 [-1, 1]²
+    fn sample(&self, u: Vec2) -> Vec2 {
+        let (width, height) = self.distribution.size();
+        let (p, _) = self.distribution.sample(u);
+        // 图像的行从上往下计数
+        let half = Vec2::new(width as f32, height as f32) / width.max(height) as f32;
+        Vec2::new(half.x * (2.0 * p.x - 1.0), half.y * (1.0 - 2.0 * p.y))
+    }
+}
+
+impl Aperture {
+    /// 将单位圆盘内均匀分布的点映射为光圈内按透过率分布的点，保持采样点的分层结构。
+    /// 采样点的分布与透过率成正比，各样本的权重均为 1，画面亮度与圆形光圈相同
+    pub fn sample(&self, disk: Vec2) -> Vec2 {
+        match *self {
+            Aperture::Gaussian { sigma } if sigma > 0.0 => {
+                // 圆盘采样点半径的平方 u 均匀分布，按截断的二维高斯分布的径向累积分布函数
+                // (1 - exp(-r² / 2σ²)) / (1 - exp(-1 / 2σ²)) 求逆得到新的半径，角度保持不变
+                let u = disk.length_squared();
+                if u == 0.0 {
+                    return Vec2::ZERO;
+                }
+                let two_sigma2 = 2.0 * sigma * sigma;
+                let r = (-two_sigma2 * (-u * (-(-1.0 / two_sigma2).exp_m1())).ln_1p()).sqrt();
+                disk * (r.min(1.0) / u.sqrt())
+            }
+            Aperture::Polygon { blades, rotation } if blades >= 3 => {
+                // 多边形由 blades 个以中心为顶点的三角形组成：
+                // 圆盘采样点的角度决定所在的三角形与在外边上的位置，半径决定到中心的距离，
+                // 半径的平方均匀分布，恰好使三角形内的采样点均匀分布
+                let sector = 2.0 * PI / blades as f32;
+                let angle = disk.y.atan2(disk.x).rem_euclid(2.0 * PI);
+                let index = (angle / sector).floor();
+                let fraction = angle / sector - index;
+                let corner = |i: f32| {
+                    let theta = rotation + i * sector;
+                    Vec2::new(theta.cos(), theta.sin())
+                };
+                disk.length() * corner(index).lerp(corner(index + 1.0), fraction)
+            }
+            Aperture::Mask(ref mask) => {
+                // 圆盘采样点半径的平方与角度都在 [0, 1) 内均匀分布，作为遮罩分布的均匀采样点
+                let angle = disk.y.atan2(disk.x).rem_euclid(2.0 * PI) / (2.0 * PI);
+                mask.sample(Vec2::new(angle, disk.length_squared()))
+            }
+            _ => disk,
+        }
+    }
+}
+
+/// 清晰成像的平面，由平面上一点与法向确定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FocusPlane {
+    pub point: Vec3,
+    pub normal: Vec3, // 单位向量
+}
+
+/// 图像中的一个矩形图块，以像素为单位，`(x, y)` 为左上角，y 从图像顶部向下计数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub image_width: u32,
+    pub image_height: u32,
+}
 
 /// 摄像机，使用薄透镜模型
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub origin: Vec3, // 摄像机原点，即透镜光心
     pub lower_left_corner: Vec3, // 视口左下角
@@ -14,9 +127,16 @@ pub struct Camera {
     pub vertical: Vec3, // 视口的垂直向量，即 v * viewport_height
     pub focal_length: f32, // 焦距，即原点到视口平面的距离
     pub lens_radius: f32, // 透镜半径，即理想光圈半径
+    pub aperture: Aperture, // 光圈形状，多边形光圈的外接圆半径与遮罩较长一边的一半为 lens_radius
+    pub shutter_open: f32, // 快门打开的时刻，光线的时刻在快门打开与关闭之间均匀分布
+    pub shutter_close: f32, // 快门关闭的时刻，与 shutter_open 相同时没有运动模糊
+    pub focus_plane: Option<FocusPlane>, // 清晰成像的平面，为 None 时对焦于无穷远，透镜上各点发出的光线与主光线平行
 }
 
 impl Camera {
+    /// 参数不合法时 panic：`look_from` 与 `look_at` 重合、`vup` 与视线平行、
+    /// 视场角不在 (0, 180) 内、宽高比或焦距不为正数、透镜半径为负数，
+    /// 这些情况下会得到 NaN，最终渲染出全黑或错乱的图像
     pub fn new(
         look_from: Vec3,
         look_at: Vec3,
@@ -26,14 +146,35 @@ impl Camera {
         focal_length: f32,
         lens_radius: f32,
     ) -> Self {
+        assert!(
+            vertical_fov > 0.0 && vertical_fov < 180.0,
+            "vertical_fov must be in (0, 180) degrees, got {vertical_fov}"
+        );
+        assert!(
+            aspect_ratio > 0.0 && aspect_ratio.is_finite(),
+            "aspect_ratio must be positive, got {aspect_ratio}"
+        );
+        assert!(
+            focal_length > 0.0 && focal_length.is_finite(),
+            "focal_length must be positive, got {focal_length}"
+        );
+        assert!(
+            lens_radius >= 0.0 && lens_radius.is_finite(),
+            "lens_radius must not be negative, got {lens_radius}"
+        );
+
         let theta = vertical_fov.to_radians();
         let h = (theta / 2.0).tan() * focal_length;
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (look_from - look_at).normalize();
-        let u = vup.cross(w).normalize();
-        let v = vup.normalize();
+        let w = (look_from - look_at).try_normalize().unwrap_or_else(|| {
+            panic!("look_from and look_at must be different points, got {look_from} and {look_at}")
+        });
+        let u = vup.cross(w).try_normalize().unwrap_or_else(|| {
+            panic!("vup {vup} must not be parallel to the view direction {}", -w)
+        });
+        let v = w.cross(u);
 
         let lower_left_corner = look_from
             - u * viewport_width / 2.0
@@ -50,12 +191,105 @@ impl Camera {
             vertical: v * viewport_height,
             focal_length,
             lens_radius,
+            aperture: Aperture::Circle,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            focus_plane: None,
         }
     }
 
+    /// 移轴：镜头平行于传感器平移，视口沿右方向与上方向分别平移 `shift.x` 倍视口宽度与 `shift.y` 倍视口高度，
+    /// 视线方向不变。例如摄像机保持水平并向上平移以拍摄高楼，竖直的线条在图像中仍然竖直，不会产生梯形畸变
+    pub fn with_lens_shift(self, shift: Vec2) -> Self {
+        Camera {
+            lower_left_corner: self.lower_left_corner + self.horizontal * shift.x + self.vertical * shift.y,
+            ..self
+        }
+    }
+
+    /// 倾斜：镜头相对传感器绕水平轴倾斜 `tilt` 度，清晰成像的平面随之倾斜（沙姆定律），
+    /// 该平面经过视线方向上距离为 `focus_distance` 的点，由垂直于视线的平面绕右方向旋转 `tilt` 得到，
+    /// 正值使平面上方远离摄像机、下方靠近摄像机，向前延伸的地面可以整体清晰；负值则与地面相交，
+    /// 配合较大的光圈只有一条带状区域清晰，可以得到微缩模型般的效果。
+    /// `tilt` 为 0 时即为普通的有限距离对焦。不改变画面的构图
+    pub fn with_tilt(self, tilt: f32, focus_distance: f32) -> Self {
+        assert!(
+            tilt.abs() < 90.0,
+            "tilt must be in (-90, 90) degrees, got {tilt}"
+        );
+        assert!(
+            focus_distance > 0.0 && focus_distance.is_finite(),
+            "focus_distance must be positive, got {focus_distance}"
+        );
+        let normal = Quat::from_axis_angle(self.u, -tilt.to_radians()) * self.w;
+        Camera {
+            focus_plane: Some(FocusPlane { point: self.origin - self.w * focus_distance, normal }),
+            ..self
+        }
+    }
+
+    /// 生成一个沿 `direction` 方向观察、恰好能看到整个包围盒的摄像机，焦平面位于包围盒中心。
+    /// 包围盒退化为一个点时（例如 `Scene::bounds` 对空场景返回的包围盒）取景以该点为中心的单位球；
+    /// `direction` 为零向量时 panic，视场角与宽高比不合法时与 `Camera::new` 一样 panic
+    pub fn frame(bounds: &AABB, direction: Vec3, vertical_fov: f32, aspect_ratio: f32) -> Self {
+        let direction = direction.try_normalize()
+            .unwrap_or_else(|| panic!("direction must not be zero, got {direction}"));
+        let center = bounds.center();
+        // 使用包围盒的外接球，保证从任意方向观察都不会被裁切
+        // 包围盒退化为一个点时使用单位半径，避免摄像机与观察点重合
+        let radius = (bounds.max - bounds.min).length() / 2.0;
+        let radius = if radius > 0.0 { radius } else { 1.0 };
+
+        // 取水平与竖直视场角中较小的一个
+        let half_vertical = vertical_fov.to_radians() / 2.0;
+        let half_horizontal = (half_vertical.tan() * aspect_ratio).atan();
+        let half_fov = half_vertical.min(half_horizontal);
+        let distance = radius / half_fov.sin();
+
+        // 视线接近竖直时换用 z 轴作为上方向
+        let vup = if direction.cross(Vec3::Y).length_squared() < 1e-6 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+
+        Camera::new(
+            center - direction * distance,
+            center,
+            vup,
+            vertical_fov,
+            aspect_ratio,
+            distance,
+            0.0,
+        )
+    }
+
+    /// 生成左右眼的一对摄像机，两眼沿右方向分别偏移 `eye_separation` 的一半，视线保持平行，
+    /// 视口向中间平移，使距离为 `convergence` 的平面上的物体在两眼图像中位置相同（零视差），
+    /// 更近的物体凸出屏幕，更远的物体凹入屏幕
+    pub fn stereo_pair(&self, eye_separation: f32, convergence: f32) -> (Camera, Camera) {
+        assert!(
+            convergence > 0.0 && convergence.is_finite(),
+            "convergence must be positive, got {convergence}"
+        );
+        // 会聚平面上的视窗对两眼相同，换算到视口平面上，视口相对眼睛的偏移为 -offset * 焦距 / 会聚距离
+        let eye = |offset: Vec3| Camera {
+            origin: self.origin + offset,
+            lower_left_corner: self.lower_left_corner + offset * (1.0 - self.focal_length / convergence),
+            ..self.clone()
+        };
+        let offset = self.u * eye_separation / 2.0;
+        (eye(-offset), eye(offset))
+    }
+
     /// 根据像素位置生成光线
     pub fn get_ray(&self, horizontal_ratio: f32, vertical_ratio: f32) -> Ray {
-        let random_in_lens = self.lens_radius * rand_util::random_in_unit_disk();
+        self.get_ray_with_lens(horizontal_ratio, vertical_ratio, rand_util::random_in_unit_disk())
+    }
+
+    /// 根据像素位置与单位圆盘内的透镜采样点生成光线，采样点会按光圈形状映射到光圈内
+    pub fn get_ray_with_lens(&self, horizontal_ratio: f32, vertical_ratio: f32, lens_sample: Vec2) -> Ray {
+        let random_in_lens = self.lens_radius * self.aperture.sample(lens_sample);
         let offset = self.u * random_in_lens.x + self.v * random_in_lens.y;
 
         // 焦平面上任意一点发出的光经薄透镜折射后，光的方向与透镜光心与该点连线平行
@@ -63,7 +297,195 @@ impl Camera {
             + self.horizontal * horizontal_ratio
             + self.vertical * vertical_ratio
             - self.origin;
+        // 有清晰成像的平面时，透镜上各点发出的光线会聚于主光线与该平面的交点
+        let direction = match self.focus_plane {
+            Some(plane) if offset != Vec3::ZERO => {
+                let denominator = plane.normal.dot(direction);
+                let t = plane.normal.dot(plane.point - self.origin) / denominator;
+                if denominator.abs() > f32::EPSILON && t > 0.0 {
+                    self.origin + direction * t - (self.origin + offset)
+                } else {
+                    direction
+                }
+            }
+            _ => direction,
+        };
+
+        let time = self.shutter_open + rand_util::random::<f32>() * (self.shutter_close - self.shutter_open);
+        Ray::new(self.origin + offset, direction).with_time(time)
+    }
+
+    /// 一次生成图块内所有像素中心的主光线，按行优先顺序从上到下排列，与渲染输出的像素顺序一致。
+    /// 光线都从透镜光心发出（不考虑景深），时刻为快门打开的时刻，与 `get_ray_with_lens(u, v, Vec2::ZERO)`
+    /// 的方向相同；起点相同、方向相近，适合用 `Scene::hit_packet` 成组求交，用于预览、拾取等只需要主光线可见性的场合
+    pub fn get_rays(&self, tile: Tile) -> RayPacket {
+        let directions = (tile.y..tile.y + tile.height).flat_map(|y| {
+            (tile.x..tile.x + tile.width).map(move |x| {
+                let u = (x as f32 + 0.5) / tile.image_width as f32;
+                let v = ((tile.image_height - y) as f32 - 0.5) / tile.image_height as f32;
+                self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin
+            })
+        });
+        RayPacket::new(self.origin, directions, self.shutter_open)
+    }
+
+    /// 与 `get_ray_with_lens` 相同，并附带光线微分，`pixel_width`、`pixel_height` 为一个像素在
+    /// `horizontal_ratio`、`vertical_ratio` 上的跨度，相邻像素的光线使用同一个透镜采样点
+    pub fn get_ray_with_differentials(
+        &self,
+        horizontal_ratio: f32,
+        vertical_ratio: f32,
+        lens_sample: Vec2,
+        pixel_width: f32,
+        pixel_height: f32,
+    ) -> Ray {
+        let ray = self.get_ray_with_lens(horizontal_ratio, vertical_ratio, lens_sample);
+        let rx = self.get_ray_with_lens(horizontal_ratio + pixel_width, vertical_ratio, lens_sample);
+        let ry = self.get_ray_with_lens(horizontal_ratio, vertical_ratio + pixel_height, lens_sample);
+        ray.with_differentials(RayDifferentials {
+            rx_origin: rx.origin,
+            rx_direction: rx.direction,
+            ry_origin: ry.origin,
+            ry_direction: ry.direction,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 基向量两两正交且都是单位向量
+    fn assert_orthonormal(camera: &Camera) {
+        for (a, b) in [(camera.u, camera.v), (camera.v, camera.w), (camera.w, camera.u)] {
+            assert!(a.dot(b).abs() < 1e-6, "{a} {b}");
+        }
+        for axis in [camera.u, camera.v, camera.w] {
+            assert!(axis.is_normalized(), "{axis}");
+        }
+    }
+
+    #[test]
+    fn tilted_camera_has_orthonormal_basis() {
+        // 向下 45° 俯视，vup 与视线不垂直
+        let camera = Camera::new(Vec3::new(0.0, 5.0, 5.0), Vec3::ZERO, Vec3::Y, 40.0, 1.5, 1.0, 0.0);
+        assert_orthonormal(&camera);
+        assert!(camera.v.abs_diff_eq(Vec3::new(0.0, 1.0, -1.0).normalize(), 1e-6), "{}", camera.v);
+        // 图像中心的光线指向观察点
+        let ray = camera.get_ray_with_lens(0.5, 0.5, Vec2::ZERO);
+        assert!(ray.direction.normalize().abs_diff_eq(Vec3::new(0.0, -1.0, -1.0).normalize(), 1e-6));
+    }
+
+    #[test]
+    fn framed_camera_sees_the_whole_box_from_outside() {
+        let bounds = AABB::new(Vec3::new(-1.0, -2.0, -3.0), Vec3::new(4.0, 5.0, 6.0));
+        let corners = (0..8).map(|i| Vec3::select(
+            glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), bounds.max, bounds.min,
+        )).collect::<Vec<_>>();
+        let center = bounds.center();
+        for direction in [Vec3::NEG_Z, Vec3::X, Vec3::new(1.0, -1.0, 0.5), Vec3::NEG_Y, Vec3::Y] {
+            for aspect_ratio in [0.5, 1.0, 2.0] {
+                let camera = Camera::frame(&bounds, direction, 40.0, aspect_ratio);
+                assert_orthonormal(&camera);
+                for (x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.5, 0.5)] {
+                    let ray = camera.get_ray_with_lens(x, y, Vec2::ZERO);
+                    let outside = ray.origin.cmplt(bounds.min).any() || ray.origin.cmpgt(bounds.max).any();
+                    assert!(outside, "{direction} {aspect_ratio}");
+                    assert!(ray.direction.dot(center - ray.origin) > 0.0);
+                }
+                // 包围盒的每个角都在视野之内
+                let half_height = (40f32.to_radians() / 2.0).tan();
+                let half_width = half_height * aspect_ratio;
+                for corner in &corners {
+                    let to_corner = *corner - camera.origin;
+                    let depth = -to_corner.dot(camera.w);
+                    assert!(depth > 0.0);
+                    assert!(to_corner.dot(camera.u).abs() / depth <= half_width + 1e-5, "{direction} {aspect_ratio}");
+                    assert!(to_corner.dot(camera.v).abs() / depth <= half_height + 1e-5, "{direction} {aspect_ratio}");
+                }
+            }
+        }
+    }
+
+    // 4x2 的遮罩，只有左上角的像素透光
+    fn corner_mask() -> Aperture {
+        let mut pixels = image::GrayImage::new(4, 2);
+        pixels.put_pixel(0, 0, image::Luma([255]));
+        Aperture::Mask(Arc::new(ApertureMask::from_image(&DynamicImage::ImageLuma8(pixels)).unwrap()))
+    }
+
+    #[test]
+    fn aperture_mask_samples_only_transparent_pixels() {
+        let aperture = corner_mask();
+        for i in 0..64 {
+            let disk = rand_util::square_to_disk(rand_util::cmj(i, 64, 7));
+            let p = aperture.sample(disk);
+            // 较长的一边为光圈直径，左上角的像素覆盖 x ∈ [-1, -0.5]，y ∈ [0, 0.5]
+            assert!((-1.0..=-0.5).contains(&p.x) && (0.0..=0.5).contains(&p.y), "{p}");
+        }
+    }
+
+    #[test]
+    fn black_aperture_mask_is_rejected() {
+        assert!(ApertureMask::from_image(&DynamicImage::ImageLuma8(image::GrayImage::new(2, 2))).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "vertical_fov must be in (0, 180)")]
+    fn zero_fov_panics() {
+        Camera::new(Vec3::Z, Vec3::ZERO, Vec3::Y, 0.0, 1.0, 1.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "vertical_fov must be in (0, 180)")]
+    fn straight_angle_fov_panics() {
+        Camera::new(Vec3::Z, Vec3::ZERO, Vec3::Y, 180.0, 1.0, 1.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "aspect_ratio must be positive")]
+    fn zero_aspect_ratio_panics() {
+        Camera::new(Vec3::Z, Vec3::ZERO, Vec3::Y, 40.0, 0.0, 1.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "focal_length must be positive")]
+    fn zero_focal_length_panics() {
+        Camera::new(Vec3::Z, Vec3::ZERO, Vec3::Y, 40.0, 1.0, 0.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "lens_radius must not be negative")]
+    fn negative_lens_radius_panics() {
+        Camera::new(Vec3::Z, Vec3::ZERO, Vec3::Y, 40.0, 1.0, 1.0, -0.1);
+    }
+
+    #[test]
+    #[should_panic(expected = "look_from and look_at must be different points")]
+    fn coincident_look_from_and_look_at_panics() {
+        Camera::new(Vec3::ONE, Vec3::ONE, Vec3::Y, 40.0, 1.0, 1.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be parallel to the view direction")]
+    fn vup_parallel_to_view_direction_panics() {
+        Camera::new(Vec3::Y, Vec3::ZERO, Vec3::Y, 40.0, 1.0, 1.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "direction must not be zero")]
+    fn framing_along_zero_direction_panics() {
+        Camera::frame(&AABB::new(Vec3::ZERO, Vec3::ONE), Vec3::ZERO, 40.0, 1.0);
+    }
 
-        Ray::new(self.origin + offset, direction)
+    #[test]
+    fn framing_a_point_uses_a_unit_sphere() {
+        let point = Vec3::new(1.0, 2.0, 3.0);
+        let camera = Camera::frame(&AABB::new(point, point), Vec3::NEG_Z, 40.0, 1.0);
+        assert_orthonormal(&camera);
+        // 单位球恰好内切于视场，摄像机到球心的距离为 1 / sin(半视场角)
+        let distance = 1.0 / 20f32.to_radians().sin();
+        assert!(camera.origin.abs_diff_eq(point + Vec3::Z * distance, 1e-4), "{}", camera.origin);
+        assert!((camera.focal_length - distance).abs() < 1e-4);
     }
 }
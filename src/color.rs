@@ -0,0 +1,209 @@
+use std::fs;
+use std::io;
+use glam::{Mat3, Vec3};
+
+// 线性 sRGB 到线性 Display P3 的矩阵，两者白点均为 D65，由各自原色的色度坐标推导
+const SRGB_TO_DISPLAY_P3: Mat3 = Mat3::from_cols_array(&[
+    0.822462, 0.033194, 0.017083,
+    0.177538, 0.966806, 0.072397,
+    0.0, 0.0, 0.910520,
+]);
+
+/// 输出图像的颜色空间。渲染在线性 RGB 下进行，原色与白点与 sRGB 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// sRGB 原色与 sRGB 传递函数，适用于普通显示器与网页
+    #[default]
+    Srgb,
+    /// 线性 sRGB，不应用传递函数，适用于后期合成
+    Linear,
+    /// Rec.709 原色（与 sRGB 相同）与 BT.709 传递函数，适用于视频
+    Rec709,
+    /// Display P3 原色与 sRGB 传递函数，适用于广色域显示器
+    DisplayP3,
+}
+
+impl ColorSpace {
+    /// 将线性 RGB 颜色转换到该颜色空间并编码，结果的分量截断到 [0, 1]
+    pub fn encode(self, color: Vec3) -> Vec3 {
+        match self {
+            ColorSpace::Srgb => color.clamp(Vec3::ZERO, Vec3::ONE).to_array().map(srgb_oetf).into(),
+            ColorSpace::Linear => color.clamp(Vec3::ZERO, Vec3::ONE),
+            ColorSpace::Rec709 => color.clamp(Vec3::ZERO, Vec3::ONE).to_array().map(rec709_oetf).into(),
+            ColorSpace::DisplayP3 => ColorSpace::Srgb.encode(SRGB_TO_DISPLAY_P3 * color),
+        }
+    }
+
+    /// 将 `render_hdr` 输出的线性浮点 RGB 数据编码为该颜色空间的 8 位 RGB 数据
+    pub fn encode_8bit(self, image_data: &[f32]) -> Vec<u8> {
+        self.encode_8bit_graded(image_data, None)
+    }
+
+    /// 与 `encode_8bit` 相同，编码后在显示空间中再应用调色查找表 `lut`
+    pub fn encode_8bit_graded(self, image_data: &[f32], lut: Option<&ColorLut>) -> Vec<u8> {
+        image_data.chunks_exact(3)
+            .flat_map(|color| self.encode_graded(Vec3::from_slice(color), lut).to_array())
+            .map(|x| (x * 255.99) as u8)
+            .collect()
+    }
+
+    /// 与 `encode` 相同，编码后在显示空间中再应用调色查找表 `lut`
+    pub fn encode_graded(self, color: Vec3, lut: Option<&ColorLut>) -> Vec3 {
+        let encoded = self.encode(color);
+        match lut {
+            Some(lut) => lut.apply(encoded),
+            None => encoded,
+        }
+    }
+}
+
+/// 调色查找表，在编码后的显示空间中逐像素应用，用于匹配特定的画面风格。
+/// 可以由 `.cube` 文件（Adobe/Resolve 格式）加载，也可以直接给出一维色调曲线
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorLut {
+    /// 一维查找表，三个通道分别查找，`table` 的各项对应在 `domain` 内均匀分布的输入，相邻项之间线性插值
+    Curve { table: Vec<Vec3>, domain: (Vec3, Vec3) },
+    /// 三维查找表，共 `size`³ 项，红色变化最快、蓝色最慢（与 .cube 相同），三线性插值
+    Cube { size: usize, table: Vec<Vec3>, domain: (Vec3, Vec3) },
+}
+
+impl ColorLut {
+    /// 三个通道共用的色调曲线，`points` 为输入 0 到 1 均匀分布时的输出，至少两项
+    pub fn curve(points: &[f32]) -> Self {
+        assert!(points.len() >= 2, "A tone curve needs at least two points");
+        ColorLut::Curve {
+            table: points.iter().map(|&point| Vec3::splat(point)).collect(),
+            domain: (Vec3::ZERO, Vec3::ONE),
+        }
+    }
+
+    /// 读取 `.cube` 文件
+    pub fn load_cube(file_path: &str) -> io::Result<Self> {
+        Self::parse_cube(&fs::read_to_string(file_path)?)
+    }
+
+    /// 解析 `.cube` 格式的文本，支持 LUT_1D_SIZE、LUT_3D_SIZE、DOMAIN_MIN、DOMAIN_MAX 与
+    /// Resolve 的 LUT_1D_INPUT_RANGE、LUT_3D_INPUT_RANGE，TITLE 与 # 开头的注释忽略
+    pub fn parse_cube(text: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let numbers = |values: &[&str]| values.iter()
+            .map(|value| value.parse::<f32>().map_err(|_| invalid(format!("Invalid number {value} in .cube file"))))
+            .collect::<io::Result<Vec<_>>>();
+        let (mut size_1d, mut size_3d) = (None, None);
+        let mut domain = (Vec3::ZERO, Vec3::ONE);
+        let mut table = vec![];
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] | ["TITLE", ..] => {}
+                ["LUT_1D_SIZE", size] => size_1d = size.parse::<usize>().ok(),
+                ["LUT_3D_SIZE", size] => size_3d = size.parse::<usize>().ok(),
+                ["DOMAIN_MIN", r, g, b] => domain.0 = Vec3::from_slice(&numbers(&[r, g, b])?),
+                ["DOMAIN_MAX", r, g, b] => domain.1 = Vec3::from_slice(&numbers(&[r, g, b])?),
+                ["LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE", min, max] => {
+                    let range = numbers(&[min, max])?;
+                    domain = (Vec3::splat(range[0]), Vec3::splat(range[1]));
+                }
+                // 定义域的分量数量不对
+                ["DOMAIN_MIN" | "DOMAIN_MAX" | "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE", ..] => {
+                    return Err(invalid(format!("Invalid line in .cube file: {line}")));
+                }
+                [keyword, ..] if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(invalid(format!("Unknown keyword {keyword} in .cube file")));
+                }
+                values if values.len() == 3 => table.push(Vec3::from_slice(&numbers(values)?)),
+                _ => return Err(invalid(format!("Invalid line in .cube file: {line}"))),
+            }
+        }
+        let lut = match (size_1d, size_3d) {
+            (Some(size), None) if size >= 2 && table.len() == size => ColorLut::Curve { table, domain },
+            (None, Some(size)) if size >= 2 && size.checked_pow(3) == Some(table.len()) => {
+                ColorLut::Cube { size, table, domain }
+            }
+            _ => return Err(invalid(format!("Invalid .cube size or entry count ({} entries)", table.len()))),
+        };
+        Ok(lut)
+    }
+
+    /// 对一个显示空间的颜色查找，超出定义域的输入截断到边界
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        // 输入换算到 [0, 最大下标]
+        let position = |domain: &(Vec3, Vec3), size: usize| {
+            ((color - domain.0) / (domain.1 - domain.0).max(Vec3::splat(f32::EPSILON)))
+                .clamp(Vec3::ZERO, Vec3::ONE) * (size - 1) as f32
+        };
+        match self {
+            ColorLut::Curve { table, domain } => {
+                let position = position(domain, table.len());
+                Vec3::from_array(std::array::from_fn(|channel| {
+                    let x = position[channel];
+                    let index = (x as usize).min(table.len() - 2);
+                    let t = x - index as f32;
+                    table[index][channel] * (1.0 - t) + table[index + 1][channel] * t
+                }))
+            }
+            ColorLut::Cube { size, table, domain } => {
+                let position = position(domain, *size);
+                let base = position.as_uvec3().min(glam::UVec3::splat(*size as u32 - 2));
+                let t = position - base.as_vec3();
+                let at = |r: u32, g: u32, b: u32| table[(r + g * *size as u32 + b * (*size * *size) as u32) as usize];
+                let (r, g, b) = (base.x, base.y, base.z);
+                let c00 = at(r, g, b).lerp(at(r + 1, g, b), t.x);
+                let c10 = at(r, g + 1, b).lerp(at(r + 1, g + 1, b), t.x);
+                let c01 = at(r, g, b + 1).lerp(at(r + 1, g, b + 1), t.x);
+                let c11 = at(r, g + 1, b + 1).lerp(at(r + 1, g + 1, b + 1), t.x);
+                c00.lerp(c10, t.y).lerp(c01.lerp(c11, t.y), t.z)
+            }
+        }
+    }
+}
+
+// sRGB 传递函数，暗部为线性段
+fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// BT.709 传递函数，暗部为线性段
+fn rec709_oetf(x: f32) -> f32 {
+    if x < 0.018 {
+        4.5 * x
+    } else {
+        1.099 * x.powf(0.45) - 0.099
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY_CURVE: &str = "LUT_1D_SIZE 2\n0 0 0\n1 1 1\n";
+
+    #[test]
+    fn domain_keywords_are_parsed() {
+        let lut = ColorLut::parse_cube(&format!("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n{IDENTITY_CURVE}")).unwrap();
+        assert_eq!(lut.apply(Vec3::ONE), Vec3::splat(0.5));
+        let lut = ColorLut::parse_cube(&format!("LUT_1D_INPUT_RANGE 0 2\n{IDENTITY_CURVE}")).unwrap();
+        assert_eq!(lut.apply(Vec3::ONE), Vec3::splat(0.5));
+    }
+
+    #[test]
+    fn malformed_cube_files_are_errors() {
+        for header in [
+            "DOMAIN_MIN 0 0",
+            "DOMAIN_MAX 1",
+            "DOMAIN_MAX 1 1 1 1",
+            "LUT_1D_INPUT_RANGE 0",
+            "LUT_3D_INPUT_RANGE",
+        ] {
+            let error = ColorLut::parse_cube(&format!("{header}\n{IDENTITY_CURVE}")).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{header}");
+        }
+        let error = ColorLut::parse_cube(&format!("LUT_3D_SIZE {}\n0 0 0\n", usize::MAX / 2)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
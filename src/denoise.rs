@@ -0,0 +1,89 @@
+use glam::Vec3;
+use rayon::prelude::*;
+
+/// 降噪的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DenoiseOptions {
+    pub iterations: u32,    // à-trous 滤波的迭代次数，第 i 次迭代的采样间隔为 2^i 个像素
+    pub sigma_color: f32,   // 颜色差异的容差，每次迭代减半，越大越平滑但细节越模糊
+    pub sigma_normal: f32,  // 法向差异的容差，越小越能保持几何边缘
+    pub sigma_albedo: f32,  // 反照率差异的容差，越小越能保持贴图与材质的边缘
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self { iterations: 5, sigma_color: 1.0, sigma_normal: 0.3, sigma_albedo: 0.1 }
+    }
+}
+
+// B3 样条的一维核，二维核为两者的外积
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Dammertz 等人的边缘保持 à-trous 小波滤波，用低采样数渲染的含噪图像生成预览
+///
+/// `color`、`albedo`、`normal` 均为与 `render_hdr` 输出排列相同的 RGB 数据，
+/// 后两者可以由 `render::render_aovs` 得到。每次迭代以逐渐增大的间隔对 5x5 个像素加权平均，
+/// 权重随颜色、法向、反照率的差异指数衰减，因此不会在物体边缘、材质边界处模糊
+pub fn denoise(
+    color: &[f32],
+    albedo: &[f32],
+    normal: &[f32],
+    width: u32,
+    height: u32,
+    options: &DenoiseOptions,
+) -> Vec<f32> {
+    let pixels = (width * height) as usize;
+    assert!(
+        color.len() == pixels * 3 && albedo.len() == pixels * 3 && normal.len() == pixels * 3,
+        "Buffers do not match the image size"
+    );
+    let to_vec3 = |data: &[f32]| data.chunks_exact(3).map(Vec3::from_slice).collect::<Vec<_>>();
+    let albedo = to_vec3(albedo);
+    let normal = to_vec3(normal);
+    let mut color = to_vec3(color);
+
+    let (width, height) = (width as i32, height as i32);
+    for iteration in 0..options.iterations {
+        let step = 1 << iteration.min(16);
+        let sigma_color = options.sigma_color * 0.5f32.powi(iteration as i32);
+        let input = color;
+        color = (0..pixels).into_par_iter().map(|index| {
+            let (x, y) = (index as i32 % width, index as i32 / width);
+            let (c, n, a) = (input[index], normal[index], albedo[index]);
+
+            let mut sum = Vec3::ZERO;
+            let mut weight_sum = 0.0;
+            for (dy, ky) in KERNEL.iter().enumerate() {
+                let qy = y + (dy as i32 - 2) * step;
+                if !(0..height).contains(&qy) {
+                    continue;
+                }
+                for (dx, kx) in KERNEL.iter().enumerate() {
+                    let qx = x + (dx as i32 - 2) * step;
+                    if !(0..width).contains(&qx) {
+                        continue;
+                    }
+                    let q = (qy * width + qx) as usize;
+                    let weight = kx * ky
+                        * edge_stopping(c.distance_squared(input[q]), sigma_color)
+                        * edge_stopping(n.distance_squared(normal[q]), options.sigma_normal)
+                        * edge_stopping(a.distance_squared(albedo[q]), options.sigma_albedo);
+                    sum += input[q] * weight;
+                    weight_sum += weight;
+                }
+            }
+            // 中心像素的权重恒为正，不会除以 0
+            sum / weight_sum
+        }).collect();
+    }
+
+    color.iter().flat_map(|c| c.to_array()).collect()
+}
+
+// 差异的平方为 distance2 时的权重，容差不为正时只保留完全相同的像素
+fn edge_stopping(distance2: f32, sigma: f32) -> f32 {
+    if sigma <= 0.0 {
+        return if distance2 == 0.0 { 1.0 } else { 0.0 };
+    }
+    (-distance2 / (sigma * sigma)).exp()
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use glam::Vec3;
+
+/// 辐照度缓存的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IrradianceCacheOptions {
+    pub samples: u32,     // 计算一条缓存记录时在半球内采样的光线数量
+    pub accuracy: f32,    // Ward 误差阈值 a，越小缓存越密、偏差越小
+    pub min_spacing: f32, // 缓存记录有效半径的下限，避免在角落处过密
+    pub max_spacing: f32, // 缓存记录有效半径的上限，避免在开阔处过疏
+}
+
+impl Default for IrradianceCacheOptions {
+    fn default() -> Self {
+        Self { samples: 64, accuracy: 0.2, min_spacing: 0.01, max_spacing: 0.2 }
+    }
+}
+
+// 一条缓存记录
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    point: Vec3,
+    normal: Vec3,
+    irradiance: Vec3, // 按余弦加权的半球内入射辐亮度均值，即辐照度 / π
+    radius: f32,      // 有效半径，由半球采样光线的调和平均距离乘以 accuracy 得到
+}
+
+// 所有缓存记录，以及按均匀网格划分的索引
+#[derive(Default)]
+struct Records {
+    records: Vec<Record>,
+    grid: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+/// Ward 的辐照度缓存，在稀疏的表面点上记录半球辐照度，附近的漫反射交点插值复用，
+/// 靠近其他几何体（调和平均距离小）或法向变化大的地方会重新计算
+pub struct IrradianceCache {
+    options: IrradianceCacheOptions,
+    records: RwLock<Records>,
+}
+
+impl IrradianceCache {
+    pub fn new(options: IrradianceCacheOptions) -> Self {
+        Self { options, records: RwLock::default() }
+    }
+
+    pub fn options(&self) -> &IrradianceCacheOptions {
+        &self.options
+    }
+
+    /// 缓存记录数量
+    pub fn len(&self) -> usize {
+        self.records.read().unwrap().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 在已有记录之间插值得到 `point` 处的辐照度，附近没有有效记录时返回 None
+    pub fn lookup(&self, point: Vec3, normal: Vec3) -> Option<Vec3> {
+        let records = self.records.read().unwrap();
+        let indices = records.grid.get(&self.cell(point))?;
+
+        let mut irradiance = Vec3::ZERO;
+        let mut total_weight = 0.0;
+        for record in indices.iter().map(|index| &records.records[*index]) {
+            // 记录位于交点前方时会漏光，不使用
+            if (point - record.point).dot(record.normal + normal) < -0.01 {
+                continue;
+            }
+            let error = point.distance(record.point) / record.radius
+                + (1.0 - normal.dot(record.normal)).max(0.0).sqrt() / self.options.accuracy;
+            if error < 1.0 {
+                // 在有效范围边界处权重平滑地降为 0，避免插值结果出现明显的接缝
+                let weight = 1.0 / error.max(1e-4) - 1.0;
+                irradiance += weight * record.irradiance;
+                total_weight += weight;
+            }
+        }
+
+        (total_weight > 0.0).then(|| irradiance / total_weight)
+    }
+
+    /// 添加一条记录，`harmonic_distance` 为半球采样光线击中距离的调和平均
+    pub fn insert(&self, point: Vec3, normal: Vec3, irradiance: Vec3, harmonic_distance: f32) {
+        let radius = (self.options.accuracy * harmonic_distance)
+            .clamp(self.options.min_spacing, self.options.max_spacing);
+        let record = Record { point, normal, irradiance, radius };
+
+        // 有效半径不超过网格大小，因此每条记录最多登记到 8 个网格中
+        let (min, max) = (self.cell(point - radius), self.cell(point + radius));
+        let mut records = self.records.write().unwrap();
+        let index = records.records.len();
+        records.records.push(record);
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    records.grid.entry((x, y, z)).or_default().push(index);
+                }
+            }
+        }
+    }
+
+    // 点所在的网格，网格大小为有效半径的上限
+    fn cell(&self, point: Vec3) -> (i32, i32, i32) {
+        let cell = (point / self.options.max_spacing).floor();
+        (cell.x as i32, cell.y as i32, cell.z as i32)
+    }
+}
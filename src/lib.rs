@@ -0,0 +1,19 @@
+pub mod ray;
+pub mod scene;
+pub mod camera;
+pub mod render;
+pub mod material;
+pub mod rand_util;
+pub mod texture;
+pub mod noise;
+pub mod video;
+pub mod background;
+pub mod photon;
+pub mod irradiance;
+pub mod spectrum;
+pub mod accumulation;
+pub mod denoise;
+pub mod stereo;
+pub mod color;
+pub mod stats;
+mod bdpt;
@@ -1,16 +1,11 @@
-mod ray;
-mod scene;
-mod camera;
-mod render;
-mod material;
-mod rand_util;
-mod texture;
-
 use std::sync::Arc;
 use std::time::Instant;
 use glam::{Mat4, Vec3};
-use crate::camera::Camera;
-use crate::scene::{Scene};
+use nebula::camera::Camera;
+use nebula::material::Material;
+use nebula::render;
+use nebula::scene::Scene;
+use nebula::scene::graph::SceneGraph;
 
 fn create_test_scenes() -> Vec<Scene> {
     let mut scenes = vec![];
@@ -30,13 +25,18 @@ fn create_test_scenes() -> Vec<Scene> {
     // 场景三：CornellBoxName + MyName
     // CornellBoxName 为一个空的 Cornell Box，后方墙壁上贴了一张漫反射贴图，上面是我的学号和名字 :)
     // MyName 中是我的名字，分别使用了石膏材质、镜面材质、透明材质
+    // 两个模型及其变换由场景图文件描述，并行加载
     let mut scene = Scene::new();
-
-    scene.add_obj("scenes/CornellBoxName.obj", Mat4::IDENTITY);
-    let translate = Mat4::from_translation(Vec3::new(0.0, 0.5, -0.5));
-    let scale = Mat4::from_scale(Vec3::new(1.2, 0.6, 1.2));
-    let rotate = Mat4::from_rotation_x(std::f32::consts::PI / 4.0);
-    scene.add_obj("scenes/MyName.obj", translate * rotate * scale);
+    SceneGraph::load("scenes/CornellBoxName.json")
+        .and_then(|graph| graph.add_to(&mut scene))
+        .expect("Failed to load scene graph");
+    // 石膏字母使用 Oren-Nayar 粗糙漫反射，边缘与掠射处更亮，更接近真实的哑光表面
+    // 场景中没有 plaster 材质时保持原样
+    let plaster = scene.materials.get("plaster")
+        .map(|plaster| Material { diffuse_roughness: 0.5, ..Material::clone(plaster) });
+    if let Some(plaster) = plaster {
+        scene.override_material("plaster", plaster);
+    }
 
     scenes.push(scene);
 
@@ -52,14 +52,15 @@ fn create_camera(aspect_ratio: f32) -> Camera {
 }
 
 fn main() {
-    let mut scenes = create_test_scenes();
+    let scenes = create_test_scenes();
 
     let image_width = 640;
     let image_height = 400;
     let max_depth = 5;
     let samples_per_pixel = 10000;
 
-    let camera = Arc::new(create_camera(image_width as f32 / image_height as f32));
+    // 每个场景从列表中的所有摄像机各渲染一张图像，共用同一个 BVH
+    let cameras = vec![Arc::new(create_camera(image_width as f32 / image_height as f32))];
 
     let mut scene_number = 0;
 
@@ -67,24 +68,37 @@ fn main() {
         scene_number += 1;
         println!("Start to render scene_{scene_number}.");
         let start = Instant::now();
+        println!("Preloaded {}.", scene.preload_textures());
         scene.build_bvh();
         println!("Build BVH for {} triangles.", scene.objects.len());
-        let image_data = render::render(
+        for camera in &cameras {
+            for warning in render::check_scene(&scene, camera) {
+                eprintln!("Warning: {warning}");
+            }
+        }
+        let images = render::render_views(
             Arc::new(scene),
-            camera.clone(),
+            &cameras,
             image_width,
             image_height,
             max_depth,
             samples_per_pixel,
+            &render::RenderOptions {
+                light_samples: 1,
+                branch_depth: 3,
+                ambient_occlusion: 0.5,
+                min_throughput: 1e-3,
+                ..Default::default()
+            },
         );
         let duration = start.elapsed();
         println!("{:?} for rendering scene_{scene_number} (time for building bvh included).", duration);
-        render::save_image_as_png(
-            image_data,
+        let filenames = render::save_views_as_png(
+            images,
             image_width,
             image_height,
-            &format!("scene_{scene_number}.png"),
+            &format!("scene_{scene_number}"),
         );
-        println!("Result saved as scene_{scene_number}.png\n");
+        println!("Result saved as {}\n", filenames.join(", "));
     }
 }
\ No newline at end of file
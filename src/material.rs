@@ -1,229 +1,998 @@
 use std::str::FromStr;
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use crate::ray::Ray;
 use crate::scene::HitRecord;
+use crate::noise::SolidTexture;
 use crate::rand_util;
 use crate::texture::Texture;
+use rand::Rng;
+
+// 解析 MTL 中 bump 的参数，返回文件名与 -bm 指定的高度缩放（缺省为 1），文件名为最后一项，其他选项忽略
+fn parse_bump(option: &str) -> (&str, f32) {
+    let tokens = option.split_whitespace().collect::<Vec<_>>();
+    let multiplier = tokens.windows(2)
+        .find(|pair| pair[0] == "-bm")
+        .and_then(|pair| f32::from_str(pair[1]).ok())
+        .unwrap_or(1.0);
+    (tokens.last().copied().unwrap_or(option), multiplier)
+}
+
+// 读取 tobj 不识别、保存在 unknown_param 中的数值参数，缺省时为 `default`，无法解析时给出警告并使用 `default`
+fn unknown_param(material: &tobj::Material, key: &str, default: f32) -> f32 {
+    let Some(value) = material.unknown_param.get(key) else {
+        return default;
+    };
+    f32::from_str(value.trim()).unwrap_or_else(|_| {
+        eprintln!("Warning: invalid {key} value {value:?} in material {}, using {default}", material.name);
+        default
+    })
+}
+
+/// 颜色的亮度，用于按能量比较颜色
+pub fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// 出射光线所属的散射分量
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScatterKind {
+    Diffuse,      // 漫反射
+    Specular,     // 镜面反射
+    Transmission, // 透射
+}
 
 /// 光线经物体表面作用后出射的光线
 #[derive(Debug, Copy, Clone)]
 pub struct ScatteredRay {
     pub ray: Ray,
     pub coefficient: Vec3,
+    pub kind: ScatterKind,
+    pub pdf: f32, // 采样出射方向的概率密度（立体角），镜面反射与透射为 delta 分布，记为 0
 }
 
-#[derive(Debug, Copy, Clone)]
+/// 覆盖在材质表面的薄膜，光在薄膜上下表面反射后相互干涉，使镜面反射呈现随角度变化的彩虹色，
+/// 例如肥皂泡、油膜
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThinFilm {
+    pub thickness: f32, // 薄膜厚度，单位为纳米
+    pub ior: f32,       // 薄膜折射率
+}
+
+impl ThinFilm {
+    const WAVELENGTHS: [f32; 3] = [650.0, 510.0, 475.0]; // R、G、B 通道的代表波长，单位为纳米
+
+    /// 入射角余弦为 `cos_theta` 时，薄膜覆盖在折射率为 `substrate_ior` 的基底上，
+    /// 每个通道的反射率，使用 Airy 公式计算，取 s、p 两种偏振的平均值
+    pub fn reflectance(&self, cos_theta: f32, substrate_ior: f32) -> Vec3 {
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        let cos_film = (1.0 - sin2_theta / (self.ior * self.ior)).max(0.0).sqrt();
+        let cos_substrate = (1.0 - sin2_theta / (substrate_ior * substrate_ior)).max(0.0).sqrt();
+
+        // 空气与薄膜、薄膜与基底界面的 Fresnel 振幅反射系数
+        let s12 = (cos_theta - self.ior * cos_film) / (cos_theta + self.ior * cos_film);
+        let p12 = (self.ior * cos_theta - cos_film) / (self.ior * cos_theta + cos_film);
+        let s23 = (self.ior * cos_film - substrate_ior * cos_substrate)
+            / (self.ior * cos_film + substrate_ior * cos_substrate);
+        let p23 = (substrate_ior * cos_film - self.ior * cos_substrate)
+            / (substrate_ior * cos_film + self.ior * cos_substrate);
+
+        let airy = |r12: f32, r23: f32, phase: f32| {
+            let cross = 2.0 * r12 * r23 * phase.cos();
+            (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+        };
+        Vec3::from_array(Self::WAVELENGTHS.map(|wavelength| {
+            // 两束反射光的相位差
+            let phase = 4.0 * std::f32::consts::PI * self.ior * self.thickness * cos_film / wavelength;
+            0.5 * (airy(s12, s23, phase) + airy(p12, p23, phase))
+        }))
+    }
+}
+
+/// 各向异性的光泽反射，沿切向与副切向使用不同的粗糙度，可以表现拉丝金属等被拉长的高光
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Anisotropy {
+    pub roughness_u: f32, // 沿切向的粗糙度，属于(0.0, 1.0]
+    pub roughness_v: f32, // 沿副切向的粗糙度，属于(0.0, 1.0]
+}
+
+impl Anisotropy {
+    /// 按 GGX 分布采样微表面法向并反射入射光线，返回反射方向与 Smith 遮蔽项对应的权重，
+    /// 反射方向位于表面以下时返回 None。`normal` 朝向入射光线一侧
+    fn sample(&self, direction: Vec3, normal: Vec3, tangent: Vec3, rng: &mut impl Rng) -> Option<(Vec3, f32)> {
+        let (alpha_u, alpha_v) = (self.roughness_u.max(1e-3), self.roughness_v.max(1e-3));
+        let (micro_normal, tangent, bitangent) = self.sample_normal(normal, tangent, rng);
+
+        let incoming = -direction;
+        let reflected = direction.reflect(micro_normal);
+        if reflected.dot(normal) <= 0.0 || incoming.dot(normal) <= 0.0 {
+            return None;
+        }
+
+        // Smith 遮蔽函数中的 Λ，在局部坐标系中计算
+        let lambda = |w: Vec3| {
+            let (x, y, z) = (w.dot(tangent), w.dot(bitangent), w.dot(normal));
+            let alpha2_tan2 = (alpha_u * alpha_u * x * x + alpha_v * alpha_v * y * y) / (z * z);
+            0.5 * (-1.0 + (1.0 + alpha2_tan2).sqrt())
+        };
+        let g = 1.0 / (1.0 + lambda(incoming) + lambda(reflected));
+        let weight = incoming.dot(micro_normal).abs() * g
+            / (incoming.dot(normal) * micro_normal.dot(normal));
+        Some((reflected, weight))
+    }
+
+    /// 按 GGX 分布采样微表面法向，同时返回由切向量构造的与法向正交的切向与副切向
+    fn sample_normal(&self, normal: Vec3, tangent: Vec3, rng: &mut impl Rng) -> (Vec3, Vec3, Vec3) {
+        let (alpha_u, alpha_v) = (self.roughness_u.max(1e-3), self.roughness_v.max(1e-3));
+        let tangent = (tangent - normal * tangent.dot(normal)).try_normalize()
+            .unwrap_or_else(|| normal.any_orthonormal_vector());
+        let bitangent = normal.cross(tangent);
+
+        let (u1, u2) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let phi = (alpha_v * phi.sin()).atan2(alpha_u * phi.cos());
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let tan2_theta = u1 / (1.0 - u1).max(1e-6)
+            / (cos_phi * cos_phi / (alpha_u * alpha_u) + sin_phi * sin_phi / (alpha_v * alpha_v));
+        let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let micro_normal = sin_theta * cos_phi * tangent + sin_theta * sin_phi * bitangent + cos_theta * normal;
+        (micro_normal, tangent, bitangent)
+    }
+}
+
+/// 封闭物体内部均匀的参与介质，光线透射进入物体后直到射出，沿途按 Beer-Lambert 定律衰减，
+/// 并可能在内部散射，例如有色玻璃、略带浑浊的玉石与液体。系数的单位为 1/场景长度单位，
+/// 分量越大，光线穿过相同距离后被吸收或散射的越多
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Medium {
+    pub absorption: Vec3, // 吸收系数 σa
+    pub scattering: Vec3, // 散射系数 σs，为 0 时只有吸收，介质清澈
+    pub asymmetry: f32, // Henyey-Greenstein 相函数的不对称参数 g，属于(-1.0, 1.0)，正值向前散射，0 为各向同性
+}
+
+impl Medium {
+    /// 只吸收、不散射的介质，白光穿过 `distance` 后的颜色为 `color`，颜色越深、距离越短，介质越浓
+    pub fn tinted(color: Vec3, distance: f32) -> Self {
+        let optical_depth = Vec3::from_array(color.clamp(Vec3::splat(1e-6), Vec3::ONE).to_array().map(|c| -c.ln()));
+        Self {
+            absorption: optical_depth / distance.max(f32::EPSILON),
+            scattering: Vec3::ZERO,
+            asymmetry: 0.0,
+        }
+    }
+
+    /// 消光系数 σt = σa + σs
+    pub fn extinction(&self) -> Vec3 {
+        self.absorption + self.scattering
+    }
+
+    /// 按 Henyey-Greenstein 相函数采样沿 `direction` 传播的光线散射后的方向，
+    /// 相函数与概率密度相同，权重为 1
+    pub fn sample_phase(&self, direction: Vec3, rng: &mut impl Rng) -> Vec3 {
+        let g = self.asymmetry.clamp(-0.99, 0.99);
+        let u = rng.gen::<f32>();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+        let tangent = direction.any_orthonormal_vector();
+        let bitangent = direction.cross(tangent);
+        (sin_theta * (phi.cos() * tangent + phi.sin() * bitangent) + cos_theta * direction).normalize()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Material {
     pub ambient: Vec3, // 环境光，分量属于[0.0, 1.0]
     pub diffuse: Vec3, // 漫反射，分量属于[0.0, 1.0]
+    pub diffuse_roughness: f32, // Oren-Nayar 漫反射的粗糙度 σ，即微表面朝向与法向夹角的标准差（弧度），为 0 时为 Lambert 漫反射
     pub diffuse_texture: Option<Texture>, // 漫反射贴图
+    pub solid_texture: Option<SolidTexture>, // 实体纹理，由交点的世界坐标求值，与漫反射颜色及贴图相乘
+    pub ambient_texture: Option<Texture>, // 环境光贴图，即 MTL 中的 map_Ka
+    pub specular_texture: Option<Texture>, // 镜面反射贴图，即 MTL 中的 map_Ks
+    pub bump_texture: Option<Texture>, // 凹凸贴图，即 MTL 中作为灰度高度图的 bump 或 map_Bump，用于扰动着色法向
+    pub bump_multiplier: f32, // 高度的缩放，即 MTL 中的 -bm 选项，贴图亮度乘以该值为高度，单位为场景长度单位
+    pub alpha_texture: Option<Texture>, // 透明度贴图，用于镂空
+    pub alpha_cutoff: f32, // 镂空阈值，采样到的 alpha 低于该值时光线直接穿过
     pub specular: Vec3, // 镜面反射，分量属于[0.0, 1.0]
-    pub emissive: Vec3, // 自发光，分量属于[0.0, 1.0)
+    pub emissive: Vec3, // 自发光颜色，即 MTL 中的 Ke，通常属于[0.0, 1.0]
+    pub emissive_strength: f32, // 自发光强度，即 MTL 中的 Ke_intensity，单位为 W/(sr·m²)，与 emissive 之积为辐亮度
     pub transmission_filter: Vec3, // 透光颜色，分量属于[0.0, 1.0]
-    pub dissolve: f32, // 透明度，属于[0.0, 1.0]
-    pub specular_exponent: f32, // 镜面反射指数，属于(-inf, +inf)
+    pub dissolve: f32, // 不透明度，与 MTL 中的 d 一致，属于[0.0, 1.0]，1.0 表示完全不透明
+    pub specular_exponent: f32, // 镜面反射指数，即 MTL 中的 Ns，属于[0.0, 1000.0]，由 specular_roughness 换算为粗糙度
+    pub roughness: Option<f32>, // 感知粗糙度，即 glTF 的 roughnessFactor，属于[0.0, 1.0]，不为 None 时代替 specular_exponent
+    pub metallic: f32, // 金属度，即 glTF 的 metallicFactor，属于[0.0, 1.0]，金属部分没有漫反射，镜面反射颜色为漫反射颜色
     pub optical_density: f32, // 折射率，属于[1.0, +inf)
+    pub illumination_model: Option<u8>, // MTL 光照模型，为 None 时根据各项系数决定
+    pub cull_backfaces: bool, // 是否剔除背面，只对不透明材质生效，适用于封闭网格
+    pub thin_film: Option<ThinFilm>, // 表面薄膜，为 None 时镜面反射不受干涉影响
+    pub anisotropy: Option<Anisotropy>, // 各向异性光泽反射，为 None 时使用各向同性的模糊镜面反射
+    pub subsurface_mean_free_path: Option<f32>, // 次表面散射的平均自由程，为 None 时漫反射光从入射点出射
+    pub medium: Option<Medium>, // 物体内部的参与介质，透射进入物体的光线在其中被吸收与散射，为 None 时内部为真空
+    pub transmission_roughness: f32, // 透射的感知粗糙度，属于[0.0, 1.0]，为 0 时为光滑透射，大于 0 时折射方向按 GGX 分布扩散，例如磨砂玻璃
+    pub abbe_number: Option<f32>, // 阿贝数，越小色散越强，只在光谱渲染时生效，为 None 时没有色散
+    pub two_sided: bool, // 是否双面，单面材质只有外法向一侧发光与反射，背面不发光、不反射
 }
 
 impl Material {
-    const FUZZ: f32 = 0.1; // 镜面反射的模糊因子
+    const MAX_SPECULAR_EXPONENT: f32 = 1000.0; // MTL 中 Ns 的上限，对应理想镜面
+    const MIN_SPECULAR_ROUGHNESS: f32 = 1e-4; // GGX 粗糙度低于该值时按理想镜面反射
     const AMBIENT_STRENGTH: f32 = 0.2; // 环境光强度因子
-    const EMISSIVE_STRENGTH: f32 = 5.0; // 自发光强度因子
+    pub const ALPHA_CUTOFF: f32 = 0.5; // 默认镂空阈值
 
     // 石膏
     pub const PLASTER: Self = Self {
         ambient: Vec3::new(0.1, 0.1, 0.1),
         diffuse: Vec3::new(0.8, 0.8, 0.8),
+        diffuse_roughness: 0.0,
         diffuse_texture: None,
+        solid_texture: None,
+        ambient_texture: None,
+        specular_texture: None,
+        bump_texture: None,
+        bump_multiplier: 1.0,
+        alpha_texture: None,
+        alpha_cutoff: Self::ALPHA_CUTOFF,
         specular: Vec3::new(0.8, 0.8, 0.8),
         emissive: Vec3::ZERO,
+        emissive_strength: 1.0,
         transmission_filter: Vec3::ZERO,
-        dissolve: 0.0,
+        dissolve: 1.0,
         specular_exponent: 0.0,
+        roughness: None,
+        metallic: 0.0,
         optical_density: 1.0,
+        illumination_model: None,
+        cull_backfaces: false,
+        thin_film: None,
+        anisotropy: None,
+        subsurface_mean_free_path: None,
+        medium: None,
+        transmission_roughness: 0.0,
+        abbe_number: None,
+        two_sided: true,
     };
 
     // 发光体
     pub const LUMINOUS: Self = Self {
         ambient: Vec3::ZERO,
         diffuse: Vec3::ZERO,
+        diffuse_roughness: 0.0,
         diffuse_texture: None,
+        solid_texture: None,
+        ambient_texture: None,
+        specular_texture: None,
+        bump_texture: None,
+        bump_multiplier: 1.0,
+        alpha_texture: None,
+        alpha_cutoff: Self::ALPHA_CUTOFF,
         specular: Vec3::ZERO,
         emissive: Vec3::ONE,
+        emissive_strength: 5.0,
         transmission_filter: Vec3::ZERO,
-        dissolve: 0.0,
+        dissolve: 1.0,
         specular_exponent: 0.0,
+        roughness: None,
+        metallic: 0.0,
         optical_density: 1.0,
+        illumination_model: None,
+        cull_backfaces: false,
+        thin_film: None,
+        anisotropy: None,
+        subsurface_mean_free_path: None,
+        medium: None,
+        transmission_roughness: 0.0,
+        abbe_number: None,
+        two_sided: true,
     };
 
     // 镜面
     pub const MIRROR: Self = Self {
         ambient: Vec3::ZERO,
         diffuse: Vec3::ZERO,
+        diffuse_roughness: 0.0,
         diffuse_texture: None,
-        specular: Vec3::new(2.0, 2.0, 2.0),
+        solid_texture: None,
+        ambient_texture: None,
+        specular_texture: None,
+        bump_texture: None,
+        bump_multiplier: 1.0,
+        alpha_texture: None,
+        alpha_cutoff: Self::ALPHA_CUTOFF,
+        specular: Vec3::ONE,
         emissive: Vec3::ZERO,
+        emissive_strength: 1.0,
         transmission_filter: Vec3::ZERO,
-        dissolve: 0.0,
+        dissolve: 1.0,
         specular_exponent: 1000.0,
+        roughness: None,
+        metallic: 0.0,
         optical_density: 1.0,
+        illumination_model: None,
+        cull_backfaces: false,
+        thin_film: None,
+        anisotropy: None,
+        subsurface_mean_free_path: None,
+        medium: None,
+        transmission_roughness: 0.0,
+        abbe_number: None,
+        two_sided: true,
     };
 
     // 玻璃
     pub const GLASS: Self = Self {
         ambient: Vec3::ZERO,
         diffuse: Vec3::ZERO,
+        diffuse_roughness: 0.0,
         diffuse_texture: None,
-        specular: Vec3::new(2.0, 2.0, 2.0),
+        solid_texture: None,
+        ambient_texture: None,
+        specular_texture: None,
+        bump_texture: None,
+        bump_multiplier: 1.0,
+        alpha_texture: None,
+        alpha_cutoff: Self::ALPHA_CUTOFF,
+        specular: Vec3::ONE,
         emissive: Vec3::ZERO,
+        emissive_strength: 1.0,
         transmission_filter: Vec3::ONE,
-        dissolve: 0.9,
+        dissolve: 0.1,
         specular_exponent: 1000.0,
+        roughness: None,
+        metallic: 0.0,
         optical_density: 1.5,
+        illumination_model: None,
+        cull_backfaces: false,
+        thin_film: None,
+        anisotropy: None,
+        subsurface_mean_free_path: None,
+        medium: None,
+        transmission_roughness: 0.0,
+        abbe_number: None,
+        two_sided: true,
     };
 
+    /// 白模材质：反照率为 `albedo` 的哑光灰白色，没有镜面反射与环境光，用于检查造型与光照
+    pub fn clay(albedo: f32) -> Self {
+        Self {
+            ambient: Vec3::ZERO,
+            diffuse: Vec3::splat(albedo.clamp(0.0, 1.0)),
+            specular: Vec3::ZERO,
+            ..Self::PLASTER
+        }
+    }
+
+    /// 按名字获取预设材质，名字不区分大小写
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "plaster" => Some(Self::PLASTER),
+            "luminous" => Some(Self::LUMINOUS),
+            "mirror" => Some(Self::MIRROR),
+            "glass" => Some(Self::GLASS),
+            _ => None,
+        }
+    }
+
     pub fn from_mtl(material: &tobj::Material, base_path: &str) -> Self {
         let ambient = material.ambient.expect("Ambient not found");
         let diffuse = material.diffuse.expect("Diffuse not found");
         let specular = material.specular.expect("Specular not found");
-        let dissolve = material.dissolve.unwrap_or(0.0);
+        // MTL 中 d 表示不透明度，Tr 表示透明度，两者互补；同时存在时以 d 为准
+        let dissolve = material.dissolve.unwrap_or_else(|| 1.0 - unknown_param(material, "Tr", 0.0));
         let specular_exponent = material.shininess.expect("Shininess not found!");
         let optical_density = material.optical_density.unwrap_or(1.0);
 
-        let emissive = material.unknown_param.get(&String::from("Ke"));
-        let emissive = if let Some(emissive) = emissive {
-            let emissive = emissive.split(" ")
-                .filter(|x| { !x.is_empty() })
-                .map(|x| {
-                    f32::from_str(x).unwrap()
-                }).collect::<Vec<_>>();
-            Vec3::from_slice(&emissive)
-        } else {
-            Vec3::new(0.0, 0.0, 0.0)
-        };
+        // Ke 为自发光颜色，许多导出工具把亮度单独写在 Ke_intensity 中，缺省为 1
+        let emissive = material.emissive.map_or(Vec3::ZERO, |emissive| Vec3::from_slice(&emissive));
+        let emissive_strength = unknown_param(material, "Ke_intensity", 1.0);
 
-        let diffuse_texture = if let Some(filename) = &material.diffuse_texture {
-            Some(Texture::load_from_file(&format!("{}/{}", base_path, filename)))
-        } else {
-            None
+        // 透明材质（例如薄玻璃、树叶）两面都可见，不透明材质只有外法向一侧有效：
+        // OBJ 中的不透明物体通常是封闭网格或朝内的墙面，背面总是被遮挡，
+        // 单面时 Cornell Box 天花板上的光源不会向屋顶外发光，功率与光源采样也只计朝外的一侧
+        let two_sided = dissolve < 1.0 || matches!(material.illumination_model, Some(4 | 6 | 7 | 9));
+
+        let diffuse_texture = material.diffuse_texture.as_ref().map(|filename| {
+            Texture::load_from_file(&format!("{}/{}", base_path, filename))
+        });
+        let ambient_texture = material.ambient_texture.as_ref().map(|filename| {
+            Texture::load_from_file(&format!("{}/{}", base_path, filename))
+        });
+        let specular_texture = material.specular_texture.as_ref().map(|filename| {
+            Texture::load_from_file(&format!("{}/{}", base_path, filename))
+        });
+        let alpha_texture = material.dissolve_texture.as_ref().map(|filename| {
+            Texture::load_from_file(&format!("{}/{}", base_path, filename))
+        });
+        // bump 与 map_Bump 多为灰度高度图，也有导出工具写入切线空间法线贴图（整体偏蓝），后者暂不支持，忽略并给出警告
+        let (bump_texture, bump_multiplier) = match material.normal_texture.as_deref().map(parse_bump) {
+            Some((filename, multiplier)) => {
+                let texture = Texture::load_from_file(&format!("{}/{}", base_path, filename));
+                if texture.is_grayscale() {
+                    (Some(texture), multiplier)
+                } else {
+                    eprintln!("Warning: bump map {filename} of material {} looks like a normal map, ignored", material.name);
+                    (None, 1.0)
+                }
+            }
+            None => (None, 1.0),
         };
 
         Self {
             ambient: Vec3::from_slice(&ambient),
             diffuse: Vec3::from_slice(&diffuse),
+            diffuse_roughness: 0.0,
             diffuse_texture,
+            solid_texture: None,
+            ambient_texture,
+            specular_texture,
+            bump_texture,
+            bump_multiplier,
+            alpha_texture,
+            alpha_cutoff: Self::ALPHA_CUTOFF,
             specular: Vec3::from_slice(&specular),
             emissive,
+            emissive_strength,
             transmission_filter: Vec3::ONE,
             dissolve,
             specular_exponent,
+            roughness: None,
+            metallic: 0.0,
             optical_density,
+            illumination_model: material.illumination_model,
+            cull_backfaces: false,
+            thin_film: None,
+            anisotropy: None,
+            subsurface_mean_free_path: None,
+            medium: None,
+            transmission_roughness: 0.0,
+            abbe_number: None,
+            two_sided,
         }
     }
 
-    /// 在材质纹理上进行采样，其中 u，v 属于 [0.0, 1.0]
-    pub fn sample(&self, u: f32, v: f32) -> Self {
-        let mut sampled_material = self.clone();
-        if let Some(diffuse_texture) = sampled_material.diffuse_texture {
-            sampled_material.diffuse *= diffuse_texture.sample(u, v);
+    /// 材质引用的所有贴图：漫反射、环境光、镜面反射、凹凸与透明度贴图
+    pub fn textures(&self) -> impl Iterator<Item = &Texture> {
+        [&self.diffuse_texture, &self.ambient_texture, &self.specular_texture, &self.bump_texture, &self.alpha_texture]
+            .into_iter()
+            .flatten()
+    }
+
+    /// 在漫反射贴图上进行采样，得到交点处的漫反射颜色，其中 u，v 属于 [0.0, 1.0]
+    pub fn diffuse_at(&self, uv: Vec2) -> Vec3 {
+        match &self.diffuse_texture {
+            Some(diffuse_texture) => self.diffuse * diffuse_texture.sample(uv.x, uv.y),
+            None => self.diffuse,
+        }
+    }
+
+    /// 实体纹理在世界坐标 `point` 处的颜色，没有实体纹理时为白色
+    pub fn solid_color(&self, point: Vec3) -> Vec3 {
+        self.solid_texture.map_or(Vec3::ONE, |texture| texture.color(point))
+    }
+
+    /// 在环境光贴图上进行采样，得到交点处的环境光系数，没有贴图时为 ambient
+    pub fn ambient_at(&self, uv: Vec2) -> Vec3 {
+        match &self.ambient_texture {
+            Some(ambient_texture) => self.ambient * ambient_texture.sample(uv.x, uv.y),
+            None => self.ambient,
+        }
+    }
+
+    /// 在镜面反射贴图上进行采样，得到交点处的镜面反射系数，没有贴图时为 specular
+    pub fn specular_at(&self, uv: Vec2) -> Vec3 {
+        match &self.specular_texture {
+            Some(specular_texture) => self.specular * specular_texture.sample(uv.x, uv.y),
+            None => self.specular,
+        }
+    }
+
+    /// 由凹凸贴图扰动法向 `normal`，`dpdu`、`dpdv` 为交点处位置对贴图坐标的偏导数。
+    /// 表面沿法向移动高度 h 后，偏导数变为 dpdu + ∂h/∂u · n 与 dpdv + ∂h/∂v · n，其叉积即为扰动后的法向；
+    /// 高度的偏导数由相邻像素处的双线性采样作中心差分得到。没有凹凸贴图时返回原法向
+    pub fn bump_normal(&self, normal: Vec3, dpdu: Vec3, dpdv: Vec3, uv: Vec2) -> Vec3 {
+        let Some(bump_texture) = &self.bump_texture else {
+            return normal;
+        };
+        let (width, height) = bump_texture.dimensions();
+        let (du, dv) = (1.0 / width as f32, 1.0 / height as f32);
+        let height_at = |u: f32, v: f32| {
+            self.bump_multiplier * bump_texture.sample_filtered(u, v, 0.0).element_sum() / 3.0
+        };
+        let dhdu = (height_at(uv.x + du, uv.y) - height_at(uv.x - du, uv.y)) / (2.0 * du);
+        let dhdv = (height_at(uv.x, uv.y + dv) - height_at(uv.x, uv.y - dv)) / (2.0 * dv);
+        let bumped = (dpdu + dhdu * normal).cross(dpdv + dhdv * normal);
+        match bumped.try_normalize() {
+            // 叉积的方向取决于贴图坐标的手性，翻转到原法向一侧
+            Some(bumped) if bumped.dot(normal) < 0.0 => -bumped,
+            Some(bumped) => bumped,
+            None => normal,
+        }
+    }
+
+    /// 由 MTL 的镜面反射指数 Ns 得到各向同性镜面反射的 GGX 粗糙度 alpha，
+    /// 与 Blender 导入 OBJ 时的约定一致：先求感知粗糙度 r = 1 - sqrt(Ns / 1000)，alpha = r²。
+    /// Ns 截断到 MTL 规定的 [0, 1000]，1000 为理想镜面，800 约为 0.011，10 约为 0.81，0 为 1；
+    /// 设置了感知粗糙度 `roughness`（glTF 材质）时直接取其平方
+    pub fn specular_roughness(&self) -> f32 {
+        let roughness = match self.roughness {
+            Some(roughness) => roughness.clamp(0.0, 1.0),
+            None => 1.0 - (self.specular_exponent.clamp(0.0, Self::MAX_SPECULAR_EXPONENT)
+                / Self::MAX_SPECULAR_EXPONENT).sqrt(),
+        };
+        roughness * roughness
+    }
+
+    /// 是否剔除背面，透明材质（例如薄玻璃）两面都可见，不会被剔除
+    pub fn culls_backfaces(&self) -> bool {
+        self.cull_backfaces && self.dissolve >= 1.0
+    }
+
+    /// 镂空测试，返回 false 表示该点透明，光线应当穿过
+    pub fn alpha_test(&self, u: f32, v: f32) -> bool {
+        match &self.alpha_texture {
+            Some(alpha_texture) => alpha_texture.sample_alpha(u, v) >= self.alpha_cutoff,
+            None => true,
         }
-        sampled_material
     }
 
     /// 入射光线照射到某材质被分散成若干条出射光线
     ///
-    /// 入射光颜色 = 出射光线颜色 * 系数 + 自发光颜色 + 环境光颜色
-    pub fn scatter(&self, ray: &Ray, hit_record: HitRecord) -> Vec<ScatteredRay> {
+    /// 入射光颜色 = 出射光线颜色 * 系数 + 自发光颜色 + 环境光颜色；
+    /// 采样使用的随机数都取自 `rng`，渲染器传入当前线程的 `rand_util::LocalRng`
+    pub fn scatter(&self, ray: &Ray, hit_record: HitRecord, rng: &mut impl Rng) -> Vec<ScatteredRay> {
         let mut scattered_rays = vec![];
+        if self.back_face(&hit_record) {
+            return scattered_rays;
+        }
         let normal = hit_record.normal;
         let origin = hit_record.point;
 
-        // 漫反射
-        let diffuse_coefficient = self.diffuse * 0.5 * (1.0 - self.dissolve);
-        let diffuse_direction = rand_util::random_unit_vector_cosine(normal);
-        let diffuse_ray = Ray::new(origin, diffuse_direction);
+        // 漫反射，Lambert BRDF 为 反照率 / π，按余弦分布采样时被积函数 反照率 / π * cos 与
+        // 概率密度 cos / π 相约，散射系数即为反照率；Oren-Nayar 同样按余弦分布采样，系数再乘以其与 Lambert 之比
+        let (diffuse_coefficient, opaque_specular) = self.opaque_coefficients(&hit_record);
+        let (diffuse_direction, diffuse_pdf) = rand_util::random_unit_vector_cosine_pdf_with(normal, rng);
+        let diffuse_ray = Ray::new_unchecked(self.subsurface_exit(origin, normal, rng), diffuse_direction)
+            .with_time(ray.time)
+            .with_wavelength(ray.wavelength);
 
         if diffuse_coefficient.max_element() > 0.0 {
+            let oren_nayar = self.oren_nayar(normal, -ray.direction.normalize(), diffuse_direction);
             scattered_rays.push(ScatteredRay {
                 ray: diffuse_ray,
-                coefficient: diffuse_coefficient,
+                coefficient: diffuse_coefficient * oren_nayar,
+                kind: ScatterKind::Diffuse,
+                pdf: diffuse_pdf,
             });
         }
 
-        // 镜面反射
-        let specular_coefficient = self.specular * 0.5 * (1.0 - self.dissolve);
-        let mut specular_direction = ray.direction.reflect(hit_record.normal);
-        specular_direction +=
-            Self::FUZZ.powf(self.specular_exponent) * rand_util::random_unit_vector();
-        specular_direction = specular_direction.normalize();
-        let specular_ray = Ray::new(origin, specular_direction);
+        // 镜面反射，不透明部分与漫反射一起归一化，透明部分按 Fresnel 反射率在表面反射，
+        // 发生全反射时透明部分全部反射
+        let refracted = self.refract(ray, normal, hit_record.front_face);
+        let transparency = if self.transmission_enabled() { 1.0 - self.dissolve } else { 0.0 };
+        let reflectance = refracted.map_or(1.0, |refracted| self.fresnel(ray, normal, refracted, hit_record.front_face));
+        let mut specular_coefficient = opaque_specular + Vec3::splat(transparency * reflectance);
+        let transmissive_coefficient = self.transmission_filter * transparency * (1.0 - reflectance);
+        if let Some(thin_film) = self.thin_film {
+            // 只改变各通道之间的比例，保持镜面反射的总体强度不变，
+            // 放大后的通道不超过漫反射与透射剩下的部分，避免散射的能量超过入射能量
+            let substrate_ior = self.optical_density.max(1.0);
+            let reflectance = thin_film.reflectance(ray.direction.dot(normal).abs(), substrate_ior);
+            let mean = reflectance.element_sum() / 3.0;
+            if mean > 0.0 {
+                let headroom = (Vec3::ONE - diffuse_coefficient - transmissive_coefficient).max(Vec3::ZERO);
+                specular_coefficient = (specular_coefficient * reflectance / mean).min(headroom);
+            }
+        }
+        debug_assert!(
+            (diffuse_coefficient + specular_coefficient + transmissive_coefficient).max_element() <= 1.0 + 1e-4,
+            "Material scatters more energy than it receives"
+        );
+        let specular_direction = match self.anisotropy {
+            Some(anisotropy) => {
+                anisotropy.sample(ray.direction, normal, hit_record.tangent, rng).map(|(direction, weight)| {
+                    specular_coefficient *= weight;
+                    direction
+                })
+            }
+            // 各向同性的光泽反射与各向异性使用同一个 GGX 采样，粗糙度由 specular_roughness 换算
+            None => match self.specular_roughness() {
+                alpha if alpha < Self::MIN_SPECULAR_ROUGHNESS => Some(ray.direction.reflect(normal)),
+                alpha => {
+                    let lobe = Anisotropy { roughness_u: alpha, roughness_v: alpha };
+                    lobe.sample(ray.direction, normal, hit_record.tangent, rng).map(|(direction, weight)| {
+                        specular_coefficient *= weight;
+                        direction
+                    })
+                }
+            },
+        };
 
-        if specular_coefficient.max_element() > 0.0 {
+        let specular_enabled = self.specular_enabled() && specular_coefficient.max_element() > 0.0;
+        if let Some(specular_direction) = specular_direction.filter(|_| specular_enabled) {
+            let specular_ray = Ray::new_unchecked(origin, specular_direction)
+                .with_time(ray.time)
+                .with_wavelength(ray.wavelength);
             scattered_rays.push(ScatteredRay {
                 ray: specular_ray,
                 coefficient: specular_coefficient,
+                kind: ScatterKind::Specular,
+                pdf: 0.0,
             });
         }
 
-        // 透射，这里认为反射与折射的能量分配总是平权的
-        let transmissive_coefficient = self.transmission_filter * self.dissolve;
+        // 透射，全反射时系数为 0
+        if let Some(transmissive_direction) = refracted.filter(|_| transmissive_coefficient.max_element() > 0.0) {
+            let transmissive_direction = self.rough_refract(ray, normal, &hit_record, rng).unwrap_or(transmissive_direction);
+            let transmissive_ray = Ray::new_unchecked(origin, transmissive_direction)
+                .with_time(ray.time)
+                .with_wavelength(ray.wavelength);
+            scattered_rays.push(ScatteredRay {
+                ray: transmissive_ray,
+                coefficient: transmissive_coefficient,
+                kind: ScatterKind::Transmission,
+                pdf: 0.0,
+            });
+        }
+
+        scattered_rays
+    }
+
+    /// 粗糙透射：按 GGX 分布采样微表面法向，沿微表面折射；粗糙度为 0、在微表面上全反射或折射后
+    /// 仍在入射一侧时返回 None，使用光滑的折射方向。透射系数仍按宏观表面计算，不考虑微表面的遮蔽
+    fn rough_refract(&self, ray: &Ray, normal: Vec3, hit_record: &HitRecord, rng: &mut impl Rng) -> Option<Vec3> {
+        if self.transmission_roughness <= 0.0 {
+            return None;
+        }
+        let alpha = self.transmission_roughness.clamp(0.0, 1.0).powi(2);
+        let lobe = Anisotropy { roughness_u: alpha, roughness_v: alpha };
+        let (micro_normal, _, _) = lobe.sample_normal(normal, hit_record.tangent, rng);
+        self.refract(ray, micro_normal, hit_record.front_face)
+            .map(Vec3::normalize)
+            .filter(|refracted| refracted.dot(normal) < 0.0)
+    }
+
+    /// 路径正则化使用的副本：镜面反射与透射的感知粗糙度至少为 `roughness`，
+    /// 光滑的镜面与玻璃变为略微粗糙的表面，焦散路径击中小光源的概率变大、贡献变小，减少萤火虫
+    pub fn regularized(&self, roughness: f32) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            roughness: Some(self.specular_roughness().sqrt().max(roughness)),
+            transmission_roughness: self.transmission_roughness.max(roughness),
+            ..self.clone()
+        }
+    }
+
+    /// 次表面散射的近似：光线进入材质后在内部散射，从入射点附近出射，
+    /// 出射点在切平面内，与入射点的距离服从以平均自由程为均值的指数分布，
+    /// 效果相当于按平均自由程模糊漫反射，使石膏、大理石等材质显得更柔和。
+    /// 渲染器随后将出射点投影回物体表面，见 `render::project_subsurface_exits`
+    fn subsurface_exit(&self, point: Vec3, normal: Vec3, rng: &mut impl Rng) -> Vec3 {
+        let Some(mean_free_path) = self.subsurface_mean_free_path.filter(|mfp| *mfp > 0.0) else {
+            return point;
+        };
+        // 截断在 3 倍平均自由程以内，避免光线从很远处的表面出射
+        let distance = (-mean_free_path * (1.0 - rng.gen::<f32>()).ln()).min(3.0 * mean_free_path);
+        let tangent = normal.any_orthonormal_vector();
+        let bitangent = normal.cross(tangent);
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+        point + distance * (phi.cos() * tangent + phi.sin() * bitangent)
+    }
 
-        if transmissive_coefficient.max_element() > 0.0 {
-            if let Some(transmissive_direction) = self.refract(ray, hit_record.normal) {
-                let transmissive_ray = Ray::new(origin, transmissive_direction);
-                scattered_rays.push(ScatteredRay {
-                    ray: transmissive_ray,
-                    coefficient: transmissive_coefficient,
-                });
+    /// 交点处漫反射分量的系数，即 scatter 中漫反射光线的系数
+    pub fn diffuse_reflectance(&self, hit_record: &HitRecord) -> Vec3 {
+        if self.back_face(hit_record) {
+            return Vec3::ZERO;
+        }
+        self.opaque_coefficients(hit_record).0
+    }
+
+    // 不透明部分的漫反射与镜面反射系数，两者之和的最大分量超过 1 时等比例缩小，
+    // 再乘以不透明度，使散射系数之和不超过 1
+    fn opaque_coefficients(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let uv = hit_record.uv;
+        let diffuse = match &self.diffuse_texture {
+            Some(diffuse_texture) if hit_record.uv_footprint > 0.0 => {
+                self.diffuse * diffuse_texture.sample_filtered(uv.x, uv.y, hit_record.uv_footprint)
             }
+            _ => self.diffuse_at(uv),
+        } * self.solid_color(hit_record.point);
+        let specular = if self.specular_enabled() { self.specular_at(uv) } else { Vec3::ZERO };
+        // 金属部分以漫反射颜色作为镜面反射颜色，没有漫反射
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let specular = specular.lerp(diffuse, metallic);
+        let diffuse = diffuse * (1.0 - metallic);
+        let scale = self.dissolve / (diffuse + specular).max_element().max(1.0);
+        (diffuse * hit_record.color * scale, specular * scale)
+    }
+
+    /// 透明部分在表面上的 Fresnel 反射率（Schlick 近似），`refracted` 为折射方向，
+    /// 从光密介质射出时使用折射角计算
+    fn fresnel(&self, ray: &Ray, normal: Vec3, refracted: Vec3, front_face: bool) -> f32 {
+        let optical_density = self.optical_density_at(ray.wavelength);
+        let r0 = ((optical_density - 1.0) / (optical_density + 1.0)).powi(2);
+        let cos_theta = if front_face {
+            ray.direction.normalize().dot(normal).abs()
+        } else {
+            refracted.normalize().dot(normal).abs()
+        };
+        r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+    }
+
+    /// 漫反射分量的 BRDF，即漫反射系数 / π，Oren-Nayar 再乘以其与 Lambert 之比，镜面反射与透射视为 delta 分布，不参与求值；
+    /// `wi`、`wo` 均由交点指向外侧，与法向不在同一侧时返回 0
+    pub fn eval(&self, hit_record: &HitRecord, wi: Vec3, wo: Vec3) -> Vec3 {
+        if wi.dot(hit_record.normal) <= 0.0 || wo.dot(hit_record.normal) <= 0.0 {
+            return Vec3::ZERO;
         }
+        self.diffuse_reflectance(hit_record) / std::f32::consts::PI * self.oren_nayar(hit_record.normal, wi, wo)
+    }
 
-        scattered_rays
+    /// Oren-Nayar 漫反射（定性模型）的 BRDF 与 Lambert BRDF 之比，`wi`、`wo` 均由交点指向外侧，
+    /// 粗糙度为 0 时为 1。粗糙表面把更多的光反射回光源方向，掠射角处显得更亮、更平，
+    /// 对应的散射系数在该方向上可能超过反照率，但对所有出射方向的平均不超过反照率
+    ///
+    /// f = ρ / π · (A + B · max(0, cos(φi - φo)) · sin α · tan β)，α、β 为 θi、θo 中较大、较小者，
+    /// 其中 cos(φi - φo) · sin θi · sin θo 即两个方向在切平面上的投影之积，因此 sin α · tan β 项
+    /// 等于投影之积除以 max(cos θi, cos θo)
+    pub fn oren_nayar(&self, normal: Vec3, wi: Vec3, wo: Vec3) -> f32 {
+        if self.diffuse_roughness <= 0.0 {
+            return 1.0;
+        }
+        let sigma2 = self.diffuse_roughness * self.diffuse_roughness;
+        let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        let (cos_i, cos_o) = (normal.dot(wi).max(0.0), normal.dot(wo).max(0.0));
+        let projected = wi.dot(wo) - cos_i * cos_o;
+        a + b * projected.max(0.0) / cos_i.max(cos_o).max(1e-4)
+    }
+
+    /// 漫反射分量按余弦分布采样得到方向 `wo` 的概率密度（立体角），Lambert 与 Oren-Nayar 相同
+    pub fn pdf(&self, hit_record: &HitRecord, wo: Vec3) -> f32 {
+        wo.dot(hit_record.normal).max(0.0) / std::f32::consts::PI
     }
 
-    /// 计算自发光颜色
+    /// 计算自发光辐亮度，即颜色乘以强度
     pub fn emissive_color(&self) -> Vec3 {
-        self.emissive * Self::EMISSIVE_STRENGTH
+        self.emissive * self.emissive_strength
     }
 
-    /// 计算材质的环境光颜色
-    pub fn ambient_color(&self) -> Vec3 {
-        self.ambient * (1.0 - self.dissolve) * Self::AMBIENT_STRENGTH
+    /// 光线击中交点时看到的自发光，单面材质的背面不发光
+    pub fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        if self.back_face(hit_record) {
+            Vec3::ZERO
+        } else {
+            self.emissive_color()
+        }
     }
 
-    /// 计算折射光线的方向
-    fn refract(&self, ray: &Ray, normal: Vec3) -> Option<Vec3> {
-        let cos_theta = ray.direction.dot(normal);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    /// 外法向为 `outward_normal` 的表面点沿 `direction` 方向发出的自发光
+    pub fn emitted_towards(&self, outward_normal: Vec3, direction: Vec3) -> Vec3 {
+        if self.two_sided || outward_normal.dot(direction) > 0.0 {
+            self.emissive_color()
+        } else {
+            Vec3::ZERO
+        }
+    }
 
-        if cos_theta > 0.0 {
-            // 光线射出当前材料
-            let sin_phi = sin_theta * self.optical_density;
-            let cos_phi = (1.0 - sin_phi * sin_phi).sqrt();
-            // 发生全反射
-            if sin_phi > 1.0 {
-                return None;
-            }
-            // 直射
-            if sin_phi < f32::EPSILON {
-                return Some(normal);
-            }
+    /// 发光的面数，双面材质为 2，单面材质为 1
+    pub fn emitting_sides(&self) -> f32 {
+        if self.two_sided { 2.0 } else { 1.0 }
+    }
+
+    /// 光线是否击中了单面材质的背面
+    fn back_face(&self, hit_record: &HitRecord) -> bool {
+        !self.two_sided && !hit_record.front_face
+    }
+
+    /// 计算材质在贴图坐标 uv 处的环境光颜色
+    pub fn ambient_color(&self, uv: Vec2) -> Vec3 {
+        self.ambient_at(uv) * self.dissolve * Self::AMBIENT_STRENGTH
+    }
+
+    /// 波长 `wavelength`（纳米）处的折射率，`optical_density` 视为 d 线（587.6nm）处的折射率，
+    /// 按阿贝数拟合 Cauchy 公式 n = A + B / λ²；没有波长或阿贝数时返回 `optical_density`
+    fn optical_density_at(&self, wavelength: Option<f32>) -> f32 {
+        let (Some(wavelength), Some(abbe_number)) = (wavelength, self.abbe_number) else {
+            return self.optical_density;
+        };
+        // F 线、C 线、d 线的波长（微米）
+        let (f, c, d) = (0.4861f32, 0.6563f32, 0.5876f32);
+        let b = (self.optical_density - 1.0) / (abbe_number * (f.powi(-2) - c.powi(-2)));
+        let a = self.optical_density - b / (d * d);
+        let wavelength = wavelength / 1000.0;
+        a + b / (wavelength * wavelength)
+    }
+
+    /// 根据光照模型判断是否启用镜面反射，illum 0 与 1 不包含高光项
+    fn specular_enabled(&self) -> bool {
+        !matches!(self.illumination_model, Some(0) | Some(1))
+    }
+
+    /// 根据光照模型判断是否启用透射，只有 illum 4、6、7、9 包含透明项
+    fn transmission_enabled(&self) -> bool {
+        match self.illumination_model {
+            Some(illum) => matches!(illum, 4 | 6 | 7 | 9),
+            None => true,
+        }
+    }
 
-            let u = normal.cross(ray.direction.cross(normal)).normalize();
-            let v = normal;
-            Some((sin_phi * u + cos_phi * v).normalize())
+    /// 使用 Snell 定律计算折射光线的方向，发生全反射时返回 None
+    ///
+    /// `normal` 总是朝向入射光线一侧，`front_face` 为 true 表示光线从外部射入材料
+    fn refract(&self, ray: &Ray, normal: Vec3, front_face: bool) -> Option<Vec3> {
+        let optical_density = self.optical_density_at(ray.wavelength);
+        let eta = if front_face {
+            1.0 / optical_density
         } else {
-            // 光线射入当前材料
-            let sin_phi = sin_theta / self.optical_density;
-            let cos_phi = (1.0 - sin_phi * sin_phi).sqrt();
-            // 直射
-            if sin_phi < f32::EPSILON {
-                return Some(-normal);
-            }
+            optical_density
+        };
+        let refracted = ray.direction.refract(normal, eta);
+        if refracted == Vec3::ZERO {
+            None
+        } else {
+            Some(refracted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // 解析 MTL 文本中唯一的材质
+    fn load_mtl(mtl: &str) -> Material {
+        let (materials, _) = tobj::load_mtl_buf(&mut mtl.as_bytes()).unwrap();
+        Material::from_mtl(&materials[0], ".")
+    }
 
-            let u = normal.cross(ray.direction.cross(normal)).normalize();
-            let v = -normal;
-            Some((sin_phi * u + cos_phi * v).normalize())
+    // 透明光照模型下只有 d 或 Tr 不同的玻璃
+    fn glass(opacity: &str) -> Material {
+        load_mtl(&format!("newmtl glass\nKa 0 0 0\nKd 0 0 0\nKs 0.8 0.8 0.8\nNs 800\nNi 1.5\nillum 7\n{opacity}\n"))
+    }
+
+    fn transmits(material: &Material) -> bool {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::NEG_Z);
+        let hit = HitRecord::new(&ray, Vec3::ZERO, Vec3::Z, 1.0, Vec2::ZERO, material);
+        let mut rng = StdRng::seed_from_u64(0);
+        material.scatter(&ray, hit, &mut rng).iter().any(|s| s.kind == ScatterKind::Transmission)
+    }
+
+    #[test]
+    fn d_one_is_fully_opaque() {
+        let material = glass("d 1.0");
+        assert_eq!(material.dissolve, 1.0);
+        assert!(!transmits(&material));
+    }
+
+    #[test]
+    fn d_is_opacity_and_tr_is_transparency() {
+        assert_eq!(glass("d 0.25").dissolve, 0.25);
+        assert_eq!(glass("Tr 0.25").dissolve, 0.75);
+        assert!(transmits(&glass("d 0.25")));
+        // 同时存在时以 d 为准
+        assert_eq!(glass("d 1.0\nTr 0.5").dissolve, 1.0);
+        assert_eq!(glass("").dissolve, 1.0);
+    }
+
+    #[test]
+    fn malformed_tr_falls_back_to_opaque() {
+        assert_eq!(glass("Tr abc").dissolve, 1.0);
+    }
+
+    // 只有自发光强度不同的光源
+    fn light(intensity: &str) -> Material {
+        load_mtl(&format!("newmtl light\nKa 0 0 0\nKd 0.78 0.78 0.78\nKs 0 0 0\nNs 10\nKe 17 12 4\n{intensity}\n"))
+    }
+
+    // 从 +z 一侧（外法向一侧）或 -z 一侧射向 xy 平面上的点
+    fn emitted_from(material: &Material, side: f32) -> Vec3 {
+        let ray = Ray::new(Vec3::Z * side, Vec3::NEG_Z * side);
+        material.emitted(&HitRecord::new(&ray, Vec3::ZERO, Vec3::Z, 1.0, Vec2::ZERO, material))
+    }
+
+    #[test]
+    fn one_sided_emitters_only_emit_from_the_front() {
+        let light = light("Ke_intensity 5");
+        assert!(!light.two_sided);
+        assert_eq!(light.emitting_sides(), 1.0);
+        assert_eq!(emitted_from(&light, 1.0), light.emissive_color());
+        assert_eq!(emitted_from(&light, -1.0), Vec3::ZERO);
+        assert_eq!(light.emitted_towards(Vec3::Z, Vec3::Z), light.emissive_color());
+        assert_eq!(light.emitted_towards(Vec3::Z, Vec3::NEG_Z), Vec3::ZERO);
+    }
+
+    #[test]
+    fn two_sided_emitters_emit_from_both_sides() {
+        let light = Material { two_sided: true, ..light("") };
+        assert_eq!(light.emitting_sides(), 2.0);
+        assert_eq!(emitted_from(&light, 1.0), light.emissive_color());
+        assert_eq!(emitted_from(&light, -1.0), light.emissive_color());
+        assert_eq!(light.emitted_towards(Vec3::Z, Vec3::NEG_Z), light.emissive_color());
+    }
+
+    #[test]
+    fn only_see_through_mtl_materials_are_two_sided() {
+        assert!(glass("d 0.5").two_sided);
+        assert!(!load_mtl("newmtl wall\nKa 0 0 0\nKd 0.5 0.5 0.5\nKs 0 0 0\nNs 10\nillum 2\n").two_sided);
+        assert!(load_mtl("newmtl glass\nKa 0 0 0\nKd 0 0 0\nKs 1 1 1\nNs 10\nillum 7\n").two_sided);
+    }
+
+    #[test]
+    fn ke_intensity_scales_emission() {
+        assert_eq!(light("").emissive_strength, 1.0);
+        assert_eq!(light("Ke_intensity 5").emissive_strength, 5.0);
+        assert_eq!(light("Ke_intensity 5").emissive_color(), Vec3::new(85.0, 60.0, 20.0));
+    }
+
+    #[test]
+    fn malformed_ke_intensity_falls_back_to_one() {
+        assert_eq!(light("Ke_intensity bright").emissive_strength, 1.0);
+    }
+
+    // 玻璃（n = 1.5）的 Fresnel 反射率在垂直入射时为 ((n - 1) / (n + 1))² = 0.04
+    const GLASS_R0: f32 = 0.04;
+
+    #[test]
+    fn refract_straight_on() {
+        let ray = Ray::new(Vec3::Z, Vec3::NEG_Z);
+        let refracted = Material::GLASS.refract(&ray, Vec3::Z, true).unwrap();
+        assert!(refracted.abs_diff_eq(Vec3::NEG_Z, 1e-6), "{refracted}");
+        let reflectance = Material::GLASS.fresnel(&ray, Vec3::Z, refracted, true);
+        assert!((reflectance - GLASS_R0).abs() < 1e-6, "{reflectance}");
+    }
+
+    #[test]
+    fn refract_at_45_degrees() {
+        let direction = Vec3::new(1.0, 0.0, -1.0).normalize();
+        let ray = Ray::new(Vec3::ZERO, direction);
+        let refracted = Material::GLASS.refract(&ray, Vec3::Z, true).unwrap();
+        // Snell 定律：sin θt = sin 45° / 1.5，折射光线偏向法向
+        let sin_t = std::f32::consts::FRAC_1_SQRT_2 / 1.5;
+        let expected = Vec3::new(sin_t, 0.0, -(1.0 - sin_t * sin_t).sqrt());
+        assert!(refracted.abs_diff_eq(expected, 1e-5), "{refracted}");
+        let reflectance = Material::GLASS.fresnel(&ray, Vec3::Z, refracted, true);
+        let schlick = GLASS_R0 + (1.0 - GLASS_R0) * (1.0 - std::f32::consts::FRAC_1_SQRT_2).powi(5);
+        assert!((reflectance - schlick).abs() < 1e-6, "{reflectance}");
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        // 从玻璃内部以 60° 射出，1.5 · sin 60° > 1，发生全反射
+        let direction = Vec3::new(60f32.to_radians().sin(), 0.0, 60f32.to_radians().cos());
+        let ray = Ray::new(Vec3::ZERO, direction);
+        let normal = Vec3::NEG_Z; // 朝向入射光线一侧，外法向为 +z
+        assert!(Material::GLASS.refract(&ray, normal, false).is_none());
+
+        let hit = HitRecord::new(&ray, Vec3::Z, Vec3::Z, 1.0, Vec2::ZERO, &Material::GLASS);
+        assert!(!hit.front_face);
+        let mut rng = StdRng::seed_from_u64(0);
+        let scattered = Material::GLASS.scatter(&ray, hit, &mut rng);
+        assert!(scattered.iter().all(|s| s.kind != ScatterKind::Transmission));
+        let reflected = scattered.iter().find(|s| s.kind == ScatterKind::Specular).unwrap();
+        assert!(reflected.ray.direction.normalize().abs_diff_eq(direction.reflect(normal), 1e-5));
+        // 透明部分全部反射，与不透明部分的镜面反射一起，入射能量全部反射
+        assert!(reflected.coefficient.abs_diff_eq(Vec3::ONE, 1e-5), "{}", reflected.coefficient);
+    }
+
+    #[test]
+    fn thin_film_never_scatters_more_than_it_receives() {
+        let thin_film = ThinFilm { thickness: 400.0, ior: 1.33 };
+        for material in [Material::MIRROR, Material::GLASS, Material::PLASTER] {
+            let material = Material { thin_film: Some(thin_film), ..material };
+            let mut rng = StdRng::seed_from_u64(0);
+            for degrees in [0.0f32, 30.0, 60.0, 85.0] {
+                let direction = Vec3::new(degrees.to_radians().sin(), 0.0, -degrees.to_radians().cos());
+                let ray = Ray::new(Vec3::ZERO, direction);
+                let hit = HitRecord::new(&ray, Vec3::NEG_Z, Vec3::Z, 1.0, Vec2::ZERO, &material);
+                let total = material.scatter(&ray, hit, &mut rng).iter().map(|s| s.coefficient).sum::<Vec3>();
+                assert!(total.max_element() <= 1.0 + 1e-4, "{degrees}°: {total}");
+            }
         }
     }
 }
@@ -0,0 +1,153 @@
+use glam::{IVec3, Vec3};
+
+/// 三维 Perlin 梯度噪声，结果大致属于 [-1.0, 1.0]，整数格点处为 0
+///
+/// 格点梯度由格点坐标的整数哈希决定，不需要置换表，同一点的结果在任何线程中都相同
+pub fn perlin(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let base = cell.as_ivec3();
+    let local = point - cell;
+    // 五次插值曲线 6t⁵ - 15t⁴ + 10t³，一阶与二阶导数在格点处连续
+    let fade = local * local * local * (local * (local * 6.0 - 15.0) + 10.0);
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let offset = IVec3::new(dx, dy, dz);
+        gradient(hash(base + offset)).dot(local - offset.as_vec3())
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+    lerp(lerp(x00, x10, fade.y), lerp(x01, x11, fade.y), fade.z)
+}
+
+/// 三维 Worley（细胞）噪声，每个整数格子中有一个随机特征点，返回到最近特征点的距离，属于 [0.0, √3]，
+/// 多数点小于 1.0
+pub fn worley(point: Vec3) -> f32 {
+    let base = point.floor().as_ivec3();
+    let mut nearest = f32::INFINITY;
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let cell = base + IVec3::new(dx, dy, dz);
+                let h = hash(cell);
+                let jitter = Vec3::new(
+                    (h & 0x3ff) as f32,
+                    ((h >> 10) & 0x3ff) as f32,
+                    ((h >> 20) & 0x3ff) as f32,
+                ) / 1024.0;
+                nearest = nearest.min(point.distance_squared(cell.as_vec3() + jitter));
+            }
+        }
+    }
+    nearest.sqrt()
+}
+
+/// 湍流：`octaves` 个倍频的 Perlin 噪声绝对值之和，每个倍频的频率加倍、振幅减半，结果属于 [0.0, 1.0)
+pub fn turbulence(point: Vec3, octaves: u32) -> f32 {
+    let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 0.5, 1.0, 0.0);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(point * frequency).abs();
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// 由噪声生成的图案
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// 大理石：沿 x 方向的正弦条纹被湍流扰动，形成弯曲的纹理
+    Marble,
+    /// 木纹：围绕 y 轴的同心年轮，半径被湍流扰动
+    Wood,
+    /// 细胞：Worley 噪声，适合石块、鳞片等由胞体组成的图案
+    Cells,
+    /// 湍流本身，适合云雾、锈迹等不规则的斑块
+    Turbulence,
+}
+
+/// 由三维噪声在交点的世界坐标处求值的实体纹理，不需要贴图坐标，适用于没有 uv 的导入模型；
+/// 物体内部各处都有定义，切开的表面纹理连续
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SolidTexture {
+    pub pattern: NoisePattern,
+    pub scale: f32, // 求值前坐标乘以该值，越大纹理越细密
+    pub octaves: u32, // 湍流叠加的倍频数，越多细节越丰富，至少为 1
+    pub ramp: &'static [Vec3], // 颜色渐变，图案值 0 到 1 均匀对应各个颜色，相邻颜色之间线性插值
+}
+
+impl SolidTexture {
+    pub const MARBLE_RAMP: &'static [Vec3] = &[
+        Vec3::new(0.25, 0.25, 0.28),
+        Vec3::new(0.85, 0.85, 0.85),
+        Vec3::new(0.95, 0.95, 0.93),
+    ];
+    pub const WOOD_RAMP: &'static [Vec3] = &[
+        Vec3::new(0.45, 0.25, 0.10),
+        Vec3::new(0.75, 0.50, 0.25),
+    ];
+
+    /// 由图案与缩放创建实体纹理，倍频数为 4，使用该图案的默认颜色渐变（细胞与湍流为黑到白）
+    pub fn new(pattern: NoisePattern, scale: f32) -> Self {
+        let ramp = match pattern {
+            NoisePattern::Marble => Self::MARBLE_RAMP,
+            NoisePattern::Wood => Self::WOOD_RAMP,
+            NoisePattern::Cells | NoisePattern::Turbulence => &[Vec3::ZERO, Vec3::ONE],
+        };
+        Self { pattern, scale, octaves: 4, ramp }
+    }
+
+    /// 世界坐标 `point` 处的图案值，属于 [0.0, 1.0]
+    pub fn value(&self, point: Vec3) -> f32 {
+        let p = point * self.scale;
+        let value = match self.pattern {
+            NoisePattern::Marble => 0.5 + 0.5 * (p.x + 5.0 * turbulence(p, self.octaves)).sin(),
+            NoisePattern::Wood => {
+                // 年轮由浅到深再到浅，避免每圈出现突变的边界
+                let radius = (p.x * p.x + p.z * p.z).sqrt() + 0.5 * turbulence(p, self.octaves);
+                1.0 - (2.0 * radius.fract() - 1.0).abs()
+            }
+            NoisePattern::Cells => worley(p),
+            NoisePattern::Turbulence => turbulence(p, self.octaves),
+        };
+        value.clamp(0.0, 1.0)
+    }
+
+    /// 世界坐标 `point` 处的颜色，由图案值在颜色渐变上插值得到，渐变为空时为白色
+    pub fn color(&self, point: Vec3) -> Vec3 {
+        match self.ramp {
+            [] => Vec3::ONE,
+            [color] => *color,
+            ramp => {
+                let x = self.value(point) * (ramp.len() - 1) as f32;
+                let index = (x.floor() as usize).min(ramp.len() - 2);
+                ramp[index].lerp(ramp[index + 1], x - index as f32)
+            }
+        }
+    }
+}
+
+// 格点坐标的整数哈希
+fn hash(cell: IVec3) -> u32 {
+    let mut h = (cell.x as u32).wrapping_mul(0x8da6_b343)
+        ^ (cell.y as u32).wrapping_mul(0xd816_3841)
+        ^ (cell.z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+// Perlin 改进版噪声的 12 个梯度方向，即立方体各棱的中点方向
+fn gradient(hash: u32) -> Vec3 {
+    const GRADIENTS: [Vec3; 12] = [
+        Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(-1.0, -1.0, 0.0),
+        Vec3::new(1.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, 1.0), Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, -1.0),
+    ];
+    GRADIENTS[(hash % 12) as usize]
+}
@@ -0,0 +1,171 @@
+use std::f32::consts::PI;
+use glam::Vec3;
+use rayon::prelude::*;
+use crate::material::{luminance, ScatterKind};
+use crate::rand_util;
+use crate::ray::Ray;
+use crate::render::{T_MAX, T_MIN};
+use crate::scene::Scene;
+
+/// 焦散光子图的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CausticsOptions {
+    pub photon_count: usize, // 从光源发射的光子数量
+    pub gather_radius: f32,  // 最终收集时的搜索半径，越大噪点越少但焦散越模糊
+}
+
+impl Default for CausticsOptions {
+    fn default() -> Self {
+        Self { photon_count: 200000, gather_radius: 0.03 }
+    }
+}
+
+// 存储在漫反射表面上的光子
+#[derive(Debug, Clone, Copy)]
+struct Photon {
+    position: Vec3,  // 光子落点
+    direction: Vec3, // 光子的入射方向
+    power: Vec3,     // 光子携带的能量
+}
+
+/// 焦散光子图，只记录从光源出发、经过至少一次镜面反射或透射后落在漫反射表面上的光子，
+/// 光子按平衡 kd-tree 的顺序存储在数组中，区间 [lo, hi) 的中点为节点，左右两半为子树
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>, // 每个节点的划分轴
+}
+
+impl PhotonMap {
+    /// 从场景的光源列表发射 `photon_count` 个光子，光子最多发生 `max_depth` 次散射，
+    /// 场景需要已经构建好 BVH
+    pub fn build(scene: &Scene, photon_count: usize, max_depth: u32) -> Self {
+        if photon_count == 0 || scene.lights.is_empty() {
+            return Self { photons: vec![], axes: vec![] };
+        }
+
+        let mut photons = (0..photon_count).into_par_iter().flat_map_iter(|_| {
+            Self::trace(scene, photon_count, max_depth)
+        }).collect::<Vec<_>>();
+
+        let mut axes = vec![0; photons.len()];
+        Self::build_node(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    // 按功率选择光源发射一个光子，返回它沿途存储的光子
+    fn trace(scene: &Scene, photon_count: usize, max_depth: u32) -> Vec<Photon> {
+        let mut stored = vec![];
+        let Some((light, _)) = scene.pick_light() else {
+            return stored;
+        };
+        let sample = light.sample_point().unwrap();
+        // 双面发光时两面各占一半的光子，方向按余弦分布，
+        // 光子能量为 辐亮度 * |cos| / (位置概率密度 * 方向概率密度 * 光子数)
+        let material = light.material().unwrap();
+        let sides = material.emitting_sides();
+        let mut power = material.emissive_color() * sides * PI / (scene.light_pdf(material) * photon_count as f32);
+        let normal = if sides > 1.0 && rand_util::random::<bool>() { -sample.normal } else { sample.normal };
+        let mut ray = Ray::new_unchecked(sample.point, rand_util::random_unit_vector_cosine(normal));
+        let mut caustic = false;
+
+        for _ in 0..max_depth {
+            let Some(hit) = scene.hit(&ray, T_MIN, T_MAX) else {
+                break;
+            };
+            let m = hit.material;
+            if caustic && m.diffuse_reflectance(&hit).max_element() > 0.0 {
+                stored.push(Photon { position: hit.point, direction: ray.direction, power });
+            }
+
+            // 按各分量系数的亮度随机选择一个分量继续追踪（俄罗斯轮盘），
+            // 选中漫反射或未选中任何分量时停止，漫反射路径由路径追踪负责
+            let scattered_rays = m.scatter(&ray, hit, &mut rand_util::LocalRng);
+            let total = scattered_rays.iter().map(|s| luminance(s.coefficient)).sum::<f32>().max(1.0);
+            let mut pick = rand_util::random::<f32>() * total;
+            let Some(scattered_ray) = scattered_rays.iter().find(|s| {
+                pick -= luminance(s.coefficient);
+                pick < 0.0
+            }) else {
+                break;
+            };
+            if scattered_ray.kind == ScatterKind::Diffuse {
+                break;
+            }
+            power *= scattered_ray.coefficient * total / luminance(scattered_ray.coefficient);
+            ray = scattered_ray.ray;
+            caustic = true;
+        }
+
+        stored
+    }
+
+    // 在包围盒最长的轴上按中位数划分，递归构建 kd-tree
+    fn build_node(photons: &mut [Photon], axes: &mut [u8]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let (min, max) = photons.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), photon| (min.min(photon.position), max.max(photon.position)),
+        );
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+        axes[mid] = axis as u8;
+
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        Self::build_node(left, left_axes);
+        Self::build_node(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    /// 光子数量
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// 估计 `point` 处来自 `normal` 一侧的焦散辐照度，即半径 `radius` 内光子能量之和除以圆盘面积，
+    /// 乘以 BRDF 即为出射辐亮度
+    pub fn irradiance(&self, point: Vec3, normal: Vec3, radius: f32) -> Vec3 {
+        let mut power = Vec3::ZERO;
+        self.gather(0, self.photons.len(), point, radius * radius, &mut |photon| {
+            if photon.direction.dot(normal) < 0.0 {
+                power += photon.power;
+            }
+        });
+        power / (PI * radius * radius)
+    }
+
+    // 遍历区间 [lo, hi) 对应子树中与 point 距离的平方小于 radius2 的光子
+    fn gather(&self, lo: usize, hi: usize, point: Vec3, radius2: f32, f: &mut impl FnMut(&Photon)) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid] as usize;
+        let distance = point[axis] - photon.position[axis];
+
+        if distance < 0.0 || distance * distance < radius2 {
+            self.gather(lo, mid, point, radius2, f);
+        }
+        if distance >= 0.0 || distance * distance < radius2 {
+            self.gather(mid + 1, hi, point, radius2, f);
+        }
+        if photon.position.distance_squared(point) < radius2 {
+            f(photon);
+        }
+    }
+}
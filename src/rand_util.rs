@@ -1,39 +1,249 @@
+use std::cell::RefCell;
 use glam::{Vec2, Vec3};
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
+thread_local! {
+    // 每个线程的随机数生成器，默认以系统熵初始化
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// 由当前线程的随机数生成器生成随机数，渲染中所有的随机数都来自这里，
+/// 因此可以用 `seed` 使结果可复现
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// 以 `seed` 重新设置当前线程的随机数生成器
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// 当前线程的随机数生成器的句柄，每次取数都来自 `random` 使用的同一个生成器，因此同样受 `seed` 控制。
+/// 渲染器向需要 `&mut impl Rng` 的函数（例如 `Material::scatter`）传入它；
+/// 外部工具也可以传入自己的生成器，使材质采样的结果与线程无关
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalRng;
+
+impl RngCore for LocalRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
 
 /// 生成 tent 滤波下的 [0, 1] 的随机数
 pub fn random_unit_tent() -> f32 {
-    let rand = rand::random::<f32>() * 2.0;
-    if rand < 1.0 {
-        rand.sqrt() / 2.0
+    tent(random::<f32>())
+}
+
+/// 将 [0, 1) 上的均匀分布映射为 tent 滤波下的 [0, 1] 分布
+pub fn tent(x: f32) -> f32 {
+    let x = x * 2.0;
+    if x < 1.0 {
+        x.sqrt() / 2.0
     } else {
-        1.0 - (2.0 - rand).sqrt() / 2.0
+        1.0 - (2.0 - x).sqrt() / 2.0
     }
 }
 
 /// 生成单位圆盘内的均匀采样
 pub fn random_in_unit_disk() -> Vec2 {
     loop {
-        let p = Vec2::new(rand::random::<f32>(), rand::random::<f32>());
+        let p = Vec2::new(
+            random::<f32>() * 2.0 - 1.0,
+            random::<f32>() * 2.0 - 1.0,
+        );
         if p.length_squared() < 1.0 {
             return p;
         }
     }
 }
 
+/// 使用同心映射将单位正方形内的点映射到单位圆盘内，保持分层结构
+pub fn square_to_disk(p: Vec2) -> Vec2 {
+    let p = p * 2.0 - Vec2::ONE;
+    if p == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    let (r, theta) = if p.x.abs() > p.y.abs() {
+        (p.x, std::f32::consts::FRAC_PI_4 * (p.y / p.x))
+    } else {
+        (p.y, std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (p.x / p.y))
+    };
+    r * Vec2::new(theta.cos(), theta.sin())
+}
+
+/// 二维分段常数分布：网格中每个单元被选中的概率与其权重成正比，单元内均匀分布，
+/// 用于按图像亮度采样，例如光圈遮罩与环境贴图
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    width: usize,
+    height: usize,
+    rows: Vec<f32>,    // 各行权重之和的前缀和
+    columns: Vec<f32>, // 每行内各单元权重的前缀和，按行排列
+}
+
+impl Distribution2D {
+    /// 由按行排列的 `width * height` 个非负权重构建，权重之和不为正数时返回 None
+    pub fn new(width: usize, height: usize, weights: &[f32]) -> Option<Self> {
+        assert_eq!(weights.len(), width * height);
+        let mut rows = Vec::with_capacity(height);
+        let mut columns = Vec::with_capacity(width * height);
+        // 使用 f64 累加，避免大图像的前缀和丢失精度
+        let mut total = 0.0f64;
+        for row in weights.chunks(width.max(1)) {
+            let mut sum = 0.0f64;
+            for &weight in row {
+                sum += weight.max(0.0) as f64;
+                columns.push(sum as f32);
+            }
+            total += sum;
+            rows.push(total as f32);
+        }
+        (total > 0.0 && total.is_finite()).then_some(Self { width, height, rows, columns })
+    }
+
+    /// 网格的宽度与高度
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// 将 [0, 1)² 内均匀分布的点映射为按权重分布的点，坐标属于 [0, 1)²，
+    /// x 沿行内方向，y 沿行序号增大的方向，保持采样点的分层结构；同时返回该点的概率密度（相对于面积）
+    pub fn sample(&self, u: Vec2) -> (Vec2, f32) {
+        let (row, y) = Self::sample_1d(&self.rows, u.y);
+        let row_prefix = &self.columns[row * self.width..(row + 1) * self.width];
+        let (column, x) = Self::sample_1d(row_prefix, u.x);
+        let point = Vec2::new(
+            (column as f32 + x) / self.width as f32,
+            (row as f32 + y) / self.height as f32,
+        );
+        (point, self.pdf_at(row, column))
+    }
+
+    /// 点 `p`（坐标属于 [0, 1]²）处的概率密度（相对于面积）
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        let column = ((p.x * self.width as f32) as usize).min(self.width - 1);
+        let row = ((p.y * self.height as f32) as usize).min(self.height - 1);
+        self.pdf_at(row, column)
+    }
+
+    fn pdf_at(&self, row: usize, column: usize) -> f32 {
+        let index = row * self.width + column;
+        let previous = if column == 0 { 0.0 } else { self.columns[index - 1] };
+        let total = self.rows[self.height - 1];
+        (self.columns[index] - previous) / total * (self.width * self.height) as f32
+    }
+
+    // 在前缀和 prefix 上按 u 选择一个单元，返回单元序号与 u 在该单元内的相对位置
+    fn sample_1d(prefix: &[f32], u: f32) -> (usize, f32) {
+        let total = prefix[prefix.len() - 1];
+        let pick = u.clamp(0.0, 1.0 - f32::EPSILON) * total;
+        let index = prefix.partition_point(|&sum| sum <= pick).min(prefix.len() - 1);
+        let start = if index == 0 { 0.0 } else { prefix[index - 1] };
+        let width = prefix[index] - start;
+        let offset = if width > 0.0 { ((pick - start) / width).clamp(0.0, 1.0 - f32::EPSILON) } else { 0.5 };
+        (index, offset)
+    }
+}
+
+/// Kensler 的相关多重抖动采样（Correlated Multi-Jittered Sampling），
+/// 返回一组 `count` 个样本中的第 `index` 个，位于 [0, 1) 的二维点，
+/// `pattern` 不同的两组样本之间互不相关
+pub fn cmj(index: u32, count: u32, pattern: u32) -> Vec2 {
+    let m = ((count as f32).sqrt() as u32).max(1);
+    let n = count.div_ceil(m);
+
+    let index = permute(index, count, pattern.wrapping_mul(0x51633e2d));
+    let sx = permute(index % m, m, pattern.wrapping_mul(0x68bc21eb));
+    let sy = permute(index / m, n, pattern.wrapping_mul(0x02e5be93));
+    let jx = hash_to_unit(index, pattern.wrapping_mul(0x967a889b));
+    let jy = hash_to_unit(index, pattern.wrapping_mul(0x368cc8b7));
+
+    Vec2::new(
+        (sx as f32 + (sy as f32 + jx) / n as f32) / m as f32,
+        (index as f32 + jy) / count as f32,
+    )
+}
+
+// 以 pattern 为种子生成 [0, length) 的一个伪随机排列，返回 index 在排列中的位置
+fn permute(mut index: u32, length: u32, pattern: u32) -> u32 {
+    let mut w = length.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        index ^= pattern;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= pattern >> 16;
+        index ^= (index & w) >> 4;
+        index ^= pattern >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= pattern >> 23;
+        index ^= (index & w) >> 1;
+        index = index.wrapping_mul(1 | pattern >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & w) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & w) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & w) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= w;
+        index ^= index >> 5;
+        if index < length {
+            break;
+        }
+    }
+    index.wrapping_add(pattern) % length
+}
+
+// 将整数哈希为 [0, 1) 上的浮点数
+fn hash_to_unit(mut index: u32, pattern: u32) -> f32 {
+    index ^= pattern;
+    index ^= index >> 17;
+    index ^= index >> 10;
+    index = index.wrapping_mul(0xb36534e5);
+    index ^= index >> 12;
+    index ^= index >> 21;
+    index = index.wrapping_mul(0x93fc4795);
+    index ^= 0xdf6e307f;
+    index ^= index >> 17;
+    index = index.wrapping_mul(1 | pattern >> 18);
+    index as f32 * (1.0 / 4294967808.0)
+}
+
 pub fn random_unit_element() -> Vec3 {
-    let x: f32 = rand::random::<f32>();
-    let y: f32 = rand::random::<f32>();
-    let z: f32 = rand::random::<f32>();
+    let x: f32 = random::<f32>();
+    let y: f32 = random::<f32>();
+    let z: f32 = random::<f32>();
     Vec3::new(x, y, z)
 }
 
 /// 生成随机单位向量
 pub fn random_unit_vector() -> Vec3 {
     loop {
-        let x: f32 = rand::random::<f32>() - 0.5;
-        let y: f32 = rand::random::<f32>() - 0.5;
-        let z: f32 = rand::random::<f32>() - 0.5;
+        let x: f32 = random::<f32>() - 0.5;
+        let y: f32 = random::<f32>() - 0.5;
+        let z: f32 = random::<f32>() - 0.5;
         let vector = Vec3 { x, y, z };
         if vector.length_squared() > f32::EPSILON {
             return vector.normalize();
@@ -43,9 +253,20 @@ pub fn random_unit_vector() -> Vec3 {
 
 /// 在给定半球内生成余弦加权分布的随机向量
 pub fn random_unit_vector_cosine(normal: Vec3) -> Vec3 {
+    random_unit_vector_cosine_pdf(normal).0
+}
+
+/// 在给定半球内生成余弦加权分布的随机向量，同时返回该方向的概率密度（立体角）cos(theta) / π，
+/// 用于与光源采样等其他采样方法组合
+pub fn random_unit_vector_cosine_pdf(normal: Vec3) -> (Vec3, f32) {
+    random_unit_vector_cosine_pdf_with(normal, &mut LocalRng)
+}
+
+/// 与 `random_unit_vector_cosine_pdf` 相同，随机数取自 `rng`
+pub fn random_unit_vector_cosine_pdf_with(normal: Vec3, rng: &mut impl Rng) -> (Vec3, f32) {
     // 随机生成二维点
-    let r1: f32 = rand::random::<f32>();
-    let r2: f32 = rand::random::<f32>();
+    let r1: f32 = rng.gen();
+    let r2: f32 = rng.gen();
 
     let r = r1.sqrt();
     let theta = 2.0 * std::f32::consts::PI * r2;
@@ -63,8 +284,6 @@ pub fn random_unit_vector_cosine(normal: Vec3) -> Vec3 {
     };
     let bitangent = normal.cross(tangent);
 
-    // 转换到世界坐标
-    tangent * x + bitangent * y + normal * z
+    // 转换到世界坐标，z 即为与法向夹角的余弦
+    (tangent * x + bitangent * y + normal * z, z / std::f32::consts::PI)
 }
-
-
@@ -1,15 +1,59 @@
 use glam::Vec3;
 
+/// 光线微分，即相邻像素（x、y 方向各一个）对应光线的起点与方向，用于估计像素在表面上的覆盖范围
+#[derive(Debug, Copy, Clone)]
+pub struct RayDifferentials {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3, // 方向单位向量
+    pub inv_direction: Vec3, // 方向向量各分量的倒数，用于加速包围盒求交
+    pub time: f32, // 光线所处的时刻，用于运动模糊
+    pub wavelength: Option<f32>, // 光谱渲染时光线携带的波长（纳米），为 None 时按 RGB 计算
+    pub differentials: Option<RayDifferentials>, // 光线微分，只有摄像机发出的光线带有
 }
 
 impl Ray {
+    /// 方向会被归一化，`direction` 为零向量时得到 NaN
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        let direction = direction.normalize();
-        Ray { origin, direction }
+        Self::new_unchecked(origin, direction.normalize())
+    }
+
+    /// 与 `new` 相同，但不归一化方向，调用方需保证 `direction` 已经是单位向量，
+    /// 用于散射、光源采样等方向由单位向量反射、折射或采样得到的热路径；debug 构建中会检查
+    pub fn new_unchecked(origin: Vec3, direction: Vec3) -> Self {
+        debug_assert!(direction.is_normalized(), "Ray direction {direction} is not normalized");
+        Ray { origin, direction, inv_direction: direction.recip(), time: 0.0, wavelength: None, differentials: None }
+    }
+
+    // 返回处于时刻 time 的同一条光线
+    pub fn with_time(self, time: f32) -> Self {
+        Ray { time, ..self }
+    }
+
+    // 返回携带波长 wavelength 的同一条光线
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Self {
+        Ray { wavelength, ..self }
+    }
+
+    // 返回带有光线微分的同一条光线
+    pub fn with_differentials(self, differentials: RayDifferentials) -> Self {
+        Ray { differentials: Some(differentials), ..self }
+    }
+
+    // 相邻像素的光线与本光线方向的夹角（弧度），取 x、y 两个方向中较大者，即像素锥体的张角；
+    // 没有光线微分时返回 None
+    pub fn spread(&self) -> Option<f32> {
+        self.differentials.map(|differentials| {
+            (differentials.rx_direction - self.direction).length()
+                .max((differentials.ry_direction - self.direction).length())
+        })
     }
 
     // 获取光线在时刻 t 到达的位置
@@ -17,3 +61,75 @@ impl Ray {
         self.origin + t * self.direction
     }
 }
+
+/// 一组起点相同的光线，方向按分量分别存储（SoA），用于对相邻像素的主光线成组遍历 BVH，
+/// 一次包围盒检查可以同时处理整组光线
+#[derive(Debug, Clone)]
+pub struct RayPacket {
+    pub origin: Vec3,
+    pub direction: [Vec<f32>; 3], // 各光线方向单位向量的 x、y、z 分量
+    pub inv_direction: [Vec<f32>; 3], // 各光线方向各分量的倒数
+    pub inv_direction_bounds: Option<(Vec3, Vec3)>, // 所有光线方向倒数各分量的最小值与最大值，某个轴上方向符号不一致时为 None
+    pub time: f32, // 所有光线所处的时刻
+}
+
+impl RayPacket {
+    /// 由共同的起点与各光线的方向构建，方向会被归一化
+    pub fn new(origin: Vec3, directions: impl IntoIterator<Item = Vec3>, time: f32) -> Self {
+        let mut direction: [Vec<f32>; 3] = Default::default();
+        let mut inv_direction: [Vec<f32>; 3] = Default::default();
+        for d in directions {
+            let d = d.normalize();
+            let inv = d.recip();
+            for axis in 0..3 {
+                direction[axis].push(d[axis]);
+                inv_direction[axis].push(inv[axis]);
+            }
+        }
+        // 每个轴上所有方向同号时，方向倒数的范围可以用来对整组光线一次剔除包围盒
+        let bounds = |axis: usize| {
+            let values = &inv_direction[axis];
+            let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            (min.is_finite() && max.is_finite() && min.signum() == max.signum()).then_some((min, max))
+        };
+        let inv_direction_bounds = match (bounds(0), bounds(1), bounds(2)) {
+            (Some(x), Some(y), Some(z)) => Some((Vec3::new(x.0, y.0, z.0), Vec3::new(x.1, y.1, z.1))),
+            _ => None,
+        };
+        Self { origin, direction, inv_direction, inv_direction_bounds, time }
+    }
+
+    /// 整组光线是否都不与包围盒 [min, max] 相交，用区间运算一次判断，不逐条检查；
+    /// 返回 false 时仍可能都不相交。方向倒数的范围不可用时总是返回 false
+    pub fn misses_box(&self, min: Vec3, max: Vec3) -> bool {
+        let Some((inv_min, inv_max)) = self.inv_direction_bounds else {
+            return false;
+        };
+        let (mut near, mut far) = (f32::NEG_INFINITY, f32::INFINITY);
+        for axis in 0..3 {
+            // 方向为正时先穿过 min 所在的平面，为负时先穿过 max 所在的平面
+            let (entry, exit) = if inv_min[axis] > 0.0 { (min[axis], max[axis]) } else { (max[axis], min[axis]) };
+            let (entry, exit) = (entry - self.origin[axis], exit - self.origin[axis]);
+            // 各光线进入该 slab 的时间的下界与离开的时间的上界
+            near = near.max((entry * inv_min[axis]).min(entry * inv_max[axis]));
+            far = far.min((exit * inv_min[axis]).max(exit * inv_max[axis]));
+        }
+        near > far || far <= 0.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.direction[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 第 index 条光线
+    pub fn ray(&self, index: usize) -> Ray {
+        let direction = Vec3::new(self.direction[0][index], self.direction[1][index], self.direction[2][index]);
+        let inv_direction = Vec3::new(self.inv_direction[0][index], self.inv_direction[1][index], self.inv_direction[2][index]);
+        Ray { origin: self.origin, direction, inv_direction, time: self.time, wavelength: None, differentials: None }
+    }
+}
@@ -1,17 +1,325 @@
+use std::fmt;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Seek, Write};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rand::Rng;
 use rayon::prelude::*;
-use glam::Vec3;
-use image::{Rgb, RgbImage};
-use crate::scene::Scene;
-use crate::camera::Camera;
-use crate::rand_util::random_unit_tent;
+use rayon::{ThreadPool, ThreadPoolBuildError};
+use glam::{Vec2, Vec3};
+use image::{ImageBuffer, ImageError, ImageFormat, ImageResult, Rgb, RgbImage, RgbaImage};
+use image::error::{ParameterError, ParameterErrorKind};
+use crate::accumulation::Accumulation;
+use crate::background::{Background, EnvironmentCone, EnvironmentDistribution};
+use crate::bdpt;
+use crate::color::{ColorLut, ColorSpace};
+use crate::denoise::{self, DenoiseOptions};
+use crate::irradiance::{IrradianceCache, IrradianceCacheOptions};
+use crate::material::{self, Material, Medium, ScatterKind, ScatteredRay};
+use crate::photon::{CausticsOptions, PhotonMap};
+use crate::scene::{HitRecord, Scene};
+use crate::camera::{Camera, Tile};
+use crate::rand_util;
 use crate::ray::Ray;
+use crate::spectrum;
+use crate::stats::{self, IntersectionStats};
 
-const T_MIN: f32 = 0.001;
-const T_MAX: f32 = 100000.0;
+pub(crate) const T_MIN: f32 = 0.001;
+pub(crate) const T_MAX: f32 = 100000.0;
+// 渲染前检查场景时每个方向上发射的主光线数量
+const CHECK_GRID: u32 = 32;
+// 主光线击中物体的比例低于该值时认为摄像机没有对准场景
+const CHECK_MIN_HIT_RATE: f32 = 0.01;
+// 击中背面的比例高于该值时认为法向可能朝内
+const CHECK_MAX_BACK_FACE_RATE: f32 = 0.5;
+// 工作单元覆盖的图块边长（像素）
+const TILE_SIZE: u32 = 16;
+// 期望的工作单元数量，远多于线程数，使各线程的负载均衡
+const TARGET_WORK_UNITS: u32 = 1024;
+// 降噪时生成反照率与法向图像使用的最大每像素采样数，只需要平滑物体边缘
+const AOV_SAMPLES: u32 = 16;
+// 生成反照率与法向图像时沿镜面反射、透射方向最多追踪的次数
+const AOV_MAX_DEPTH: u32 = 8;
+// 绘制线框时每个像素在每个方向上的子样本数，用于估计线条对像素的覆盖比例
+const WIREFRAME_SUBSAMPLES: u32 = 4;
+// 锥体追踪时每条主光线至多着色的细三角面层数
+const CONE_MAX_LAYERS: usize = 4;
 
+/// 像素位置与透镜位置的采样方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sampler {
+    /// 各维度独立随机采样
+    #[default]
+    Random,
+    /// Kensler 的相关多重抖动采样，像素位置与透镜位置分别分层，
+    /// 同一像素的样本之间分布更均匀，能明显降低景深的噪点
+    CorrelatedMultiJittered,
+}
+
+/// 计算主光线颜色的积分器，实现该 trait 即可接入其他光线传输算法
+pub trait Integrator: fmt::Debug {
+    /// 主光线 `ray` 带来的颜色（线性 RGB），以及主光线是否击中物体，用于 alpha 通道
+    fn radiance(&self, ray: &Ray, context: &Context) -> (Vec3, bool);
+}
+
+/// 分支路径追踪，每次散射将光线分为漫反射、镜面反射、透射若干条光线
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathTracing;
+
+/// 双向路径追踪，适用于光源被部分遮挡或存在焦散等光路难以从摄像机一侧找到的场景，
+/// 不使用焦散光子图与辐照度缓存
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bidirectional;
+
+/// 由一个像素的各个样本估计像素颜色的方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Estimator {
+    /// 所有样本的平均值，无偏，但单个极亮的样本（萤火虫）会明显影响整个像素
+    #[default]
+    Mean,
+    /// 均值中位数：将样本分为 `groups` 组分别求平均，取亮度处于中位数的一组，
+    /// 不受少数离群样本影响，也不会像硬截断那样损失高光的亮度，
+    /// 代价是分布偏斜时略微偏暗；每组样本数越多偏差越小
+    MedianOfMeans { groups: u32 },
+}
+
+impl Estimator {
+    // 每个像素的样本分成的组数
+    fn groups(self, samples_per_pixel: u32) -> usize {
+        match self {
+            Estimator::Mean => 1,
+            Estimator::MedianOfMeans { groups } => groups.clamp(1, samples_per_pixel.max(1)) as usize,
+        }
+    }
+
+    // 由各组样本的平均值得到像素颜色
+    fn estimate(self, mut group_means: Vec<Vec3>) -> Vec3 {
+        let middle = group_means.len() / 2;
+        let (_, median, _) = group_means.select_nth_unstable_by(middle, |a, b| {
+            material::luminance(*a).total_cmp(&material::luminance(*b))
+        });
+        *median
+    }
+}
+
+/// 线框叠加的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WireframeOptions {
+    pub color: Vec3,    // 线条颜色，线性 RGB
+    pub thickness: f32, // 线条宽度（像素），按交点处一个像素对应的宽度换算，远近物体的线条粗细一致
+}
+
+impl Default for WireframeOptions {
+    fn default() -> Self {
+        Self { color: Vec3::ONE, thickness: 1.0 }
+    }
+}
+
+/// 渲染选项，默认值与 `render` 的行为一致
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub sampler: Sampler,
+    pub integrator: Arc<dyn Integrator + Sync + Send>, // 计算主光线颜色的积分器，默认为 PathTracing
+    pub background: Background, // 未击中任何物体的光线返回的颜色
+    pub environment_radiance: Vec3, // 散射后未击中任何物体的光线额外得到的环境辐亮度，近似天空补光，摄像机直接看到的背景不变
+    pub environment_sampling: Option<EnvironmentCone>, // 启用时在漫反射处与光源采样一起对该范围内的背景（含环境辐亮度）直接采样，范围内的部分不再由散射光线计算
+    pub caustics: Option<CausticsOptions>, // 启用时先发射光子构建焦散光子图，在漫反射表面上估计焦散
+    pub irradiance_cache: Option<IrradianceCacheOptions>, // 启用时首次漫反射处的间接光由辐照度缓存插值得到
+    pub light_samples: u32, // 每次漫反射时对光源采样的阴影光线数量，为 0 时不对光源采样，只靠散射光线击中光源
+    pub spectral: bool, // 光谱渲染，每个样本随机选择一个波长追踪，可以得到色散效果，只对路径追踪生效
+    pub threads: usize, // 渲染使用的线程数，为 0 时使用 rayon 的全局线程池，即所有核心
+    pub estimator: Estimator, // 由像素的各个样本估计像素颜色的方法
+    pub denoise: Option<DenoiseOptions>, // 启用时渲染后由反照率与法向引导降噪，用于低采样数的预览
+    pub branch_depth: u32, // 路径追踪散射次数达到该值后，每次只随机追踪一个分量，限制光线数量的指数增长，默认 u32::MAX 为完全分支
+    pub min_throughput: f32, // 路径追踪中散射系数沿路径的乘积的最大分量低于该值时不再追踪该光线，为 0 时只受 max_depth 限制
+    pub ambient_occlusion: f32, // 环境光遮蔽半径，环境光乘以交点处该距离内半球未被遮挡的比例，为 0 时不计算遮蔽
+    pub seed: Option<u64>, // 随机数种子，设置时每个工作单元的随机数由种子、图块与样本批次决定，结果可复现
+    pub color_space: ColorSpace, // 输出 8 位图像时的颜色空间与传递函数，不影响 render_hdr 的线性输出
+    pub color_lut: Option<ColorLut>, // 输出 8 位图像时按颜色空间编码后再应用的调色查找表（色调曲线或 .cube），不影响 render_hdr 的线性输出
+    pub energy_check: bool, // 调试用，检查每个交点处散射系数之和是否超过 1，渲染结束后输出各材质的最大值
+    pub wireframe: Option<WireframeOptions>, // 启用时在渲染结果上叠加摄像机直接看到的三角面的边，用于检查网格的细分密度
+    pub time_budget: Option<Duration>, // 采样的时间预算，超过后不再开始新的样本批次，返回已完成样本的均值，各区域的采样数可能不同
+    pub regularize: Option<f32>, // 路径正则化，第一次漫反射之后，镜面反射与透射的感知粗糙度至少为该值，以少量偏差换取焦散处更少的萤火虫，只对路径追踪生效
+    pub cone_tracing: bool, // 主光线的锥体追踪，比像素细的三角面按覆盖像素的比例混合，低采样数时细线条更平滑，只对路径追踪生效
+    pub clay: Option<f32>, // 白模渲染，启用时除发光材质外的所有表面都换成该反照率的哑光材质（见 Material::clay），用于检查造型与光照
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            sampler: Sampler::default(),
+            integrator: Arc::new(PathTracing),
+            background: Background::default(),
+            environment_radiance: Vec3::ZERO,
+            environment_sampling: None,
+            caustics: None,
+            irradiance_cache: None,
+            light_samples: 0,
+            spectral: false,
+            threads: 0,
+            estimator: Estimator::default(),
+            denoise: None,
+            branch_depth: u32::MAX,
+            min_throughput: 0.0,
+            ambient_occlusion: 0.0,
+            seed: None,
+            color_space: ColorSpace::default(),
+            color_lut: None,
+            energy_check: false,
+            wireframe: None,
+            time_budget: None,
+            regularize: None,
+            cone_tracing: false,
+            clay: None,
+        }
+    }
+}
+
+/// 渲染一帧时所有光线共享的数据
+pub struct Context<'a> {
+    scene: &'a Scene,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &'a RenderOptions,
+    caustics: Option<PhotonMap>,
+    irradiance_cache: Option<IrradianceCache>,
+    energy: Option<EnergyCheck>,
+    stats: Mutex<IntersectionStats>, // 各工作单元合并后的求交统计，只有启用 stats feature 时才会计数
+    clay: Option<Material>, // 白模渲染时替换表面材质的哑光材质
+    environment: Option<EnvironmentDistribution>, // 对环境贴图采样时按亮度选择方向的分布
+}
+
+impl Context<'_> {
+    /// 正在渲染的场景
+    pub fn scene(&self) -> &Scene {
+        self.scene
+    }
+
+    /// 最大散射次数
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// 渲染选项
+    pub fn options(&self) -> &RenderOptions {
+        self.options
+    }
+}
+
+// 能量检查的结果，以材质的地址为键记录各材质在所有交点处散射系数之和的最大值，
+// 系数之和按 RGB 各分量分别计算，取最大的分量
+#[derive(Default)]
+struct EnergyCheck {
+    worst: Mutex<HashMap<usize, f32>>,
+}
+
+impl EnergyCheck {
+    fn record(&self, material: &Material, scattered_rays: &[ScatteredRay]) {
+        let total = scattered_rays.iter().map(|s| s.coefficient).sum::<Vec3>().max_element();
+        let mut worst = self.worst.lock().unwrap();
+        let previous = worst.entry(material as *const Material as usize).or_insert(0.0);
+        // 每种材质只在第一次超过 1 时警告
+        if total > 1.0 && *previous <= 1.0 {
+            eprintln!("Warning: material at {material:p} is not energy conserving, total scattering coefficient {total:.3}");
+        }
+        *previous = previous.max(total);
+    }
+
+    // 按最大值从大到小输出，材质库中的材质显示名字，其余材质显示地址
+    fn report(&self, scene: &Scene) {
+        let worst = self.worst.lock().unwrap();
+        let mut entries = worst.iter().map(|(&address, &total)| {
+            let name = scene.materials.iter()
+                .find(|(_, material)| Arc::as_ptr(material) as usize == address)
+                .map_or_else(|| format!("{:p}", address as *const Material), |(name, _)| name.clone());
+            (name, total)
+        }).collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.1.total_cmp(&a.1));
+        eprintln!("Energy check, worst-case total scattering coefficient per material:");
+        for (name, total) in entries {
+            let mark = if total > 1.0 { " (exceeds 1)" } else { "" };
+            eprintln!("  {name}: {total:.3}{mark}");
+        }
+    }
+}
+
+// 路径此前经过的散射分量，用于避免散射光线与光源采样、焦散光子图重复计算同一条光路
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PathState {
+    Direct,  // 尚未发生漫反射
+    Diffuse, // 最近一次散射为漫反射，启用光源采样时击中光源的部分已由光源采样计算
+    Caustic, // 漫反射之后又经过了镜面反射或透射，击中光源的部分由光子图负责
+}
+
+impl PathState {
+    fn next(self, kind: ScatterKind) -> Self {
+        match (self, kind) {
+            (_, ScatterKind::Diffuse) => PathState::Diffuse,
+            (PathState::Direct, _) => PathState::Direct,
+            _ => PathState::Caustic,
+        }
+    }
+}
+
+/// 渲染前检查场景时发现的问题，这些问题通常导致渲染出全黑的图像
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneWarning {
+    /// 几乎所有主光线都没有击中物体，`hit_rate` 为击中物体的比例
+    NothingVisible { hit_rate: f32 },
+    /// 击中的大部分是物体的背面，`back_face_rate` 为击中背面的比例
+    MostlyBackFaces { back_face_rate: f32 },
+}
+
+impl fmt::Display for SceneWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneWarning::NothingVisible { hit_rate } => write!(
+                f,
+                "only {:.1}% of camera rays hit the scene; check that the camera looks at the objects \
+                 (Camera::frame can fit the camera to the scene bounds)",
+                hit_rate * 100.0,
+            ),
+            SceneWarning::MostlyBackFaces { back_face_rate } => write!(
+                f,
+                "{:.1}% of camera rays hit back faces; the normals or face winding may point inward, \
+                 or the camera may be inside a closed object",
+                back_face_rate * 100.0,
+            ),
+        }
+    }
+}
+
+/// 渲染前从摄像机发射稀疏的网格状主光线，检查常见的场景问题，没有问题时返回空列表，
+/// 场景需要已经构建好 BVH。检查只需要约一千条光线，远快于渲染本身
+pub fn check_scene(scene: &Scene, camera: &Camera) -> Vec<SceneWarning> {
+    let tile = Tile { x: 0, y: 0, width: CHECK_GRID, height: CHECK_GRID, image_width: CHECK_GRID, image_height: CHECK_GRID };
+    let (mut hits, mut back_faces) = (0, 0);
+    for hit in scene.hit_packet(&camera.get_rays(tile)).into_iter().flatten() {
+        hits += 1;
+        if !hit.front_face {
+            back_faces += 1;
+        }
+    }
+
+    let mut warnings = vec![];
+    let hit_rate = hits as f32 / (CHECK_GRID * CHECK_GRID) as f32;
+    if hit_rate < CHECK_MIN_HIT_RATE {
+        warnings.push(SceneWarning::NothingVisible { hit_rate });
+    }
+    if hits > 0 {
+        let back_face_rate = back_faces as f32 / hits as f32;
+        if back_face_rate > CHECK_MAX_BACK_FACE_RATE {
+            warnings.push(SceneWarning::MostlyBackFaces { back_face_rate });
+        }
+    }
+    warnings
+}
+
+/// 渲染图像，返回 8 位 RGB 数据
+///
+/// `max_depth` 为每条路径最多发生的散射次数，为 0 时只计算首次碰撞处的自发光与环境光
 pub fn render(
     scene: Arc<Scene>,
     camera: Arc<Camera>,
@@ -20,70 +328,1264 @@ pub fn render(
     max_depth: u32,
     samples_per_pixel: u32,
 ) -> Vec<u8> {
-    let image_data_raw = vec![0.0; (image_width * image_height * 3) as usize];
-    let image_data_raw = Arc::new(Mutex::new(image_data_raw));
-
-    (0..image_height).rev().collect::<Vec<_>>().par_iter().map(|j| {
-        let j = *j;
-        let image_data_raw = image_data_raw.clone();
-        let scene = scene.clone();
-        let camera = camera.clone();
-        for i in 0..image_width {
-            let mut color = Vec3::ZERO;
-            for _ in 0..samples_per_pixel {
-                // 在一个像素内进行采样
-                let shift_u = random_unit_tent();
-                let shift_v = random_unit_tent();
-                let u = (i as f32 + shift_u) / image_width as f32;
-                let v = (j as f32 + shift_v) / image_height as f32;
-                let ray = camera.get_ray(u, v);
-                color += ray_color(&ray, &*scene, 0, max_depth);
+    render_with_options(
+        scene,
+        camera,
+        image_width,
+        image_height,
+        max_depth,
+        samples_per_pixel,
+        &RenderOptions::default(),
+    )
+}
+
+/// 使用指定的渲染选项渲染图像，返回按 `options.color_space` 编码、再应用 `options.color_lut` 的 8 位 RGB 数据
+pub fn render_with_options(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> Vec<u8> {
+    let image_data = render_hdr(
+        scene,
+        camera,
+        image_width,
+        image_height,
+        max_depth,
+        samples_per_pixel,
+        options,
+    );
+    options.color_space.encode_8bit_graded(&image_data, options.color_lut.as_ref())
+}
+
+/// 渲染带 alpha 通道的图像，返回 8 位 RGBA 数据，用于叠加到其他图像上：
+/// alpha 为像素中主光线击中物体的样本比例，未击中任何物体的像素完全透明，物体边缘为介于 0 与 1 之间的值。
+/// RGB 为击中物体的样本的颜色均值，不含背景，也未预乘 alpha，透明像素为黑色；背景仍然照亮场景。
+/// RGB 由样本的均值得到，`options.estimator` 不生效
+pub fn render_rgba(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> Vec<u8> {
+    let output = in_thread_pool(options.threads, || {
+        let mut output = render_pass(
+            scene.clone(),
+            camera.clone(),
+            image_width,
+            image_height,
+            max_depth,
+            samples_per_pixel,
+            options,
+            0,
+            None,
+        );
+        output.covered_color = post_process(
+            output.covered_color, &scene, &camera, image_width, image_height, samples_per_pixel, options,
+        );
+        output
+    });
+    options.color_space.encode_8bit_graded(&output.covered_color, options.color_lut.as_ref()).chunks_exact(3).zip(output.coverage)
+        .flat_map(|(rgb, alpha)| [rgb[0], rgb[1], rgb[2], (alpha * 255.0).round() as u8])
+        .collect()
+}
+
+/// 预览的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviewOptions {
+    pub scale: f32, // 预览图像的分辨率与目标分辨率之比，在 (0, 1] 内
+    pub samples_per_pixel: u32, // 预览的每像素采样数
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self { scale: 0.25, samples_per_pixel: 4 }
+    }
+}
+
+/// 先以较低的分辨率与采样数快速渲染预览，放大到目标分辨率后交给 `on_preview`（例如显示在窗口中），
+/// 再渲染完整的图像并返回 8 位 RGB 数据；两次渲染共用同一个场景与 BVH
+#[allow(clippy::too_many_arguments)]
+pub fn render_with_preview(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+    preview: &PreviewOptions,
+    on_preview: impl FnOnce(&[u8]),
+) -> Vec<u8> {
+    assert!(
+        preview.scale > 0.0 && preview.scale <= 1.0,
+        "Preview scale must be in (0, 1], got {}",
+        preview.scale
+    );
+    let preview_width = ((image_width as f32 * preview.scale).round() as u32).max(1);
+    let preview_height = ((image_height as f32 * preview.scale).round() as u32).max(1);
+    let preview_data = render_with_options(
+        scene.clone(),
+        camera.clone(),
+        preview_width,
+        preview_height,
+        max_depth,
+        preview.samples_per_pixel,
+        options,
+    );
+    on_preview(&upscale(&preview_data, preview_width, preview_height, image_width, image_height));
+
+    render_with_options(scene, camera, image_width, image_height, max_depth, samples_per_pixel, options)
+}
+
+// 最近邻插值放大 8 位 RGB 图像
+fn upscale(image_data: &[u8], width: u32, height: u32, new_width: u32, new_height: u32) -> Vec<u8> {
+    (0..new_height).flat_map(|y| (0..new_width).map(move |x| (x, y))).flat_map(|(x, y)| {
+        let source_x = (x * width / new_width).min(width - 1);
+        let source_y = (y * height / new_height).min(height - 1);
+        let index = ((source_y * width + source_x) * 3) as usize;
+        [image_data[index], image_data[index + 1], image_data[index + 2]]
+    }).collect()
+}
+
+/// 从多个摄像机渲染同一个场景，例如立体像对或多角度的展示图，返回每个摄像机的 8 位 RGB 数据，
+/// 顺序与 `cameras` 相同。场景需要已经构建好 BVH，所有视角共用同一个 BVH
+#[allow(clippy::too_many_arguments)]
+pub fn render_views(
+    scene: Arc<Scene>,
+    cameras: &[Arc<Camera>],
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> Vec<Vec<u8>> {
+    cameras.iter().map(|camera| {
+        render_with_options(
+            scene.clone(),
+            camera.clone(),
+            image_width,
+            image_height,
+            max_depth,
+            samples_per_pixel,
+            options,
+        )
+    }).collect()
+}
+
+/// 渲染图像，返回未经截断的线性浮点 RGB 数据
+pub fn render_hdr(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> Vec<f32> {
+    render_hdr_with_error(scene, camera, image_width, image_height, max_depth, samples_per_pixel, options).0
+}
+
+/// 与 `render_hdr` 相同，另外返回每个像素亮度均值的标准误差，每个像素一个值，按行优先顺序排列，
+/// 在累加样本时同时累加亮度的平方得到，几乎没有额外开销。标准误差大的区域噪点多，
+/// 可以用 `heatmap` 转换为热度图查看；它衡量的是降噪前样本均值的误差
+pub fn render_hdr_with_error(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> (Vec<f32>, Vec<f32>) {
+    let output = render_hdr_tiles(scene, camera, image_width, image_height, max_depth, samples_per_pixel, options, None);
+    (output.image, output.standard_error)
+}
+
+/// 与 `render_hdr` 相同，另外返回追踪样本时的求交统计，不包括构建焦散光子图、降噪与叠加线框中的求交。
+/// 只有启用 `stats` feature 编译时才会计数（每次渲染结束时还会在标准错误输出摘要），否则统计全为 0
+pub fn render_hdr_with_stats(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> (Vec<f32>, IntersectionStats) {
+    let output = render_hdr_tiles(scene, camera, image_width, image_height, max_depth, samples_per_pixel, options, None);
+    (output.image, output.stats)
+}
+
+/// 与 `render_hdr` 相同，另外返回每个像素实际完成的采样数，每个像素一个值，按行优先顺序排列。
+/// 设置 `options.time_budget` 时渲染分轮进行：第一轮每像素一个样本，之后每轮的样本数翻倍，
+/// 超过预算后不再开始新的轮次，正在进行的一轮中尚未开始的图块也不再采样，
+/// 因此同一个 16x16 的图块内采样数相同，不同图块之间可能相差一轮。
+/// 预算从开始采样时计时，不包括之前构建焦散光子图与之后降噪、叠加线框的时间；结果与线程调度有关，不可复现
+pub fn render_hdr_with_samples(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> (Vec<f32>, Vec<u32>) {
+    let output = render_hdr_tiles(scene, camera, image_width, image_height, max_depth, samples_per_pixel, options, None);
+    (output.image, output.samples)
+}
+
+/// 与 `render_with_options` 相同，另外在每个图块的所有样本完成时调用
+/// `on_tile(x0, y0, 宽, 高, 像素)`，`(x0, y0)` 为图块左上角在图像中的像素坐标，
+/// 像素为按 `options.color_space` 编码（并应用 `options.color_lut`）、截断到 [0, 1] 的 RGB 浮点数据，按行优先顺序从上到下排列。
+/// 回调在完成该图块的渲染线程中调用，可能同时被多个线程调用，需要自行同步，例如发送到 channel；
+/// 回调得到的是降噪与叠加线框之前的结果，用于界面中逐块显示渲染进度；
+/// 设置 `options.time_budget` 时每一轮结束后都会以该图块目前所有样本的结果再次调用
+#[allow(clippy::too_many_arguments)]
+pub fn render_with_tile_callback(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+    on_tile: impl Fn(u32, u32, u32, u32, &[f32]) + Sync,
+) -> Vec<u8> {
+    let output = render_hdr_tiles(
+        scene,
+        camera,
+        image_width,
+        image_height,
+        max_depth,
+        samples_per_pixel,
+        options,
+        Some(&on_tile),
+    );
+    options.color_space.encode_8bit_graded(&output.image, options.color_lut.as_ref())
+}
+
+// 每个图块完成后调用的回调，参数为图块左上角的坐标、宽、高与编码后的像素
+type TileCallback<'a> = &'a (dyn Fn(u32, u32, u32, u32, &[f32]) + Sync);
+
+#[allow(clippy::too_many_arguments)]
+fn render_hdr_tiles(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+    on_tile: Option<TileCallback>,
+) -> PassOutput {
+    in_thread_pool(options.threads, || {
+        let mut output = render_pass(
+            scene.clone(),
+            camera.clone(),
+            image_width,
+            image_height,
+            max_depth,
+            samples_per_pixel,
+            options,
+            0,
+            on_tile,
+        );
+        output.image = post_process(output.image, &scene, &camera, image_width, image_height, samples_per_pixel, options);
+        output
+    })
+}
+
+// 按渲染选项对图像进行降噪与叠加线框
+fn post_process(
+    image_data: Vec<f32>,
+    scene: &Scene,
+    camera: &Camera,
+    image_width: u32,
+    image_height: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+) -> Vec<f32> {
+    let mut image_data = match options.denoise {
+        Some(denoise_options) => {
+            let aov_samples = samples_per_pixel.clamp(1, AOV_SAMPLES);
+            let clay = options.clay.map(Material::clay);
+            let (albedo, normal) = render_aovs_with(scene, camera, image_width, image_height, aov_samples, clay.as_ref());
+            denoise::denoise(&image_data, &albedo, &normal, image_width, image_height, &denoise_options)
+        }
+        None => image_data,
+    };
+    if let Some(wireframe) = &options.wireframe {
+        overlay_wireframe(&mut image_data, scene, camera, image_width, image_height, wireframe);
+    }
+    image_data
+}
+
+// 在图像上叠加线框：每个像素内按网格发射针孔光线，击中点到三角面边的距离小于半个线宽时计为线条，
+// 按线条覆盖的子样本比例混合线条颜色；一个像素在交点处对应的宽度由视口高度与焦距按距离换算
+fn overlay_wireframe(
+    image_data: &mut [f32],
+    scene: &Scene,
+    camera: &Camera,
+    image_width: u32,
+    image_height: u32,
+    options: &WireframeOptions,
+) {
+    let pixel_angle = camera.vertical.length() / (camera.focal_length * image_height as f32);
+    let subsamples = WIREFRAME_SUBSAMPLES;
+    image_data.par_chunks_exact_mut(3).enumerate().for_each(|(index, pixel)| {
+        let (i, j) = (index as u32 % image_width, image_height - 1 - index as u32 / image_width);
+        let mut covered = 0;
+        for sample in 0..subsamples * subsamples {
+            let u = (i as f32 + ((sample % subsamples) as f32 + 0.5) / subsamples as f32) / image_width as f32;
+            let v = (j as f32 + ((sample / subsamples) as f32 + 0.5) / subsamples as f32) / image_height as f32;
+            let ray = camera.get_ray_with_lens(u, v, Vec2::ZERO);
+            if let Some(hit) = scene.hit(&ray, T_MIN, T_MAX) {
+                if hit.edge_distance < 0.5 * options.thickness * pixel_angle * hit.t {
+                    covered += 1;
+                }
+            }
+        }
+        let coverage = covered as f32 / (subsamples * subsamples) as f32;
+        let color = Vec3::from_slice(pixel).lerp(options.color, coverage);
+        pixel.copy_from_slice(&color.to_array());
+    });
+}
+
+/// 渲染反照率（漫反射颜色）与法向图像，作为 `denoise::denoise` 的引导，
+/// 排列与 `render_hdr` 的输出相同，未击中任何物体的像素为 0
+///
+/// 光线击中以镜面反射或透射为主的材质（例如镜面、玻璃）时沿该方向继续追踪，
+/// 记录之后首个漫反射表面的反照率与法向，使降噪保持反射与折射出的物体边缘
+pub fn render_aovs(
+    scene: &Scene,
+    camera: &Camera,
+    image_width: u32,
+    image_height: u32,
+    samples_per_pixel: u32,
+) -> (Vec<f32>, Vec<f32>) {
+    render_aovs_with(scene, camera, image_width, image_height, samples_per_pixel, None)
+}
+
+// 与 render_aovs 相同，白模渲染时按白模材质记录反照率
+fn render_aovs_with(
+    scene: &Scene,
+    camera: &Camera,
+    image_width: u32,
+    image_height: u32,
+    samples_per_pixel: u32,
+    clay: Option<&Material>,
+) -> (Vec<f32>, Vec<f32>) {
+    let pixels = (0..image_width * image_height).into_par_iter().map(|index| {
+        let (i, j) = (index % image_width, image_height - 1 - index / image_width);
+        let (mut albedo, mut normal) = (Vec3::ZERO, Vec3::ZERO);
+        for _ in 0..samples_per_pixel {
+            let u = (i as f32 + rand_util::random_unit_tent()) / image_width as f32;
+            let v = (j as f32 + rand_util::random_unit_tent()) / image_height as f32;
+            let mut ray = camera.get_ray(u, v);
+            for _ in 0..AOV_MAX_DEPTH {
+                let Some(hit) = scene.hit(&ray, T_MIN, T_MAX) else {
+                    break;
+                };
+                let hit = with_clay(hit, clay);
+                let m = hit.material;
+                let scattered_rays = m.scatter(&ray, hit, &mut rand_util::LocalRng);
+                let specular = scattered_rays.iter()
+                    .filter(|s| s.kind != ScatterKind::Diffuse)
+                    .max_by(|a, b| material::luminance(a.coefficient).total_cmp(&material::luminance(b.coefficient)));
+                match specular {
+                    Some(specular) if material::luminance(specular.coefficient)
+                        > material::luminance(m.diffuse_reflectance(&hit)) => ray = specular.ray,
+                    _ => {
+                        albedo += m.diffuse_at(hit.uv) * m.solid_color(hit.point) * hit.color;
+                        normal += hit.normal;
+                        break;
+                    }
+                }
             }
-            color /= samples_per_pixel as f32;
-            color = color.clamp(Vec3::ZERO, Vec3::ONE);
-            let mut image_data_raw = image_data_raw.lock().unwrap();
-            image_data_raw[((i + (image_height - 1 - j) * image_width) * 3) as usize] = color.x; // R
-            image_data_raw[((i + (image_height - 1 - j) * image_width) * 3 + 1) as usize] = color.y; // G
-            image_data_raw[((i + (image_height - 1 - j) * image_width) * 3 + 2) as usize] = color.z; // B
-        }
-    }).collect::<()>();
-
-    let image_data_raw = image_data_raw.lock().unwrap();
-    (&*image_data_raw).iter().map(|x| { (x * 255.99) as u8 }).collect::<Vec<_>>()
-}
-
-/// 光线颜色计算
-fn ray_color(ray: &Ray, scene: &Scene, depth: u32, max_depth: u32) -> Vec3 {
-    if let Some(hit) = scene.hit(ray, T_MIN, T_MAX) {
-        let m = hit.material;
-        let mut color = m.ambient_color() + m.emissive_color();
-        // 如果弹射次数大于设定的次数，就不再弹射了
-        if depth > max_depth {
-            return color;
-        }
-        // 光线照射到物体后被分散为若干光线
-        let scattered_rays = m.scatter(ray, hit);
-        for scattered_ray in &scattered_rays {
-            color += ray_color(&scattered_ray.ray, scene, depth + 1, max_depth)
-                * scattered_ray.coefficient;
         }
+        let samples = samples_per_pixel.max(1) as f32;
+        (albedo / samples, normal / samples)
+    }).collect::<Vec<_>>();
+
+    let albedo = pixels.iter().flat_map(|(albedo, _)| albedo.to_array()).collect();
+    let normal = pixels.iter().flat_map(|(_, normal)| normal.to_array()).collect();
+    (albedo, normal)
+}
+
+/// 调试用的深度复杂度图：不进行着色，每个像素从中心发射一条主光线，记录其遍历 BVH 时检查包围盒的节点数，
+/// 每个像素一个值，按行优先顺序从上到下排列，可以用 `heatmap` 归一化为热度图后用 `save_image_as_png` 保存，
+/// 亮的区域即加速结构开销大的地方。需要先调用 `Scene::build_bvh`
+pub fn render_depth_complexity(scene: &Scene, camera: &Camera, image_width: u32, image_height: u32) -> Vec<f32> {
+    (0..image_width * image_height).into_par_iter().map(|index| {
+        let (i, j) = (index % image_width, image_height - 1 - index / image_width);
+        let u = (i as f32 + 0.5) / image_width as f32;
+        let v = (j as f32 + 0.5) / image_height as f32;
+        scene.depth_complexity(&camera.get_ray(u, v)) as f32
+    }).collect()
+}
+
+/// 在累积缓冲区的基础上继续渲染 `samples_per_pixel` 个每像素样本并加入缓冲区，
+/// 图像大小与缓冲区一致，可以与 `Accumulation::save`、`Accumulation::load` 配合分多次完成渲染；
+/// 缓冲区保存未降噪的样本，`options.denoise` 不生效；缓冲区中所有像素的采样数相同，`options.time_budget` 也不生效
+pub fn render_accumulate(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+    accumulation: &mut Accumulation,
+) {
+    // 以已完成的样本数区分各次渲染，使相关多重抖动采样在各次渲染中使用不同的排列
+    let (width, height, pass) = (accumulation.width, accumulation.height, accumulation.samples);
+    let options = &RenderOptions { time_budget: None, ..options.clone() };
+    let image_data = in_thread_pool(options.threads, || {
+        render_pass(scene, camera, width, height, max_depth, samples_per_pixel, options, pass, None).image
+    });
+    accumulation.add(&image_data, samples_per_pixel);
+}
+
+// threads 个线程的线程池，按线程数缓存，逐次累积渲染等连续调用不会反复创建、销毁线程
+fn thread_pool(threads: usize) -> Result<Arc<ThreadPool>, ThreadPoolBuildError> {
+    static POOLS: Mutex<Vec<(usize, Arc<ThreadPool>)>> = Mutex::new(Vec::new());
+    let mut pools = POOLS.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some((_, pool)) = pools.iter().find(|(count, _)| *count == threads) {
+        return Ok(pool.clone());
+    }
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(threads).build()?);
+    pools.push((threads, pool.clone()));
+    Ok(pool)
+}
+
+// 在 threads 个线程的线程池中执行 op，threads 为 0 时直接使用 rayon 的全局线程池；
+// 无法创建线程池时给出警告并使用全局线程池，设置了种子时结果与线程数无关
+fn in_thread_pool<R: Send>(threads: usize, op: impl FnOnce() -> R + Send) -> R {
+    if threads == 0 {
+        return op();
+    }
+    match thread_pool(threads) {
+        Ok(pool) => pool.install(op),
+        Err(error) => {
+            eprintln!("Warning: failed to build a thread pool with {threads} threads ({error}), using the global pool");
+            op()
+        }
+    }
+}
+
+// 渲染一次，`pass` 用于区分同一图像的多次渲染，返回图像与每个像素亮度均值的标准误差
+//
+// 图像被分为 TILE_SIZE 大小的图块，每个图块的样本再分为若干批，(图块, 样本批次) 作为一个工作单元
+// 交给 rayon 的工作窃取调度，各单元写入自己的缓冲区，全部完成后按批次顺序累加
+#[allow(clippy::too_many_arguments)]
+fn render_pass(
+    scene: Arc<Scene>,
+    camera: Arc<Camera>,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    options: &RenderOptions,
+    pass: u32,
+    on_tile: Option<TileCallback>,
+) -> PassOutput {
+    // 白模中没有镜面反射与透射，也就没有焦散
+    let caustics = options.caustics.filter(|_| options.clay.is_none()).map(|caustics| {
+        PhotonMap::build(&scene, caustics.photon_count, max_depth)
+    });
+    let irradiance_cache = options.irradiance_cache.map(IrradianceCache::new);
+    let energy = options.energy_check.then(EnergyCheck::default);
+    let stats = Mutex::new(IntersectionStats::default());
+    let clay = options.clay.map(Material::clay);
+    let environment = options.environment_sampling.filter(|_| options.light_samples > 0)
+        .and_then(|_| EnvironmentDistribution::new(&options.background, options.environment_radiance));
+    let context = Context {
+        scene: &scene, max_depth, samples_per_pixel, options, caustics, irradiance_cache, energy, stats, clay, environment,
+    };
+    let groups = options.estimator.groups(samples_per_pixel);
+    let deadline = options.time_budget.map(|budget| Instant::now() + budget);
+
+    let tiles_x = image_width.div_ceil(TILE_SIZE);
+    let tiles = tiles_x * image_height.div_ceil(TILE_SIZE);
+    let tile_rect = |tile: u32| {
+        let (x0, y0) = (tile % tiles_x * TILE_SIZE, tile / tiles_x * TILE_SIZE);
+        (x0, y0, (x0 + TILE_SIZE).min(image_width), (y0 + TILE_SIZE).min(image_height))
+    };
+    // 各图块已完成的样本，按轮次、批次的顺序累加；以及由其估计的像素颜色等结果，每轮结束后更新
+    let tile_sums = (0..tiles).map(|tile| {
+        let (x0, y0, x1, y1) = tile_rect(tile);
+        let pixels = ((x1 - x0) * (y1 - y0)) as usize;
+        Mutex::new(TileSums {
+            group_sums: vec![Vec3::ZERO; pixels * groups],
+            pixel_sums: (0..pixels).map(|_| PixelSums::default()).collect(),
+            group_counts: vec![0; groups],
+        })
+    }).collect::<Vec<_>>();
+    let tile_results = (0..tiles).map(|_| Mutex::new(None)).collect::<Vec<_>>();
+
+    // 没有时间预算时所有样本在一轮内完成；有时间预算时分轮渲染，第一轮每像素一个样本，
+    // 之后每轮的样本数与此前完成的样本数相同，超过预算后不再开始新的轮次与工作单元。
+    // 各轮的样本编号连续，相关多重抖动采样的分层与按组估计都与一次完成时相同
+    let (mut round_start, mut unit_offset) = (0, 0);
+    loop {
+        let round_end = match deadline {
+            Some(_) => (round_start * 2).max(round_start + 1).min(samples_per_pixel),
+            None => samples_per_pixel,
+        };
+        let round_samples = round_end - round_start;
+
+        // 图块较少（小图像）时把样本分成更多批，使工作单元的数量接近 TARGET_WORK_UNITS；
+        // 划分只取决于图像大小与采样数，与线程数无关
+        let batches = TARGET_WORK_UNITS.div_ceil(tiles.max(1)).clamp(1, round_samples.max(1));
+        let batch_size = round_samples.div_ceil(batches).max(1);
+        let batches = round_samples.div_ceil(batch_size).max(1);
+
+        // 每个工作单元输出图块内各像素各组样本颜色之和，按像素、组的顺序排列，
+        // 以及各像素的 PixelSums 与完成的样本范围；图块的所有批次完成后由最后完成的线程累加
+        let units = (0..tiles * batches).map(|_| Mutex::new(None)).collect::<Vec<_>>();
+        let finished_batches = (0..tiles).map(|_| AtomicU32::new(0)).collect::<Vec<_>>();
+        (0..tiles * batches).into_par_iter().for_each(|unit| {
+            if let Some(seed) = options.seed {
+                let unit = (unit_offset + unit) as u64;
+                rand_util::seed(seed ^ (((pass as u64) << 32) | unit).wrapping_mul(0x9e3779b97f4a7c15));
+            }
+            // 丢弃该线程之前在工作单元之外（例如构建光子图）的计数
+            stats::take();
+            let tile = unit / batches;
+            let (x0, y0, x1, y1) = tile_rect(tile);
+            let batch = unit % batches;
+            // 第一轮总是完成，保证每个像素至少有一轮的样本
+            let expired = round_start > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            let sample_range = if expired {
+                round_start..round_start
+            } else {
+                round_start + batch * batch_size..(round_start + (batch + 1) * batch_size).min(round_end)
+            };
+            let mut group_sums = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize * groups);
+            let mut pixel_sums = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+            for y in y0..y1 {
+                let j = image_height - 1 - y;
+                for i in x0..x1 {
+                    let mut sums = vec![Vec3::ZERO; groups];
+                    let mut pixel = PixelSums::default();
+                    for sample in sample_range.clone() {
+                        let (color, covered) = sample_pixel(&camera, &context, i, j, image_width, image_height, sample, pass);
+                        sums[sample as usize % groups] += color;
+                        pixel.square_sum += material::luminance(color).powi(2);
+                        if covered {
+                            pixel.covered += 1;
+                            pixel.covered_sum += color;
+                        }
+                    }
+                    group_sums.extend(sums);
+                    pixel_sums.push(pixel);
+                }
+            }
+            *units[unit as usize].lock().unwrap() = Some((group_sums, pixel_sums, sample_range));
+            context.stats.lock().unwrap().merge(&stats::take());
+            if finished_batches[tile as usize].fetch_add(1, Ordering::AcqRel) + 1 < batches {
+                return;
+            }
+
+            // 按批次顺序累加，结果与完成顺序无关
+            let mut sums = tile_sums[tile as usize].lock().unwrap();
+            for unit in &units[(tile * batches) as usize..((tile + 1) * batches) as usize] {
+                let (unit_sums, unit_pixels, sample_range) = unit.lock().unwrap().take().unwrap();
+                for (sum, color) in sums.group_sums.iter_mut().zip(unit_sums) {
+                    *sum += color;
+                }
+                for (sum, pixel) in sums.pixel_sums.iter_mut().zip(unit_pixels) {
+                    sum.square_sum += pixel.square_sum;
+                    sum.covered += pixel.covered;
+                    sum.covered_sum += pixel.covered_sum;
+                }
+                for sample in sample_range {
+                    sums.group_counts[sample as usize % groups] += 1;
+                }
+            }
+            let tile_samples = sums.group_counts.iter().sum::<u32>();
+            let samples = tile_samples as f32;
+            let (colors, pixels): (Vec<_>, Vec<_>) = sums.group_sums.chunks(groups).zip(&sums.pixel_sums).map(|(group_sums, pixel)| {
+                // 样本方差 (Σl² - n·mean²) / (n - 1)，均值的标准误差为 sqrt(方差 / n)
+                let mean = material::luminance(group_sums.iter().sum::<Vec3>()) / samples.max(1.0);
+                let variance = (pixel.square_sum - samples * mean * mean).max(0.0) / (samples - 1.0).max(1.0);
+                let group_means = group_sums.iter().zip(&sums.group_counts).map(|(sum, count)| {
+                    *sum / (*count).max(1) as f32
+                }).collect::<Vec<_>>();
+                let coverage = pixel.covered as f32 / samples.max(1.0);
+                let covered_color = pixel.covered_sum / pixel.covered.max(1) as f32;
+                let error = (variance / samples.max(1.0)).sqrt();
+                (options.estimator.estimate(group_means), (error, coverage, covered_color, tile_samples))
+            }).unzip();
+            drop(sums);
+            if let Some(on_tile) = on_tile {
+                let pixels = colors.iter()
+                    .flat_map(|color| options.color_space.encode_graded(*color, options.color_lut.as_ref()).to_array())
+                    .collect::<Vec<_>>();
+                on_tile(x0, y0, x1 - x0, y1 - y0, &pixels);
+            }
+            *tile_results[tile as usize].lock().unwrap() = Some((colors, pixels));
+        });
+
+        unit_offset += tiles * batches;
+        round_start = round_end;
+        if round_start >= samples_per_pixel || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+    }
+
+    let pixel_count = (image_width * image_height) as usize;
+    let mut output = PassOutput {
+        image: vec![0.0; pixel_count * 3],
+        standard_error: vec![0.0; pixel_count],
+        coverage: vec![0.0; pixel_count],
+        covered_color: vec![0.0; pixel_count * 3],
+        samples: vec![0; pixel_count],
+        stats: context.stats.into_inner().unwrap(),
+    };
+    for (tile, result) in tile_results.into_iter().enumerate() {
+        let (x0, y0, x1, y1) = tile_rect(tile as u32);
+        let (colors, pixels) = result.into_inner().unwrap().unwrap();
+        let mut pixels = colors.into_iter().zip(pixels);
+        for y in y0..y1 {
+            for i in x0..x1 {
+                let (color, (error, coverage, covered_color, samples)) = pixels.next().unwrap();
+                let index = (i + y * image_width) as usize;
+                output.image[index * 3..index * 3 + 3].copy_from_slice(&color.to_array());
+                output.standard_error[index] = error;
+                output.coverage[index] = coverage;
+                output.covered_color[index * 3..index * 3 + 3].copy_from_slice(&covered_color.to_array());
+                output.samples[index] = samples;
+            }
+        }
+    }
+    if let Some(energy) = &context.energy {
+        energy.report(&scene);
+    }
+    if IntersectionStats::ENABLED {
+        eprintln!("Intersection stats: {}", output.stats);
+    }
+    output
+}
+
+// 一次渲染的结果，各项均按行优先顺序排列
+struct PassOutput {
+    image: Vec<f32>, // 各像素颜色，RGB
+    standard_error: Vec<f32>, // 各像素亮度均值的标准误差
+    coverage: Vec<f32>, // 主光线击中物体的样本比例，即 alpha
+    covered_color: Vec<f32>, // 主光线击中物体的样本的颜色均值，RGB，不含背景，没有样本击中时为 0
+    samples: Vec<u32>, // 各像素实际完成的采样数，只有设置了时间预算时才可能少于要求的采样数
+    stats: IntersectionStats, // 追踪样本时的求交统计
+}
+
+// 一个图块已完成的样本之和，按轮次、批次的顺序累加
+struct TileSums {
+    group_sums: Vec<Vec3>, // 各像素各组样本颜色之和，按像素、组的顺序排列
+    pixel_sums: Vec<PixelSums>,
+    group_counts: Vec<u32>, // 各组已完成的样本数，图块内所有像素相同
+}
+
+// 工作单元中一个像素除颜色之外的累加量
+#[derive(Default)]
+struct PixelSums {
+    square_sum: f32, // 样本亮度的平方和，用于估计方差
+    covered: u32, // 主光线击中物体的样本数
+    covered_sum: Vec3, // 主光线击中物体的样本颜色之和
+}
+
+// 计算像素 (i, j) 的第 sample 个样本的颜色，以及主光线是否击中物体，j 从图像底部开始计数
+#[allow(clippy::too_many_arguments)]
+fn sample_pixel(
+    camera: &Camera,
+    context: &Context,
+    i: u32,
+    j: u32,
+    image_width: u32,
+    image_height: u32,
+    sample: u32,
+    pass: u32,
+) -> (Vec3, bool) {
+    let options = context.options;
+    let samples_per_pixel = context.samples_per_pixel;
+    // 在一个像素内进行采样
+    let (shift_u, shift_v, lens) = match options.sampler {
+        Sampler::Random => (
+            rand_util::random_unit_tent(),
+            rand_util::random_unit_tent(),
+            rand_util::random_in_unit_disk(),
+        ),
+        Sampler::CorrelatedMultiJittered => {
+            // 每个像素使用不同的排列，像素与透镜两组维度之间也使用不同的排列以去除相关性
+            let pattern = (j * image_width + i) ^ pass.wrapping_mul(0x9e3779b9);
+            let pixel = rand_util::cmj(sample, samples_per_pixel, pattern);
+            let lens = rand_util::cmj(sample, samples_per_pixel, pattern ^ 0x5bd1e995);
+            (
+                rand_util::tent(pixel.x),
+                rand_util::tent(pixel.y),
+                rand_util::square_to_disk(lens),
+            )
+        }
+    };
+    let u = (i as f32 + shift_u) / image_width as f32;
+    let v = (j as f32 + shift_v) / image_height as f32;
+    let ray = camera.get_ray_with_differentials(
+        u,
+        v,
+        lens,
+        1.0 / image_width as f32,
+        1.0 / image_height as f32,
+    );
+    options.integrator.radiance(&ray, context)
+}
+
+impl Integrator for PathTracing {
+    fn radiance(&self, ray: &Ray, context: &Context) -> (Vec3, bool) {
+        let options = context.options;
+        let wavelength = options.spectral.then(spectrum::sample_wavelength);
+        let ray = ray.with_wavelength(wavelength);
+        let (color, covered) = match ray.spread().filter(|_| options.cone_tracing) {
+            Some(spread) => cone_color(&ray, spread, context),
+            None => {
+                // 与 ray_color 相同，单独求交以得到主光线是否击中物体
+                let hit = context.scene.hit(&ray, T_MIN, T_MAX);
+                let covered = hit.is_some();
+                let color = match hit {
+                    Some(hit) => shade(&ray, hit, context, 0, PathState::Direct, Vec3::ONE, None, &mut rand_util::LocalRng),
+                    None => spectral(miss_color(&ray, options, 0), &ray),
+                };
+                (color, covered)
+            }
+        };
+        match wavelength {
+            Some(wavelength) => (spectrum::to_rgb(color.x, wavelength), covered),
+            None => (color, covered),
+        }
+    }
+}
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, ray: &Ray, context: &Context) -> (Vec3, bool) {
+        bdpt::radiance(ray, context.scene, context.max_depth, context.options)
+    }
+}
+
+// 锥体追踪的主光线颜色，以及是否击中物体。细三角面按距离由近到远累加覆盖比例，
+// 相邻的细三角面互不重叠，覆盖比例相加；已有正面之后的背面多为同一封闭物体被遮挡的一侧，跳过。
+// 只着色权重最大的至多 CONE_MAX_LAYERS 层，按比例放大使总覆盖比例不变，剩余部分为普通交点或背景
+fn cone_color(ray: &Ray, spread: f32, context: &Context) -> (Vec3, bool) {
+    let (hit, layers) = context.scene.hit_cone(ray, spread);
+    let covered = hit.is_some() || !layers.is_empty();
+    let mut remaining = 1.0;
+    let mut front = false;
+    let mut weighted = vec![];
+    for layer in layers {
+        if remaining <= 0.0 {
+            break;
+        }
+        if front && !layer.hit.front_face {
+            continue;
+        }
+        front |= layer.hit.front_face;
+        let weight = layer.coverage.min(remaining);
+        remaining -= weight;
+        weighted.push((weight, layer));
+    }
+    let total = 1.0 - remaining;
+    weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    weighted.truncate(CONE_MAX_LAYERS);
+    let scale = total / weighted.iter().map(|(weight, _)| weight).sum::<f32>().max(f32::EPSILON);
+
+    let mut color = Vec3::ZERO;
+    for (weight, layer) in weighted {
+        let weight = weight * scale;
+        let throughput = Vec3::splat(weight);
+        color += weight * shade(&layer.ray, layer.hit, context, 0, PathState::Direct, throughput, None, &mut rand_util::LocalRng);
+    }
+    if remaining > 0.0 {
+        color += remaining * match hit {
+            Some(hit) => {
+                let throughput = Vec3::splat(remaining);
+                shade(ray, hit, context, 0, PathState::Direct, throughput, None, &mut rand_util::LocalRng)
+            }
+            None => spectral(miss_color(ray, context.options, 0), ray),
+        };
+    }
+    (color, covered)
+}
+
+/// 光线颜色计算，`depth` 为当前光线之前已经发生的散射次数，
+/// `throughput` 为路径上此前各次散射系数的乘积，即该光线的颜色对像素的权重，
+/// `medium` 为光线所在物体内部的介质，材质采样、俄罗斯轮盘与光源采样的分层使用的随机数取自 `rng`
+fn ray_color(
+    ray: &Ray,
+    context: &Context,
+    depth: u32,
+    state: PathState,
+    throughput: Vec3,
+    medium: Option<Medium>,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    match context.scene.hit(ray, T_MIN, T_MAX) {
+        Some(hit) => match medium {
+            Some(medium) => through_medium(ray, hit, context, depth, state, throughput, medium, rng),
+            None => shade(ray, hit, context, depth, state, throughput, None, rng),
+        },
+        // 已由环境采样计算的部分
+        None if state == PathState::Diffuse && environment_sampled(ray, context.options) => Vec3::ZERO,
+        // 未击中任何物体，返回背景颜色，默认为黑色
+        None => spectral(miss_color(ray, context.options, depth), ray),
+    }
+}
+
+// 光线在介质中传播到交点 `hit` 的颜色。只吸收时按 Beer-Lambert 定律衰减；有散射时随机选择一个通道，
+// 按其消光系数采样自由程，在交点之前散射则按相函数改变方向、仍在介质中继续追踪，
+// 否则到达交点，权重为透射率除以各通道的平均概率
+#[allow(clippy::too_many_arguments)]
+fn through_medium(
+    ray: &Ray,
+    hit: HitRecord,
+    context: &Context,
+    depth: u32,
+    state: PathState,
+    throughput: Vec3,
+    medium: Medium,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    let absorption = spectral(medium.absorption, ray);
+    let scattering = spectral(medium.scattering, ray);
+    let extinction = absorption + scattering;
+    if scattering.max_element() <= 0.0 || depth >= context.max_depth {
+        let transmittance = (-absorption * hit.t).exp();
+        return shade(ray, hit, context, depth, state, throughput * transmittance, Some(medium), rng) * transmittance;
+    }
+    let channel = rng.gen_range(0..3);
+    let distance = -(1.0 - rng.gen::<f32>()).ln() / extinction[channel];
+    if distance >= hit.t {
+        let transmittance = (-extinction * hit.t).exp();
+        let probability = transmittance.element_sum() / 3.0;
+        if probability <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let weight = transmittance / probability;
+        return shade(ray, hit, context, depth, state, throughput * weight, Some(medium), rng) * weight;
+    }
+    let transmittance = (-extinction * distance).exp();
+    let weight = scattering * transmittance / (extinction * transmittance).element_sum() * 3.0;
+    let throughput = throughput * weight;
+    if throughput.max_element() < context.options.min_throughput {
+        return Vec3::ZERO;
+    }
+    // 介质中的散射点没有光源采样，散射光线击中光源时直接计算，路径状态保持不变
+    let scattered = Ray::new_unchecked(ray.at(distance), medium.sample_phase(ray.direction, rng))
+        .with_time(ray.time)
+        .with_wavelength(ray.wavelength);
+    ray_color(&scattered, context, depth + 1, state, throughput, Some(medium), rng) * weight
+}
+
+// 漫反射之后逃逸的光线方向是否在环境采样的范围内，这部分背景已由 direct_light 计算
+fn environment_sampled(ray: &Ray, options: &RenderOptions) -> bool {
+    options.light_samples > 0 && options.environment_sampling.is_some_and(|cone| cone.contains(ray.direction))
+}
+
+// 未击中任何物体的光线的颜色，`depth` 为光线之前已经发生的散射次数，
+// 散射后逃逸的光线在背景之外加上环境辐亮度
+pub(crate) fn miss_color(ray: &Ray, options: &RenderOptions, depth: u32) -> Vec3 {
+    let background = options.background.color(ray);
+    if depth > 0 {
+        background + options.environment_radiance
+    } else {
+        background
+    }
+}
+
+// 白模渲染时把交点的材质换成白模材质，发光材质保持不变，场景仍然被原来的光源照亮
+pub(crate) fn with_clay<'a>(hit: HitRecord<'a>, clay: Option<&'a Material>) -> HitRecord<'a> {
+    match clay {
+        Some(clay) if hit.material.emissive_color().max_element() <= 0.0 => HitRecord { material: clay, ..hit },
+        _ => hit,
+    }
+}
+
+// 次表面散射的漫反射光线从入射点切平面内的另一点出射，沿法向将出射点投影回同一材质的表面；
+// 投影范围内没有该材质的表面时（出射点越过了物体的边缘或弯曲处）改从入射点出射，避免光从物体外的空间射出
+pub(crate) fn project_subsurface_exits(scene: &Scene, hit: &HitRecord, scattered_rays: &mut [ScatteredRay]) {
+    for scattered_ray in scattered_rays.iter_mut().filter(|s| s.kind == ScatterKind::Diffuse) {
+        let exit = scattered_ray.ray.origin;
+        let reach = exit.distance(hit.point);
+        if reach <= 0.0 {
+            continue;
+        }
+        let probe = Ray::new(exit + reach * hit.normal, -hit.normal).with_time(scattered_ray.ray.time);
+        let surface = scene.hit(&probe, 0.0, 2.0 * reach)
+            .filter(|surface| std::ptr::eq(surface.material, hit.material))
+            .filter(|surface| surface.normal.dot(scattered_ray.ray.direction) > 0.0);
+        scattered_ray.ray.origin = surface.map_or(hit.point, |surface| surface.point);
+    }
+}
+
+// 光线携带波长时，将 RGB 颜色或系数转换为该波长处的光谱值，三个分量相同；否则保持不变。
+// 转换是线性的，两个颜色相乘时需要分别转换
+fn spectral(color: Vec3, ray: &Ray) -> Vec3 {
+    match ray.wavelength {
+        Some(wavelength) => Vec3::splat(spectrum::from_rgb(color, wavelength)),
+        None => color,
+    }
+}
+
+// 计算光线在交点处反射回来的颜色，`medium` 为入射光线所在的介质，
+// 反射光线仍在该介质中，透射光线从正面进入材质内部的介质，从背面射出后回到真空
+#[allow(clippy::too_many_arguments)]
+fn shade(
+    ray: &Ray,
+    hit: HitRecord,
+    context: &Context,
+    depth: u32,
+    state: PathState,
+    throughput: Vec3,
+    medium: Option<Medium>,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    let hit = with_clay(hit, context.clay.as_ref());
+    let m = hit.material;
+    let transmitted_medium = if hit.front_face { m.medium } else { None };
+    let medium_of = |kind| if kind == ScatterKind::Transmission { transmitted_medium } else { medium };
+    let ambient = m.ambient_color(hit.uv);
+    let mut color = if ambient.max_element() > 0.0 {
+        spectral(ambient, ray) * ambient_occlusion(context.scene, &hit, context.options.ambient_occlusion, ray.time)
+    } else {
+        Vec3::ZERO
+    };
+    let emission_sampled = match state {
+        PathState::Direct => false,
+        PathState::Diffuse => context.options.light_samples > 0,
+        PathState::Caustic => context.caustics.is_some(),
+    };
+    if !emission_sampled {
+        color += spectral(m.emitted(&hit), ray);
+    }
+    // 已经散射了 max_depth 次，就不再弹射了
+    if depth >= context.max_depth {
         return color;
     }
+    let diffuse = spectral(m.diffuse_reflectance(&hit), ray);
+    if context.options.light_samples > 0 && diffuse.max_element() > 0.0 {
+        color += diffuse * spectral(direct_light(ray, &hit, context, rng), ray);
+    }
+    // 焦散由光子图估计，漫反射系数对应的 BRDF 为系数 / π
+    if let (Some(caustics), Some(options)) = (&context.caustics, context.options.caustics) {
+        if diffuse.max_element() > 0.0 {
+            let irradiance = caustics.irradiance(hit.point, hit.normal, options.gather_radius);
+            color += diffuse / std::f32::consts::PI * spectral(irradiance, ray);
+        }
+    }
+    // 路径上的第一次漫反射使用辐照度缓存，之后的漫反射仍然逐条追踪
+    let cached = state != PathState::Diffuse
+        && diffuse.max_element() > 0.0
+        && context.irradiance_cache.is_some();
+    if cached {
+        color += diffuse * spectral(cached_irradiance(ray, &hit, context, depth, rng), ray);
+    }
+    // 光线照射到物体后被分散为若干光线，漫反射已由辐照度缓存计算时跳过漫反射光线；
+    // 启用路径正则化时，漫反射之后的镜面反射与透射按变粗糙的材质采样
+    let regularized;
+    let sampled = match context.options.regularize {
+        Some(roughness) if state != PathState::Direct => {
+            regularized = m.regularized(roughness);
+            &regularized
+        }
+        _ => m,
+    };
+    let mut scattered_rays = sampled.scatter(ray, hit, rng);
+    project_subsurface_exits(context.scene, &hit, &mut scattered_rays);
+    if let Some(energy) = &context.energy {
+        energy.record(m, &scattered_rays);
+    }
+    let scattered_rays = scattered_rays.into_iter()
+        .filter(|scattered_ray| !(cached && scattered_ray.kind == ScatterKind::Diffuse))
+        .collect::<Vec<_>>();
+    if depth >= context.options.branch_depth {
+        // 按各分量系数的亮度随机选择一个分量继续追踪（俄罗斯轮盘），系数除以选中的概率
+        let luminance = |coefficient| material::luminance(coefficient);
+        let total = scattered_rays.iter().map(|s| luminance(s.coefficient)).sum::<f32>().max(1.0);
+        let mut pick = rng.gen::<f32>() * total;
+        if let Some(scattered_ray) = scattered_rays.iter().find(|s| {
+            pick -= luminance(s.coefficient);
+            pick < 0.0
+        }) {
+            let coefficient = scattered_ray.coefficient * total / luminance(scattered_ray.coefficient);
+            color += trace_scattered(
+                &scattered_ray.ray, scattered_ray.kind, coefficient, ray, context, depth, state, throughput,
+                medium_of(scattered_ray.kind), rng,
+            );
+        }
+        return color;
+    }
+    for scattered_ray in &scattered_rays {
+        color += trace_scattered(
+            &scattered_ray.ray, scattered_ray.kind, scattered_ray.coefficient, ray, context, depth, state, throughput,
+            medium_of(scattered_ray.kind), rng,
+        );
+    }
+    color
+}
 
-    // 背景颜色为黑色
-    Vec3::ZERO
+// 追踪一条散射光线并乘以其系数；路径的权重已经小于 min_throughput 时跳过，
+// 对像素的贡献至多为该比例，暗色或吸收性强的材质上的路径不再继续分支
+#[allow(clippy::too_many_arguments)]
+fn trace_scattered(
+    scattered: &Ray,
+    kind: ScatterKind,
+    coefficient: Vec3,
+    ray: &Ray,
+    context: &Context,
+    depth: u32,
+    state: PathState,
+    throughput: Vec3,
+    medium: Option<Medium>,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    let throughput = throughput * coefficient;
+    if throughput.max_element() < context.options.min_throughput {
+        return Vec3::ZERO;
+    }
+    ray_color(scattered, context, depth + 1, state.next(kind), throughput, medium, rng) * spectral(coefficient, ray)
 }
 
+// 环境光遮蔽，在法向一侧的半球内按余弦分布发射一条长度为 distance 的光线，未被遮挡时返回 1，
+// 对所有样本平均即为半球未被遮挡的比例，缝隙与角落处环境光较弱；distance 为 0 时不计算遮蔽
+pub(crate) fn ambient_occlusion(scene: &Scene, hit: &HitRecord, distance: f32, time: f32) -> f32 {
+    if distance <= 0.0 {
+        return 1.0;
+    }
+    let direction = rand_util::random_unit_vector_cosine(hit.normal);
+    if scene.occluded(hit.point, hit.point + direction * distance, time) {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+// 对光源进行 light_samples 次采样，估计交点处的直接光照，启用环境采样时再对背景采样同样次数，
+// 与漫反射光线的颜色含义相同（已乘以 Oren-Nayar 与 Lambert 之比），乘以漫反射系数即为漫反射的直接光照部分。
+// 各次采样选择光源使用的随机数在 [0, 1) 上分层，多光源场景收敛更快
+fn direct_light(ray: &Ray, hit: &HitRecord, context: &Context, rng: &mut impl Rng) -> Vec3 {
+    let scene = context.scene;
+    let samples = context.options.light_samples;
+    let mut radiance = Vec3::ZERO;
+    for i in 0..samples {
+        let u = (i as f32 + rng.gen::<f32>()) / samples as f32;
+        let Some((light, pick_probability)) = scene.pick_light_with(u.min(1.0 - f32::EPSILON)) else {
+            break;
+        };
+        let Some((sample, pdf_area)) = light.sample_point_from(hit.point) else {
+            continue;
+        };
+        let material = light.material().unwrap();
+        let to_light = sample.point - hit.point;
+        let distance_squared = to_light.length_squared();
+        let direction = to_light / distance_squared.sqrt();
+        let emissive = material.emitted_towards(sample.normal, -direction);
+        let cos = hit.normal.dot(direction);
+        if cos <= 0.0 || emissive.max_element() <= 0.0 || scene.occluded(hit.point, sample.point, ray.time) {
+            continue;
+        }
+        // 双面发光时光源两侧都可见，光源一侧的余弦取绝对值
+        let cos_light = sample.normal.dot(direction).abs();
+        let oren_nayar = hit.material.oren_nayar(hit.normal, -ray.direction.normalize(), direction);
+        radiance += emissive * cos * cos_light * oren_nayar
+            / (distance_squared * pick_probability * pdf_area * std::f32::consts::PI);
+    }
+    // 在指定范围内对背景采样，背景视为无穷远处的光源，按散射后逃逸的光线计算颜色；
+    // 背景为环境贴图时按亮度在整个球面上采样，范围之外的方向由散射光线计算，计为 0
+    if let Some(cone) = context.options.environment_sampling {
+        for _ in 0..samples {
+            let (direction, pdf) = match &context.environment {
+                Some(environment) => environment.sample(rng),
+                None => cone.sample(rng),
+            };
+            let cos = hit.normal.dot(direction);
+            if cos <= 0.0 || pdf <= 0.0 || !cone.contains(direction) {
+                continue;
+            }
+            let shadow_ray = Ray::new(hit.point, direction).with_time(ray.time);
+            if scene.hit(&shadow_ray, T_MIN, T_MAX).is_some() {
+                continue;
+            }
+            let oren_nayar = hit.material.oren_nayar(hit.normal, -ray.direction.normalize(), direction);
+            radiance += miss_color(&shadow_ray, context.options, 1) * cos * oren_nayar
+                / (pdf * std::f32::consts::PI);
+        }
+    }
+    radiance / samples as f32
+}
+
+// 从辐照度缓存中插值交点处的入射光，附近没有有效记录时在半球内采样计算并加入缓存
+// 缓存记录的总是 RGB 辐照度，采样光线不携带波长，光谱渲染时由调用方转换
+fn cached_irradiance(ray: &Ray, hit: &HitRecord, context: &Context, depth: u32, rng: &mut impl Rng) -> Vec3 {
+    let cache = context.irradiance_cache.as_ref().unwrap();
+    if let Some(irradiance) = cache.lookup(hit.point, hit.normal) {
+        return irradiance;
+    }
+
+    let samples = cache.options().samples.max(1);
+    let mut irradiance = Vec3::ZERO;
+    let mut inverse_distance = 0.0;
+    for _ in 0..samples {
+        let ray = Ray::new_unchecked(hit.point, rand_util::random_unit_vector_cosine_pdf_with(hit.normal, rng).0)
+            .with_time(ray.time);
+        match context.scene.hit(&ray, T_MIN, T_MAX) {
+            Some(sample_hit) => {
+                inverse_distance += 1.0 / sample_hit.t;
+                // 缓存的辐照度被多条路径共用，与当前路径的权重无关，采样光线的权重从 1 开始
+                irradiance += shade(&ray, sample_hit, context, depth + 1, PathState::Diffuse, Vec3::ONE, None, rng);
+            }
+            None if environment_sampled(&ray, context.options) => {}
+            None => irradiance += miss_color(&ray, context.options, depth + 1),
+        }
+    }
+    irradiance /= samples as f32;
+    let harmonic_distance = samples as f32 / inverse_distance.max(f32::EPSILON);
+    cache.insert(hit.point, hit.normal, irradiance, harmonic_distance);
+    irradiance
+}
+
+
+/// 将每个像素一个值的数据（例如 `render_hdr_with_error` 返回的标准误差）转换为 8 位 RGB 热度图，
+/// 可以用 `save_image_as_png` 保存。值按第 99 百分位数归一化，避免个别萤火虫像素压暗整张图，
+/// 颜色从小到大依次为黑、蓝、红、黄、白
+pub fn heatmap(values: &[f32]) -> Vec<u8> {
+    const COLORS: [Vec3; 5] = [
+        Vec3::ZERO,
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::ONE,
+    ];
+    let mut sorted = values.iter().copied().filter(|value| value.is_finite()).collect::<Vec<_>>();
+    sorted.sort_by(f32::total_cmp);
+    let scale = sorted.get(sorted.len() * 99 / 100).copied().unwrap_or(0.0);
+    values.iter().flat_map(|&value| {
+        let t = if scale > 0.0 { (value / scale).clamp(0.0, 1.0) } else { 0.0 } * (COLORS.len() - 1) as f32;
+        let index = (t as usize).min(COLORS.len() - 2);
+        let color = COLORS[index].lerp(COLORS[index + 1], t - index as f32);
+        color.to_array().map(|c| (c * 255.0).round() as u8)
+    }).collect()
+}
 
 /// 将渲染结果保存为 PPM 文件
 pub fn save_image_as_ppm(image_data: Vec<u8>, width: u32, height: u32, filename: &str) {
     let mut file = File::create(filename).unwrap();
-    writeln!(file, "P6\n{} {}\n255", width, height).unwrap();
-    file.write_all(&image_data).unwrap();
+    write_ppm(&mut file, &image_data, width, height).unwrap();
+}
+
+/// 将渲染结果以 PPM 格式写入任意 writer，例如标准输出或内存缓冲区
+pub fn write_ppm(writer: &mut impl Write, image_data: &[u8], width: u32, height: u32) -> io::Result<()> {
+    writeln!(writer, "P6\n{} {}\n255", width, height)?;
+    writer.write_all(image_data)
 }
 
 /// 将渲染结果保存为 png 文件
 pub fn save_image_as_png(image_data: Vec<u8>, width: u32, height: u32, filename: &str) {
+    to_rgb_image(&image_data, width, height).save(filename).expect("Failed to save PNG image");
+}
+
+/// 将 `render_rgba` 得到的 8 位 RGBA 数据保存为带 alpha 通道的 png 文件
+pub fn save_image_as_png_rgba(image_data: Vec<u8>, width: u32, height: u32, filename: &str) {
+    RgbaImage::from_raw(width, height, image_data)
+        .expect("Image data does not match the image size")
+        .save(filename)
+        .expect("Failed to save PNG image");
+}
+
+/// 将 `render_views` 的结果保存为 png 文件，返回各文件名：只有一个视角时保存为 `{prefix}.png`，
+/// 否则按摄像机的序号保存为 `{prefix}_camera_{序号}.png`，序号从 0 开始
+pub fn save_views_as_png(images: Vec<Vec<u8>>, width: u32, height: u32, prefix: &str) -> Vec<String> {
+    let count = images.len();
+    images.into_iter().enumerate().map(|(index, image_data)| {
+        let filename = if count == 1 {
+            format!("{prefix}.png")
+        } else {
+            format!("{prefix}_camera_{index}.png")
+        };
+        save_image_as_png(image_data, width, height, &filename);
+        filename
+    }).collect()
+}
+
+/// 将渲染结果以 png 格式写入任意 writer，png 编码需要 writer 支持 seek
+pub fn write_png(
+    writer: &mut (impl Write + Seek),
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+) -> ImageResult<()> {
+    to_rgb_image(image_data, width, height).write_to(writer, ImageFormat::Png)
+}
+
+/// 将浮点渲染结果保存为每通道 16 位的 png 文件，可以减少渐变处的色带
+pub fn save_image_as_png16(image_data: Vec<f32>, width: u32, height: u32, filename: &str) {
+    to_rgb16_image(&image_data, width, height).save(filename).expect("Failed to save PNG image");
+}
+
+/// 将浮点渲染结果以每通道 16 位的 png 格式写入任意 writer
+pub fn write_png16(
+    writer: &mut (impl Write + Seek),
+    image_data: &[f32],
+    width: u32,
+    height: u32,
+) -> ImageResult<()> {
+    to_rgb16_image(image_data, width, height).write_to(writer, ImageFormat::Png)
+}
+
+/// 两张 8 位 RGB 图像之间的差异，每个分量的误差以 [0, 1] 表示
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDifference {
+    pub mean: f32, // 所有分量误差的平均值
+    pub max: f32,  // 最大的分量误差
+}
+
+impl ImageDifference {
+    /// 平均误差与最大误差是否都不超过给定的容差
+    pub fn within(&self, mean: f32, max: f32) -> bool {
+        self.mean <= mean && self.max <= max
+    }
+}
+
+/// 逐分量比较两张大小相同的 8 位 RGB 图像
+pub fn compare_images(a: &[u8], b: &[u8]) -> ImageDifference {
+    assert_eq!(a.len(), b.len(), "Images have different sizes");
+    let (sum, max) = a.iter().zip(b).fold((0u64, 0u8), |(sum, max), (a, b)| {
+        let error = a.abs_diff(*b);
+        (sum + error as u64, max.max(error))
+    });
+    ImageDifference {
+        mean: sum as f32 / (a.len().max(1) as f32 * 255.0),
+        max: max as f32 / 255.0,
+    }
+}
+
+/// 将渲染结果与保存的参考 png 图像比较，参考图像大小不同时返回错误。
+/// 以固定的 `RenderOptions::seed` 在低分辨率下渲染小场景并与参考图像比较，
+/// 可以在重构 BVH、材质或采样代码后检查渲染结果是否改变
+pub fn compare_with_reference(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    filename: &str,
+) -> ImageResult<ImageDifference> {
+    let reference = image::open(filename)?.to_rgb8();
+    if reference.dimensions() != (width, height) {
+        return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+    }
+    Ok(compare_images(image_data, reference.as_raw()))
+}
+
+fn to_rgb_image(image_data: &[u8], width: u32, height: u32) -> RgbImage {
     let mut img = RgbImage::new(width, height);
 
     for y in 0..height {
@@ -98,6 +1600,266 @@ pub fn save_image_as_png(image_data: Vec<u8>, width: u32, height: u32, filename:
         }
     }
 
-    img.save(filename).expect("Failed to save PNG image");
+    img
+}
+
+fn to_rgb16_image(image_data: &[f32], width: u32, height: u32) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+    let image_data = image_data.iter()
+        .map(|x| { (x.clamp(0.0, 1.0) * 65535.0).round() as u16 })
+        .collect::<Vec<_>>();
+    ImageBuffer::from_raw(width, height, image_data)
+        .expect("Image data does not match the image size")
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use rand::SeedableRng;
+    use glam::Vec2;
+    use crate::scene::primitive::{MovingSphere, Triangle};
+
+    // 以 center 为中心、由 a 与 b 张成的平行四边形，分为两个三角面
+    fn add_quad(scene: &mut Scene, center: Vec3, a: Vec3, b: Vec3, material: &Arc<Material>) {
+        let corners = [center - a - b, center + a - b, center + a + b, center - a + b];
+        for [i, j, k] in [[0, 1, 2], [0, 2, 3]] {
+            let vertices = vec![corners[i], corners[j], corners[k]];
+            scene.add(Box::new(Triangle::new(vertices, vec![], vec![Vec2::ZERO; 3], material.clone())));
+        }
+    }
+
+    #[test]
+    fn write_ppm_to_memory() {
+        let image_data = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 10, 20, 30];
+        let mut buffer = Vec::new();
+        write_ppm(&mut buffer, &image_data, 3, 2).unwrap();
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&buffer[..header.len()], header);
+        assert_eq!(&buffer[header.len()..], &image_data);
+    }
+
+    #[test]
+    fn write_png_to_memory() {
+        let image_data = [255, 0, 0, 0, 255, 0];
+        let mut buffer = Cursor::new(Vec::new());
+        write_png(&mut buffer, &image_data, 2, 1).unwrap();
+        let decoded = image::load_from_memory(buffer.get_ref()).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (2, 1));
+        assert_eq!(decoded.into_raw(), image_data);
+    }
+
+    // 潜望镜：摄像机看向 45° 的镜面，光线向上反射到另一面平行的镜面，再水平射向光源，
+    // 经过恰好两次散射才能到达光源，摄像机直接看不到光源与第二面镜子
+    fn periscope() -> (Arc<Scene>, Arc<Camera>) {
+        let mut scene = Scene::new();
+        let mirror = Arc::new(Material::MIRROR);
+        let light = Arc::new(Material::LUMINOUS);
+        let diagonal = Vec3::new(0.0, 1.0, -1.0).normalize();
+        add_quad(&mut scene, Vec3::new(0.0, 0.0, -5.0), Vec3::X, diagonal, &mirror);
+        add_quad(&mut scene, Vec3::new(0.0, 5.0, -5.0), Vec3::X, diagonal, &mirror);
+        add_quad(&mut scene, Vec3::new(0.0, 5.0, -12.0), Vec3::X * 2.0, Vec3::Y * 2.0, &light);
+        scene.build_bvh();
+        let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 1.0, 1.0, 5.0, 0.0);
+        (Arc::new(scene), Arc::new(camera))
+    }
+
+    #[test]
+    fn max_depth_counts_scatter_events() {
+        let (scene, camera) = periscope();
+        let options = RenderOptions { seed: Some(1), ..Default::default() };
+        let brightness = |max_depth| {
+            let image = render_hdr(scene.clone(), camera.clone(), 1, 1, max_depth, 4, &options);
+            material::luminance(Vec3::from_slice(&image))
+        };
+        // 光源在第二次散射之后才能被看到，max_depth 为 1 时只有一次散射
+        assert_eq!(brightness(0), 0.0);
+        assert_eq!(brightness(1), 0.0);
+        let lit = brightness(2);
+        assert!((lit - Material::LUMINOUS.emissive_color().x).abs() < 1e-3, "{lit}");
+        assert_eq!(brightness(3), lit);
+    }
+
+    // 与场景无关、返回固定颜色的积分器
+    #[derive(Debug)]
+    struct Constant(Vec3);
+
+    impl Integrator for Constant {
+        fn radiance(&self, _ray: &Ray, context: &Context) -> (Vec3, bool) {
+            assert_eq!(context.max_depth(), 7);
+            (self.0, true)
+        }
+    }
+
+    #[test]
+    fn custom_integrator_computes_every_sample() {
+        let (scene, camera) = periscope();
+        let color = Vec3::new(0.25, 0.5, 0.75);
+        let options = RenderOptions { integrator: Arc::new(Constant(color)), ..Default::default() };
+        let image = render_hdr(scene, camera, 3, 2, 7, 4, &options);
+        assert_eq!(image.len(), 3 * 2 * 3);
+        for pixel in image.chunks(3) {
+            assert!((Vec3::from_slice(pixel) - color).abs().max_element() < 1e-6, "{pixel:?}");
+        }
+    }
+
+    // 从上方击中 scene 中的物体，散射若干次，返回投影后各漫反射光线的起点
+    fn subsurface_exits(scene: &Scene) -> Vec<Vec3> {
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y);
+        let hit = scene.hit(&ray, T_MIN, T_MAX).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        (0..200).flat_map(|_| {
+            let mut scattered_rays = hit.material.scatter(&ray, hit, &mut rng);
+            project_subsurface_exits(scene, &hit, &mut scattered_rays);
+            scattered_rays.into_iter().filter(|s| s.kind == ScatterKind::Diffuse).map(|s| s.ray.origin)
+        }).collect()
+    }
+
+    #[test]
+    fn subsurface_exits_stay_on_the_surface() {
+        let marble = Material { subsurface_mean_free_path: Some(1.0), ..Material::PLASTER };
+
+        // 平均自由程远大于球的半径，切平面内的出射点大多在球外，投影后都应在球面上
+        let mut scene = Scene::new();
+        scene.add_sphere(Vec3::ZERO, 0.1, marble.clone());
+        scene.build_bvh();
+        let exits = subsurface_exits(&scene);
+        assert!(!exits.is_empty());
+        for exit in exits {
+            assert!((exit.length() - 0.1).abs() < 1e-4, "{exit}");
+        }
+
+        // 足够大的平面上出射点不变，仍然模糊漫反射
+        let mut scene = Scene::new();
+        add_quad(&mut scene, Vec3::ZERO, Vec3::X * 100.0, Vec3::NEG_Z * 100.0, &Arc::new(marble));
+        scene.build_bvh();
+        let exits = subsurface_exits(&scene);
+        assert!(exits.iter().all(|exit| exit.y.abs() < 1e-4));
+        assert!(exits.iter().any(|exit| exit.length() > 0.5));
+    }
+
+    // 地面只被一个运动的发光球照亮，摄像机从上方看向地面
+    fn lit_by_moving_sphere() -> (Arc<Scene>, Arc<Camera>) {
+        let mut scene = Scene::new();
+        let floor = Material { ambient: Vec3::ZERO, ..Material::PLASTER };
+        add_quad(&mut scene, Vec3::ZERO, Vec3::X * 20.0, Vec3::NEG_Z * 20.0, &Arc::new(floor));
+        let center = Vec3::new(0.0, 2.0, 0.0);
+        scene.add(Box::new(MovingSphere::new(center, center + Vec3::X, 0.0, 1.0, 0.5, Material::LUMINOUS)));
+        scene.build_bvh();
+        let camera = Camera::new(Vec3::new(0.0, 1.0, 3.0), Vec3::ZERO, Vec3::Y, 20.0, 1.0, 1.0, 0.0);
+        (Arc::new(scene), Arc::new(camera))
+    }
+
+    #[test]
+    fn default_options_see_emitters_without_light_sampling() {
+        let (scene, camera) = lit_by_moving_sphere();
+        let options = RenderOptions { seed: Some(1), ..Default::default() };
+        let image = render_hdr(scene, camera, 4, 4, 4, 16, &options);
+        // 运动球体不能进行表面采样，不在 scene.lights 中；对光源采样时漫反射之后击中它的光线
+        // 不计自发光，地面几乎全黑（均值约 0.02），默认不对光源采样时约为 0.19
+        let mean = image.iter().sum::<f32>() / image.len() as f32;
+        assert!(mean > 0.1, "{mean}");
+    }
+
+    // 逐位比较两张 HDR 图像
+    fn bits(image: &[f32]) -> Vec<u32> {
+        image.iter().map(|value| value.to_bits()).collect()
+    }
+
+    #[test]
+    fn thread_count_does_not_change_seeded_renders() {
+        let (scene, camera) = lit_by_moving_sphere();
+        // 多于一个图块，每个像素的样本分为多个批次
+        let render = |threads| {
+            let options = RenderOptions { seed: Some(7), threads, ..Default::default() };
+            render_hdr(scene.clone(), camera.clone(), 40, 24, 4, 64, &options)
+        };
+        let single = render(1);
+        assert!(single.iter().any(|value| *value > 0.0));
+        assert_eq!(bits(&single), bits(&render(4)));
+    }
+
+    #[test]
+    fn seeded_renders_are_reproducible() {
+        let (scene, camera) = lit_by_moving_sphere();
+        let render = |seed, width, height, samples| {
+            let options = RenderOptions { seed: Some(seed), ..Default::default() };
+            render_hdr(scene.clone(), camera.clone(), width, height, 4, samples, &options)
+        };
+        // 图像较大、采样数较少，与图像极小、采样数很多两种情况下工作单元的划分不同
+        for (width, height, samples) in [(40, 24, 4), (1, 1, 512)] {
+            let image = render(3, width, height, samples);
+            assert_eq!(bits(&image), bits(&render(3, width, height, samples)));
+            assert_ne!(bits(&image), bits(&render(4, width, height, samples)));
+        }
+    }
+
+    #[test]
+    fn bidirectional_alpha_follows_primary_hits() {
+        let mut scene = Scene::new();
+        add_quad(&mut scene, Vec3::new(0.0, 0.0, -5.0), Vec3::X, Vec3::Y, &Arc::new(Material::PLASTER));
+        scene.build_bvh();
+        let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 60.0, 1.0, 5.0, 0.0);
+        let options = RenderOptions { integrator: Arc::new(Bidirectional), seed: Some(1), ..Default::default() };
+        let image = render_rgba(Arc::new(scene), Arc::new(camera), 16, 16, 2, 4, &options);
+        let alpha = |x: usize, y: usize| image[(y * 16 + x) * 4 + 3];
+        assert_eq!(alpha(8, 8), 255);
+        assert_eq!(alpha(0, 0), 0);
+        assert_eq!(alpha(15, 15), 0);
+    }
+
+    // 白色背景前十二条倾斜的黑色细条，宽度约为三分之一个像素
+    fn thin_bars() -> (Arc<Scene>, Arc<Camera>, RenderOptions) {
+        let mut scene = Scene::new();
+        let black = Arc::new(Material { ambient: Vec3::ZERO, diffuse: Vec3::ZERO, specular: Vec3::ZERO, ..Material::PLASTER });
+        let (along, across) = (Vec3::new(0.34, 0.94, 0.0), Vec3::new(0.94, -0.34, 0.0));
+        for i in 0..12 {
+            let center = Vec3::new(-3.3 + 0.6 * i as f32, 0.0, -10.0);
+            add_quad(&mut scene, center, along * 3.0, across * 0.038, &black);
+        }
+        scene.build_bvh();
+        let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 40.0, 1.0, 10.0, 0.0);
+        let options = RenderOptions {
+            background: Background::Gradient { top: Vec3::ONE, bottom: Vec3::ONE },
+            seed: Some(1),
+            ..Default::default()
+        };
+        (Arc::new(scene), Arc::new(camera), options)
+    }
+
+    #[test]
+    fn cone_tracing_smooths_thin_geometry() {
+        let (scene, camera, options) = thin_bars();
+        let render = |samples, cone_tracing| {
+            let options = RenderOptions { cone_tracing, ..options.clone() };
+            render_hdr(scene.clone(), camera.clone(), 32, 32, 0, samples, &options)
+        };
+        let reference = render(256, false);
+        let rmse = |image: Vec<f32>| {
+            let sum = image.iter().zip(&reference).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+            (sum / image.len() as f32).sqrt()
+        };
+        // 细条覆盖像素的比例与点采样的参考图像一致
+        let mean = |image: &[f32]| image.iter().sum::<f32>() / image.len() as f32;
+        assert!((mean(&render(16, true)) - mean(&reference)).abs() < 0.01);
+        // 每个像素只有一个样本时，锥体追踪的误差明显更小
+        let (point, cone) = (rmse(render(1, false)), rmse(render(1, true)));
+        assert!(cone < 0.6 * point, "cone {cone}, point {point}");
+    }
+
+    #[test]
+    fn thread_pools_are_reused() {
+        let pool = thread_pool(3).unwrap();
+        assert_eq!(pool.current_num_threads(), 3);
+        assert!(Arc::ptr_eq(&pool, &thread_pool(3).unwrap()));
+        assert!(!Arc::ptr_eq(&pool, &thread_pool(2).unwrap()));
+    }
+
+    #[test]
+    fn default_options_match_plain_path_tracing() {
+        let options = RenderOptions::default();
+        assert_eq!(options.light_samples, 0);
+        assert_eq!(options.branch_depth, u32::MAX);
+        assert_eq!(options.ambient_occlusion, 0.0);
+        assert_eq!(options.min_throughput, 0.0);
+    }
+}
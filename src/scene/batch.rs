@@ -0,0 +1,227 @@
+use std::sync::Arc;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::stats;
+
+const LANES: usize = 4; // 每批同时求交的三角面数量
+
+/// 一批三角面的顶点数据，按分量分别存储，便于编译器进行向量化
+#[derive(Clone, Copy)]
+struct Chunk {
+    v0: [[f32; LANES]; 3], // v0 的 x、y、z 分量
+    edge1: [[f32; LANES]; 3], // v1 - v0
+    edge2: [[f32; LANES]; 3], // v2 - v0
+    cull: [bool; LANES], // 是否剔除背面
+}
+
+impl Chunk {
+    // 空位填充为退化三角面，求交时必然不相交
+    const EMPTY: Self = Self {
+        v0: [[0.0; LANES]; 3],
+        edge1: [[0.0; LANES]; 3],
+        edge2: [[0.0; LANES]; 3],
+        cull: [false; LANES],
+    };
+
+    /// 与 Triangle::hit 相同的 Moller-Trumbore 方法，一次计算 LANES 个三角面，
+    /// 返回每个三角面的碰撞时间与重心坐标 v、w，不相交时碰撞时间为正无穷
+    fn intersect(&self, ray: &Ray) -> ([f32; LANES], [f32; LANES], [f32; LANES]) {
+        let (o, d) = (ray.origin, ray.direction);
+        let [v0x, v0y, v0z] = self.v0;
+        let [e1x, e1y, e1z] = self.edge1;
+        let [e2x, e2y, e2z] = self.edge2;
+
+        let mut t = [f32::INFINITY; LANES];
+        let mut v = [0.0; LANES];
+        let mut w = [0.0; LANES];
+        for lane in 0..LANES {
+            // h = ray.direction.cross(edge2)
+            let hx = d.y * e2z[lane] - d.z * e2y[lane];
+            let hy = d.z * e2x[lane] - d.x * e2z[lane];
+            let hz = d.x * e2y[lane] - d.y * e2x[lane];
+            let a = e1x[lane] * hx + e1y[lane] * hy + e1z[lane] * hz;
+            let f = 1.0 / a;
+
+            // s = ray.origin - v0
+            let sx = o.x - v0x[lane];
+            let sy = o.y - v0y[lane];
+            let sz = o.z - v0z[lane];
+            let lane_v = f * (sx * hx + sy * hy + sz * hz);
+
+            // q = s.cross(edge1)
+            let qx = sy * e1z[lane] - sz * e1y[lane];
+            let qy = sz * e1x[lane] - sx * e1z[lane];
+            let qz = sx * e1y[lane] - sy * e1x[lane];
+            let lane_w = f * (d.x * qx + d.y * qy + d.z * qz);
+            let lane_t = f * (e2x[lane] * qx + e2y[lane] * qy + e2z[lane] * qz);
+
+            let inside = a.abs() >= f32::EPSILON
+                && !(self.cull[lane] && a < 0.0)
+                && (0.0..=1.0).contains(&lane_v)
+                && lane_w >= 0.0
+                && lane_v + lane_w <= 1.0;
+            t[lane] = if inside { lane_t } else { f32::INFINITY };
+            v[lane] = lane_v;
+            w[lane] = lane_w;
+        }
+
+        (t, v, w)
+    }
+}
+
+/// 以 SoA 形式存储的一组三角面，用于 BVH 叶子节点的批量求交
+pub struct TriangleBatch {
+    chunks: Vec<Chunk>,
+    triangles: Vec<Arc<dyn Hittable + Sync + Send>>, // 用于在求得最近交点后生成碰撞记录
+}
+
+impl TriangleBatch {
+    /// 所有物体都是可以批量求交的三角面时构建批次，否则返回 None
+    pub fn build(objects: &[Arc<dyn Hittable + Sync + Send>]) -> Option<Self> {
+        let mut chunks = vec![];
+        for group in objects.chunks(LANES) {
+            let mut chunk = Chunk::EMPTY;
+            for (lane, object) in group.iter().enumerate() {
+                let triangle = object.as_triangle().filter(|triangle| triangle.batchable())?;
+                let edge1 = triangle.v1 - triangle.v0;
+                let edge2 = triangle.v2 - triangle.v0;
+                for axis in 0..3 {
+                    chunk.v0[axis][lane] = triangle.v0[axis];
+                    chunk.edge1[axis][lane] = edge1[axis];
+                    chunk.edge2[axis][lane] = edge2[axis];
+                }
+                chunk.cull[lane] = triangle.material.culls_backfaces();
+            }
+            chunks.push(chunk);
+        }
+
+        Some(Self { chunks, triangles: objects.to_vec() })
+    }
+
+    /// 批次中的三角面
+    pub fn objects(&self) -> &[Arc<dyn Hittable + Sync + Send>] {
+        &self.triangles
+    }
+
+    /// 检查光线与批次中的三角面是否相交，返回最近的碰撞
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut closest_t = t_max;
+
+        let mut hits = 0;
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let (t, v, w) = chunk.intersect(ray);
+            hits += t.iter().filter(|&&t| t >= t_min && t <= t_max && t < f32::INFINITY).count() as u64;
+            for lane in 0..LANES {
+                // 不相交时碰撞时间为正无穷，t_max 也为正无穷时需要排除
+                if t[lane] >= t_min && t[lane] <= closest_t && t[lane] < f32::INFINITY {
+                    closest_t = t[lane];
+                    closest = Some((index * LANES + lane, v[lane], w[lane]));
+                }
+            }
+        }
+
+        stats::count(|stats| {
+            stats.triangle_tests += self.triangles.len() as u64;
+            stats.triangle_hits += hits;
+        });
+        closest.map(|(index, v, w)| {
+            let triangle = self.triangles[index].as_triangle().unwrap();
+            triangle.hit_record(ray, closest_t, v, w)
+        })
+    }
+}
+
+/// 一批球体的球心与半径的平方，按分量分别存储
+#[derive(Clone, Copy)]
+struct SphereChunk {
+    center: [[f32; LANES]; 3], // 球心的 x、y、z 分量
+    radius2: [f32; LANES],     // 半径的平方
+}
+
+impl SphereChunk {
+    // 空位的半径平方为负数，判别式必然小于 0
+    const EMPTY: Self = Self { center: [[0.0; LANES]; 3], radius2: [-1.0; LANES] };
+
+    /// 与 Sphere::hit 相同的解析解法，一次计算 LANES 个球体，
+    /// 返回每个球体在 [t_min, t_max] 内最近的碰撞时间，不相交时为正无穷
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> [f32; LANES] {
+        let (o, d) = (ray.origin, ray.direction);
+        let [cx, cy, cz] = self.center;
+
+        let mut t = [f32::INFINITY; LANES];
+        for lane in 0..LANES {
+            // oc = ray.origin - center
+            let ocx = o.x - cx[lane];
+            let ocy = o.y - cy[lane];
+            let ocz = o.z - cz[lane];
+            let half_b = ocx * d.x + ocy * d.y + ocz * d.z;
+            let c = ocx * ocx + ocy * ocy + ocz * ocz - self.radius2[lane];
+            let discriminant = half_b * half_b - c;
+
+            let sqrt_d = discriminant.max(0.0).sqrt();
+            let near = -half_b - sqrt_d;
+            let far = -half_b + sqrt_d;
+            t[lane] = if discriminant <= 0.0 {
+                f32::INFINITY
+            } else if near >= t_min && near <= t_max {
+                near
+            } else if far >= t_min && far <= t_max {
+                far
+            } else {
+                f32::INFINITY
+            };
+        }
+
+        t
+    }
+}
+
+/// 以 SoA 形式存储的一组静止球体，用于 BVH 叶子节点的批量求交
+pub struct SphereBatch {
+    chunks: Vec<SphereChunk>,
+    spheres: Vec<Arc<dyn Hittable + Sync + Send>>, // 用于在求得最近交点后生成碰撞记录
+}
+
+impl SphereBatch {
+    /// 所有物体都是静止的球体时构建批次，否则返回 None
+    pub fn build(objects: &[Arc<dyn Hittable + Sync + Send>]) -> Option<Self> {
+        let mut chunks = vec![];
+        for group in objects.chunks(LANES) {
+            let mut chunk = SphereChunk::EMPTY;
+            for (lane, object) in group.iter().enumerate() {
+                let sphere = object.as_sphere()?;
+                for axis in 0..3 {
+                    chunk.center[axis][lane] = sphere.center[axis];
+                }
+                chunk.radius2[lane] = sphere.radius * sphere.radius;
+            }
+            chunks.push(chunk);
+        }
+
+        Some(Self { chunks, spheres: objects.to_vec() })
+    }
+
+    /// 批次中的球体
+    pub fn objects(&self) -> &[Arc<dyn Hittable + Sync + Send>] {
+        &self.spheres
+    }
+
+    /// 检查光线与批次中的球体是否相交，返回最近的碰撞
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut closest_t = t_max;
+
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let t = chunk.intersect(ray, t_min, closest_t);
+            for (lane, &t) in t.iter().enumerate() {
+                if t <= closest_t && t < f32::INFINITY {
+                    closest_t = t;
+                    closest = Some(index * LANES + lane);
+                }
+            }
+        }
+
+        closest.map(|index| self.spheres[index].as_sphere().unwrap().hit_record(ray, closest_t))
+    }
+}
@@ -1,8 +1,11 @@
 use std::sync::Arc;
 use glam::Vec3;
-use crate::ray::Ray;
-use crate::scene::{HitRecord, Hittable};
+use crate::ray::{Ray, RayPacket};
+use crate::scene::{ConeHit, HitRecord, Hittable};
+use crate::scene::batch::{SphereBatch, TriangleBatch};
+use crate::stats;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy)]
 pub struct AABB {
     pub min: Vec3,
@@ -15,33 +18,26 @@ impl AABB {
     }
 
     // 检查光线是否与包围盒相交，使用 slabs 方法
+    //
+    // 使用光线上预先计算好的方向倒数，三个轴同时计算，不需要分支。
+    // 方向分量为零时倒数为无穷大，对应的 slab 区间为 (-inf, +inf) 或空集，同样能得到正确结果
     pub fn hit(&self, ray: &Ray) -> bool {
-        let (mut t_min, mut t_max) = (f32::MIN, f32::MAX);
-        // 遍历所有轴
-        for i in 0..3 {
-            // 如果沿该轴方向速度为零，则检测是否夹在两个 slab 中间
-            if ray.direction[i].abs() < f32::EPSILON {
-                if ray.origin[i] <= self.min[i] || ray.origin[i] >= self.min[i] {
-                    return false;
-                }
-            } else {
-                let mut t0 = (self.min[i] - ray.origin[i]) / ray.direction[i];
-                let mut t1 = (self.max[i] - ray.origin[i]) / ray.direction[i];
-
-                if t0 > t1 {
-                    std::mem::swap(&mut t0, &mut t1);
-                }
+        stats::count(|stats| stats.aabb_tests += 1);
+        let t0 = (self.min - ray.origin) * ray.inv_direction;
+        let t1 = (self.max - ray.origin) * ray.inv_direction;
 
-                t_min = t_min.max(t0);
-                t_max = t_max.min(t1);
+        let t_min = t0.min(t1).max_element();
+        let t_max = t0.max(t1).min_element();
 
-                if t_min > t_max || t_max <= 0.0 {
-                    return false;
-                }
-            }
-        }
+        t_min <= t_max && t_max > 0.0
+    }
 
-        true
+    // 检查以光线为轴、张角为 spread 的锥体是否与包围盒相交，
+    // 保守地把包围盒扩大其最远处的锥体半径，再与光线求交
+    pub fn hit_cone(&self, ray: &Ray, spread: f32) -> bool {
+        let far = (self.center() - ray.origin).length() + 0.5 * (self.max - self.min).length();
+        let radius = Vec3::splat(0.5 * spread * far);
+        AABB::new(self.min - radius, self.max + radius).hit(ray)
     }
 
     // 合并两个 aabb
@@ -52,44 +48,64 @@ impl AABB {
         }
     }
 
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
     pub fn surface_area_half(&self) -> f32 {
         let [a, b, c] = (self.max - self.min).to_array();
         a * b + b * c + c * a
     }
 }
 
+// 按包围盒在 axis 轴上的最小坐标排序，坐标相同时按序号排序；
+// 坐标为 NaN（例如由非法顶点得到的包围盒）时视为 +inf 排在最后，不会因为无法比较而 panic
+fn sort_along_axis(objects: &mut [(usize, Arc<dyn Hittable + Sync + Send>)], axis: usize) {
+    let key = |object: &Arc<dyn Hittable + Sync + Send>| {
+        let min = object.bounding_box().min[axis];
+        if min.is_nan() { f32::INFINITY } else { min }
+    };
+    objects.sort_by(|(a_index, a), (b_index, b)| key(a).total_cmp(&key(b)).then(a_index.cmp(b_index)));
+}
+
 pub enum BVHNode {
     Internal { left: Box<BVHNode>, right: Box<BVHNode>, bbox: AABB },
     Leaf { objects: Vec<Arc<dyn Hittable + Sync + Send>>, bbox: AABB },
+    // 只包含三角面的叶子，以 SoA 形式批量求交
+    TriangleLeaf { triangles: TriangleBatch, bbox: AABB },
+    // 只包含静止球体的叶子，以 SoA 形式批量求交
+    SphereLeaf { spheres: SphereBatch, bbox: AABB },
 }
 
 impl BVHNode {
     // 构建 BVH
+    //
+    // 物体按包围盒在分割轴上的最小坐标排序，坐标相同时按在 objects 中的位置排序，
+    // 排序结果与之前按其他轴排序的顺序无关，相同的输入总是得到相同的树
     pub fn build(objects: &mut [Arc<dyn Hittable + Sync + Send>], max_objects_per_leaf: usize) -> Self {
+        let mut objects = objects.iter().cloned().enumerate().collect::<Vec<_>>();
+        Self::build_indexed(&mut objects, max_objects_per_leaf)
+    }
+
+    // 物体带有在输入中的序号，作为排序的第二关键字
+    fn build_indexed(objects: &mut [(usize, Arc<dyn Hittable + Sync + Send>)], max_objects_per_leaf: usize) -> Self {
         if objects.len() <= max_objects_per_leaf {
-            let mut bbox = objects[0].bounding_box();
-            for object in objects.iter() {
+            let mut bbox = objects[0].1.bounding_box();
+            for (_, object) in objects.iter() {
                 bbox = bbox.merge(&object.bounding_box());
             }
-            return BVHNode::Leaf {
-                objects: objects.iter().map(|x| x.clone()).collect::<Vec<_>>(),
-                bbox,
-            };
+            return Self::leaf(objects.iter().map(|(_, object)| object.clone()).collect(), bbox);
         }
 
         // 使用表面积启发确定分割位置
         let (mut best_axis, mut best_division_index, mut min_cost) = (0, 0, f32::MAX);
         // 遍历所有轴
         for axis in 0..3 {
-            objects.sort_by(|a, b| {
-                let a_center = a.bounding_box().min[axis];
-                let b_center = b.bounding_box().min[axis];
-                a_center.partial_cmp(&b_center).unwrap()
-            });
+            sort_along_axis(objects, axis);
             // 从右向左，计算右子树的代价
             let mut cost_r2l = vec![];
             let mut bbox = AABB::new(Vec3::ZERO, Vec3::ZERO);
-            for (index, object) in objects[1..].iter().rev().enumerate() {
+            for (index, (_, object)) in objects[1..].iter().rev().enumerate() {
                 bbox = bbox.merge(&object.bounding_box());
                 cost_r2l.push(bbox.surface_area_half() * (index + 1) as f32);
             }
@@ -97,7 +113,7 @@ impl BVHNode {
             bbox = AABB::new(Vec3::ZERO, Vec3::ZERO);
             // 从左向右，计算整体代价
             for i in 0..objects.len() - 1 {
-                bbox = bbox.merge(&objects[i].bounding_box());
+                bbox = bbox.merge(&objects[i].1.bounding_box());
                 let cost = bbox.surface_area_half() * (i + 1) as f32 + cost_r2l[i];
                 if cost < min_cost {
                     (best_axis, best_division_index, min_cost) = (axis, i, cost);
@@ -105,14 +121,11 @@ impl BVHNode {
             }
         }
 
-        objects.sort_by(|a, b| {
-            let a_center = a.bounding_box().min[best_axis];
-            let b_center = b.bounding_box().min[best_axis];
-            a_center.partial_cmp(&b_center).unwrap()
-        });
+        sort_along_axis(objects, best_axis);
 
-        let left = BVHNode::build(&mut objects[..best_division_index + 1].to_vec(), max_objects_per_leaf);
-        let right = BVHNode::build(&mut objects[best_division_index + 1..].to_vec(), max_objects_per_leaf);
+        let (left, right) = objects.split_at_mut(best_division_index + 1);
+        let left = BVHNode::build_indexed(left, max_objects_per_leaf);
+        let right = BVHNode::build_indexed(right, max_objects_per_leaf);
 
         let bbox = left.bbox().merge(&right.bbox());
 
@@ -124,11 +137,76 @@ impl BVHNode {
         match self {
             BVHNode::Internal { bbox, .. } => *bbox,
             BVHNode::Leaf { bbox, .. } => *bbox,
+            BVHNode::TriangleLeaf { bbox, .. } => *bbox,
+            BVHNode::SphereLeaf { bbox, .. } => *bbox,
+        }
+    }
+
+    // 保持树的结构不变，自底向上重新计算各节点的包围盒，
+    // `replace` 对叶子中的每个物体调用一次，返回 Some 时用返回的物体替换原物体
+    pub fn refit(
+        &mut self,
+        replace: &impl Fn(&Arc<dyn Hittable + Sync + Send>) -> Option<Arc<dyn Hittable + Sync + Send>>,
+    ) -> AABB {
+        let objects = match self {
+            BVHNode::Internal { left, right, bbox } => {
+                *bbox = left.refit(replace).merge(&right.refit(replace));
+                return *bbox;
+            }
+            BVHNode::Leaf { objects, .. } => objects.clone(),
+            BVHNode::TriangleLeaf { triangles, .. } => triangles.objects().to_vec(),
+            BVHNode::SphereLeaf { spheres, .. } => spheres.objects().to_vec(),
+        };
+        let objects = objects.iter()
+            .map(|object| replace(object).unwrap_or_else(|| object.clone()))
+            .collect::<Vec<_>>();
+
+        let mut bbox = objects[0].bounding_box();
+        for object in objects.iter() {
+            bbox = bbox.merge(&object.bounding_box());
+        }
+        // 替换后的物体可能不再能批量求交，因此重新构建叶子
+        *self = Self::leaf(objects, bbox);
+        bbox
+    }
+
+    // 构建叶子，物体全部是三角面或全部是静止球体时批量求交
+    fn leaf(objects: Vec<Arc<dyn Hittable + Sync + Send>>, bbox: AABB) -> Self {
+        if let Some(triangles) = TriangleBatch::build(&objects) {
+            BVHNode::TriangleLeaf { triangles, bbox }
+        } else if let Some(spheres) = SphereBatch::build(&objects) {
+            BVHNode::SphereLeaf { spheres, bbox }
+        } else {
+            BVHNode::Leaf { objects, bbox }
+        }
+    }
+
+    // 表面积启发的代价，即各节点包围盒的表面积之和（叶子按物体数量加权），
+    // 物体移动后节点包围盒相互重叠变大，代价随之增加。不除以根节点的表面积，
+    // 避免物体移出场景、根节点变大时代价反而下降
+    pub fn sah_cost(&self) -> f32 {
+        match self {
+            BVHNode::Internal { left, right, bbox } => {
+                bbox.surface_area_half() + left.sah_cost() + right.sah_cost()
+            }
+            BVHNode::Leaf { objects, bbox } => bbox.surface_area_half() * objects.len() as f32,
+            BVHNode::TriangleLeaf { triangles, bbox } => {
+                bbox.surface_area_half() * triangles.objects().len() as f32
+            }
+            BVHNode::SphereLeaf { spheres, bbox } => {
+                bbox.surface_area_half() * spheres.objects().len() as f32
+            }
         }
     }
 
     // 检查光线与 BVH 中的物体是否相交
-    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.hit_counted(ray, t_min, t_max, &mut 0)
+    }
+
+    // 与 hit 相同，`nodes` 累加检查过包围盒的节点数，用于显示遍历的开销
+    pub fn hit_counted(&self, ray: &Ray, t_min: f32, t_max: f32, nodes: &mut u32) -> Option<HitRecord<'_>> {
+        *nodes += 1;
         if !self.bbox().hit(ray) {
             return None;
         }
@@ -139,12 +217,12 @@ impl BVHNode {
                 let mut closest_t = t_max;
 
 
-                if let Some(hit) = left.hit(ray, t_min, closest_t) {
+                if let Some(hit) = left.hit_counted(ray, t_min, closest_t, nodes) {
                     closest_hit = Some(hit);
                     closest_t = hit.t;
                 }
 
-                if let Some(hit) = right.hit(ray, t_min, closest_t) {
+                if let Some(hit) = right.hit_counted(ray, t_min, closest_t, nodes) {
                     closest_hit = Some(hit);
                 }
 
@@ -163,6 +241,210 @@ impl BVHNode {
 
                 closest_hit
             }
+            BVHNode::TriangleLeaf { triangles, .. } => triangles.hit(ray, t_min, t_max),
+            BVHNode::SphereLeaf { spheres, .. } => spheres.hit(ray, t_min, t_max),
         }
     }
-}
\ No newline at end of file
+
+    // 成组检查光线与 BVH 中物体是否相交，`rays` 为组中各光线的标量形式，`closest` 与 `hits`
+    // 保存各光线目前最近的碰撞时间与碰撞，序号小于 `first` 的光线已知不与本节点相交。
+    // 相邻光线经过的节点大多相同，因此每个节点只需用 SoA 数据找到第一条与包围盒相交的光线，
+    // 子节点从这条光线开始检查；到达叶子时才逐条检查剩下的光线，使用与 hit 相同的求交
+    pub fn hit_packet<'a>(
+        &'a self,
+        packet: &RayPacket,
+        rays: &[Ray],
+        first: usize,
+        t_min: f32,
+        closest: &mut [f32],
+        hits: &mut [Option<HitRecord<'a>>],
+    ) {
+        let bbox = self.bbox();
+        if packet.misses_box(bbox.min, bbox.max) {
+            return;
+        }
+        let [dx, dy, dz] = &packet.inv_direction;
+        let (min, max) = (bbox.min - packet.origin, bbox.max - packet.origin);
+        let enters = |i: usize, closest: f32| {
+            stats::count(|stats| stats.aabb_tests += 1);
+            let (tx0, tx1) = (min.x * dx[i], max.x * dx[i]);
+            let (ty0, ty1) = (min.y * dy[i], max.y * dy[i]);
+            let (tz0, tz1) = (min.z * dz[i], max.z * dz[i]);
+            let near = tx0.min(tx1).max(ty0.min(ty1)).max(tz0.min(tz1));
+            let far = tx0.max(tx1).min(ty0.max(ty1)).min(tz0.max(tz1));
+            near <= far && far > 0.0 && near < closest
+        };
+        let Some(first) = (first..rays.len()).find(|&i| enters(i, closest[i])) else {
+            return;
+        };
+
+        match self {
+            BVHNode::Internal { left, right, .. } => {
+                // 先遍历沿光线方向较近的子节点，使较远的子节点可以被已有的碰撞剔除
+                let direction = rays[first].direction;
+                let distance = |node: &BVHNode| (node.bbox().center() - packet.origin).dot(direction);
+                let (near, far) = if distance(left) <= distance(right) { (left, right) } else { (right, left) };
+                near.hit_packet(packet, rays, first, t_min, closest, hits);
+                far.hit_packet(packet, rays, first, t_min, closest, hits);
+            }
+            _ => {
+                for i in first..rays.len() {
+                    if !enters(i, closest[i]) {
+                        continue;
+                    }
+                    if let Some(hit) = self.hit(&rays[i], t_min, closest[i]) {
+                        closest[i] = hit.t;
+                        hits[i] = Some(hit);
+                    }
+                }
+            }
+        }
+    }
+
+    // 锥体求交，见 Scene::hit_cone；在锥体处细的三角面加入 layers，其余物体与光线求交，最近的交点保存在 hit
+    pub fn hit_cone<'a>(
+        &'a self,
+        ray: &Ray,
+        spread: f32,
+        t_min: f32,
+        hit: &mut Option<HitRecord<'a>>,
+        layers: &mut Vec<ConeHit<'a>>,
+    ) {
+        if !self.bbox().hit_cone(ray, spread) {
+            return;
+        }
+
+        let objects = match self {
+            BVHNode::Internal { left, right, .. } => {
+                left.hit_cone(ray, spread, t_min, hit, layers);
+                right.hit_cone(ray, spread, t_min, hit, layers);
+                return;
+            }
+            BVHNode::Leaf { objects, .. } => objects.as_slice(),
+            BVHNode::TriangleLeaf { triangles, .. } => triangles.objects(),
+            BVHNode::SphereLeaf { spheres, .. } => spheres.objects(),
+        };
+        for object in objects {
+            match object.as_triangle() {
+                Some(triangle) if triangle.is_thin(ray, spread) => layers.extend(triangle.cone_coverage(ray, spread)),
+                _ => {
+                    let t_max = hit.map_or(f32::INFINITY, |hit| hit.t);
+                    if let Some(object_hit) = object.hit(ray, t_min, t_max) {
+                        *hit = Some(object_hit);
+                    }
+                }
+            }
+        }
+    }
+
+    // 收集光线与 BVH 中物体的交点，hits 按距离升序保存最近的至多 k 个交点，
+    // 与 hit 不同，不会把 t_max 缩小到最近的交点，只有已经收集满 k 个后才缩小到其中最远的一个
+    pub fn hit_k<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, k: usize, hits: &mut Vec<HitRecord<'a>>) {
+        if k == 0 || !self.bbox().hit(ray) {
+            return;
+        }
+
+        let objects = match self {
+            BVHNode::Internal { left, right, .. } => {
+                left.hit_k(ray, t_min, t_max, k, hits);
+                right.hit_k(ray, t_min, t_max, k, hits);
+                return;
+            }
+            BVHNode::Leaf { objects, .. } => objects.as_slice(),
+            // 批量求交只返回最近的交点，因此逐个物体求交
+            BVHNode::TriangleLeaf { triangles, .. } => triangles.objects(),
+            BVHNode::SphereLeaf { spheres, .. } => spheres.objects(),
+        };
+        for object in objects {
+            collect_hits(object.as_ref(), ray, t_min, t_max, k, hits);
+        }
+    }
+}
+
+// 收集光线与单个物体的交点，插入按距离升序排列的 hits 中并只保留最近的 k 个。
+// 物体每次只返回最近的交点，因此从上一个交点之后继续求交，得到球体的入射点和出射点等多个交点
+pub(super) fn collect_hits<'a>(
+    object: &'a (dyn Hittable + Sync + Send),
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    k: usize,
+    hits: &mut Vec<HitRecord<'a>>,
+) {
+    // 同一物体上相继的两个交点至少相距该距离，避免在同一交点处反复求交
+    const STEP: f32 = 0.001;
+    let mut t_start = t_min;
+    loop {
+        let t_end = if hits.len() >= k { hits[k - 1].t } else { t_max };
+        let Some(hit) = object.hit(ray, t_start, t_end) else {
+            break;
+        };
+        let index = hits.partition_point(|other| other.t <= hit.t);
+        hits.insert(index, hit);
+        hits.truncate(k);
+        t_start = hit.t + STEP;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::scene::primitive::{Sphere, Triangle};
+
+    // 按先序遍历列出各节点：内部节点为 None，叶子为其中物体在输入中的序号
+    fn topology(node: &BVHNode, objects: &[Arc<dyn Hittable + Sync + Send>], nodes: &mut Vec<Option<Vec<usize>>>) {
+        let indices = |leaf: &[Arc<dyn Hittable + Sync + Send>]| {
+            leaf.iter()
+                .map(|object| objects.iter().position(|input| Arc::ptr_eq(input, object)).unwrap())
+                .collect()
+        };
+        match node {
+            BVHNode::Internal { left, right, .. } => {
+                nodes.push(None);
+                topology(left, objects, nodes);
+                topology(right, objects, nodes);
+            }
+            BVHNode::Leaf { objects: leaf, .. } => nodes.push(Some(indices(leaf))),
+            BVHNode::TriangleLeaf { triangles, .. } => nodes.push(Some(indices(triangles.objects()))),
+            BVHNode::SphereLeaf { spheres, .. } => nodes.push(Some(indices(spheres.objects()))),
+        }
+    }
+
+    #[test]
+    fn building_twice_gives_the_same_tree() {
+        // 许多物体的包围盒在某些轴上的最小坐标相同，甚至完全重合，排序只能依靠序号区分
+        let mut objects: Vec<Arc<dyn Hittable + Sync + Send>> = vec![];
+        for i in 0..40 {
+            let x = (i % 4) as f32;
+            let vertices = vec![Vec3::new(x, 0.0, 0.0), Vec3::new(x + 1.0, 0.0, 0.0), Vec3::new(x, 1.0, (i % 3) as f32)];
+            objects.push(Arc::new(Triangle::new(vertices, vec![], vec![], Material::PLASTER)));
+        }
+        for i in 0..10 {
+            objects.push(Arc::new(Sphere::new(Vec3::new((i % 2) as f32, 0.5, 0.0), 0.5, Material::PLASTER)));
+        }
+
+        let build = || {
+            let tree = BVHNode::build(&mut objects.clone(), 4);
+            let mut nodes = vec![];
+            topology(&tree, &objects, &mut nodes);
+            nodes
+        };
+        let first = build();
+        assert!(first.iter().filter(|node| node.is_none()).count() > 1);
+        assert_eq!(first, build());
+
+        // 坐标相同时按序号排序，沿某个轴排序的结果与之前按哪个轴排过序无关
+        let indexed = objects.iter().cloned().enumerate().collect::<Vec<_>>();
+        for axis in 0..3 {
+            let order = |previous: usize| {
+                let mut objects = indexed.clone();
+                sort_along_axis(&mut objects, previous);
+                sort_along_axis(&mut objects, axis);
+                objects.iter().map(|(index, _)| *index).collect::<Vec<_>>()
+            };
+            assert_eq!(order(0), order(1));
+            assert_eq!(order(1), order(2));
+        }
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use glam::{Mat4, Quat, Vec2, Vec3};
+use serde::Deserialize;
+use crate::material::Material;
+use crate::texture::Texture;
+use super::primitive::Triangle;
+use super::{invalid_data, push_triangle, LoadedObj, MeshTransform, Scene};
+
+// glTF 文档中用到的部分，动画、蒙皮、相机等其余字段忽略
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    scenes: Vec<SceneDef>,
+    nodes: Vec<Node>,
+    meshes: Vec<Mesh>,
+    accessors: Vec<Accessor>,
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<Buffer>,
+    materials: Vec<MaterialDef>,
+    textures: Vec<TextureDef>,
+    images: Vec<Image>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SceneDef {
+    nodes: Vec<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Node {
+    name: Option<String>,
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    matrix: Option<[f32; 16]>, // 按列优先顺序排列，与 TRS 互斥
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>, // 四元数 (x, y, z, w)
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Mesh {
+    name: Option<String>,
+    primitives: Vec<MeshPrimitive>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MeshPrimitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    #[serde(default = "MeshPrimitive::default_mode")]
+    mode: u32,
+}
+
+impl MeshPrimitive {
+    const TRIANGLES: u32 = 4;
+    const TRIANGLE_STRIP: u32 = 5;
+    const TRIANGLE_FAN: u32 = 6;
+
+    fn default_mode() -> u32 {
+        Self::TRIANGLES
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>, // 为 None 时所有分量为 0
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    uri: Option<String>, // 为 None 时为 .glb 中的 BIN 块
+    byte_length: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct MaterialDef {
+    name: Option<String>,
+    pbr_metallic_roughness: PbrMetallicRoughness,
+    normal_texture: Option<TextureInfo>,
+    occlusion_texture: Option<TextureInfo>,
+    emissive_texture: Option<TextureInfo>,
+    emissive_factor: [f32; 3],
+    alpha_mode: Option<String>, // OPAQUE（默认）、MASK 或 BLEND
+    alpha_cutoff: Option<f32>,
+    double_sided: bool,
+    extensions: MaterialExtensions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PbrMetallicRoughness {
+    base_color_factor: [f32; 4],
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    metallic_roughness_texture: Option<TextureInfo>,
+}
+
+// 规范规定的默认值，没有 pbrMetallicRoughness 的材质为白色、完全金属、完全粗糙
+impl Default for PbrMetallicRoughness {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextureInfo {
+    index: usize,
+    #[serde(default)]
+    tex_coord: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrength>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmissiveStrength {
+    emissive_strength: f32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TextureDef {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Image {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+}
+
+// 已解析的文档与全部缓冲区数据
+struct Asset<'a> {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+    base: &'a Path,
+    images: Vec<Option<Texture>>, // 已加载的图像，多个材质引用同一图像时只解码一次
+}
+
+impl Scene {
+    /// 将 glTF 2.0 模型（.gltf 或 .glb）加载到场景中，返回因退化而跳过的三角面数量
+    ///
+    /// 导入默认场景中所有节点的网格，节点的变换逐层相乘后再左乘 `transform`；缓冲区与图像可以是外部文件、
+    /// base64 的 data URI 或 .glb 的 BIN 块。只支持三角形图元（含条带与扇形），读取 POSITION、NORMAL、
+    /// TEXCOORD_0 与 COLOR_0。金属度-粗糙度材质映射到 `Material`：基础颜色（及贴图）为漫反射，
+    /// metallic、roughness 直接对应，非金属部分的镜面反射为 0.04，自发光包括 KHR_materials_emissive_strength，
+    /// MASK 使用基础颜色贴图的 alpha 镂空，BLEND 的 alpha 作为不透明度。材质按名字登记到材质库。
+    /// 金属度-粗糙度、法线、遮蔽与自发光贴图不支持，会被忽略并给出警告
+    pub fn add_gltf(&mut self, file_path: &str, transform: Mat4) -> io::Result<usize> {
+        let gltf = Self::load_gltf(file_path, transform)?;
+        Ok(self.merge_obj(gltf))
+    }
+
+    // 读取并解析 glTF 文件，转换为三角形，不修改场景
+    fn load_gltf(file_path: &str, transform: Mat4) -> io::Result<LoadedObj> {
+        let bytes = fs::read(file_path)?;
+        let (json, bin) = if bytes.starts_with(b"glTF") {
+            parse_glb(&bytes)?
+        } else {
+            (&bytes[..], None)
+        };
+        let document: Document = serde_json::from_slice(json).map_err(|error| invalid_data(error.to_string()))?;
+        let base = Path::new(file_path).parent().unwrap_or(Path::new(""));
+
+        let buffers = document.buffers.iter().enumerate().map(|(index, buffer)| {
+            let data = match (&buffer.uri, bin) {
+                (Some(uri), _) => read_uri(uri, base)?,
+                (None, Some(bin)) if index == 0 => bin.to_vec(),
+                (None, _) => return Err(invalid_data(format!("Buffer {index} has no data"))),
+            };
+            if data.len() < buffer.byte_length {
+                return Err(invalid_data(format!("Buffer {index} is shorter than its byteLength")));
+            }
+            Ok(data)
+        }).collect::<io::Result<Vec<_>>>()?;
+        let images = vec![None; document.images.len()];
+        let mut asset = Asset { document, buffers, base, images };
+
+        let definitions = std::mem::take(&mut asset.document.materials);
+        let materials = definitions.iter().enumerate().map(|(index, definition)| {
+            // 没有名字的材质按序号命名
+            let name = definition.name.clone().unwrap_or_else(|| format!("material_{index}"));
+            let material = asset.material(definition, &name)?;
+            Ok((Arc::<str>::from(name.as_str()), Arc::new(material)))
+        }).collect::<io::Result<Vec<_>>>()?;
+        // 规范规定的默认材质
+        let default_material = (
+            Arc::<str>::from("default"),
+            Arc::new(asset.material(&MaterialDef::default(), "default")?),
+        );
+
+        // 依次遍历根节点，每个节点只访问一次，避免非法文件中的环导致无限递归
+        let document = &asset.document;
+        let roots = match document.scene.or((!document.scenes.is_empty()).then_some(0)) {
+            Some(scene) => document.scenes.get(scene)
+                .ok_or_else(|| invalid_data(format!("Scene {scene} does not exist")))?
+                .nodes.clone(),
+            None => {
+                // 没有场景时导入所有不是其他节点的子节点的节点
+                let children = document.nodes.iter().flat_map(|node| node.children.iter().copied())
+                    .collect::<Vec<_>>();
+                (0..document.nodes.len()).filter(|index| !children.contains(index)).collect()
+            }
+        };
+        let mut visited = vec![false; document.nodes.len()];
+        let mut instances = vec![];
+        let mut stack = roots.into_iter().rev().map(|node| (node, transform)).collect::<Vec<_>>();
+        while let Some((index, parent)) = stack.pop() {
+            let node = document.nodes.get(index)
+                .ok_or_else(|| invalid_data(format!("Node {index} does not exist")))?;
+            if std::mem::replace(&mut visited[index], true) {
+                continue;
+            }
+            let world = parent * node.local_transform();
+            if let Some(mesh) = node.mesh {
+                instances.push((index, mesh, world));
+            }
+            stack.extend(node.children.iter().rev().map(|child| (*child, world)));
+        }
+
+        let mut triangles = vec![];
+        let mut degenerate = 0;
+        for (node_index, mesh_index, world) in instances {
+            let mesh = document.meshes.get(mesh_index)
+                .ok_or_else(|| invalid_data(format!("Mesh {mesh_index} does not exist")))?;
+            let object_name: Arc<str> = Arc::from(
+                document.nodes[node_index].name.clone()
+                    .or_else(|| mesh.name.clone())
+                    .unwrap_or_else(|| format!("mesh_{mesh_index}")),
+            );
+            let mesh_transform = MeshTransform::new(world);
+
+            for primitive in &mesh.primitives {
+                if !matches!(primitive.mode,
+                    MeshPrimitive::TRIANGLES | MeshPrimitive::TRIANGLE_STRIP | MeshPrimitive::TRIANGLE_FAN) {
+                    eprintln!("Warning: glTF primitive mode {} of mesh {mesh_index} is not supported, skipped", primitive.mode);
+                    continue;
+                }
+                let (material_name, material) = match primitive.material {
+                    Some(material) => materials.get(material)
+                        .ok_or_else(|| invalid_data(format!("Material {material} does not exist")))?,
+                    None => &default_material,
+                };
+                let attribute = |name: &str, components: &[usize]| -> io::Result<Option<Vec<f32>>> {
+                    match primitive.attributes.get(name) {
+                        Some(&index) => {
+                            let (values, count) = asset.read_accessor(index)?;
+                            if !components.contains(&count) {
+                                return Err(invalid_data(format!("Attribute {name} has {count} components")));
+                            }
+                            Ok(Some(values.into_iter().map(|value| value as f32).collect()))
+                        }
+                        None => Ok(None),
+                    }
+                };
+                let positions = attribute("POSITION", &[3])?
+                    .ok_or_else(|| invalid_data(format!("Mesh {mesh_index} has no POSITION attribute")))?;
+                let normals = attribute("NORMAL", &[3])?;
+                let texcoords = attribute("TEXCOORD_0", &[2])?;
+                let color_components = primitive.attributes.get("COLOR_0")
+                    .and_then(|&index| asset.document.accessors.get(index))
+                    .map_or(3, |accessor| if accessor.kind == "VEC4" { 4 } else { 3 });
+                let colors = attribute("COLOR_0", &[color_components])?;
+
+                let vertex_count = positions.len() / 3;
+                let indices = match primitive.indices {
+                    Some(index) => asset.read_accessor(index)?.0.into_iter().map(|index| index as usize).collect(),
+                    None => (0..vertex_count).collect::<Vec<_>>(),
+                };
+                if let Some(index) = indices.iter().find(|index| **index >= vertex_count) {
+                    return Err(invalid_data(format!("Vertex index {index} is out of range")));
+                }
+                let faces: Vec<[usize; 3]> = match primitive.mode {
+                    MeshPrimitive::TRIANGLE_STRIP => indices.windows(3).enumerate()
+                        .map(|(i, w)| if i % 2 == 0 { [w[0], w[1], w[2]] } else { [w[1], w[0], w[2]] })
+                        .collect(),
+                    MeshPrimitive::TRIANGLE_FAN => indices.windows(2).skip(1)
+                        .map(|w| [indices[0], w[0], w[1]])
+                        .collect(),
+                    _ => indices.chunks_exact(3).map(|w| [w[0], w[1], w[2]]).collect(),
+                };
+
+                for face in faces {
+                    let face = mesh_transform.face(face);
+                    let vertices = face.iter()
+                        .map(|i| mesh_transform.point(Vec3::from_slice(&positions[i * 3..i * 3 + 3])))
+                        .collect();
+                    let normals = normals.as_ref().map_or(vec![], |normals| face.iter()
+                        .map(|i| mesh_transform.normal(Vec3::from_slice(&normals[i * 3..i * 3 + 3])))
+                        .collect());
+                    // glTF 的 v = 0 为图像最上面一行，与 OBJ 相反
+                    let texcoords = texcoords.as_ref().map_or(vec![], |texcoords| face.iter()
+                        .map(|i| Vec2::new(texcoords[i * 2], 1.0 - texcoords[i * 2 + 1]))
+                        .collect());
+                    let colors = colors.as_ref().map_or(vec![], |colors| face.iter()
+                        .map(|i| Vec3::from_slice(&colors[i * color_components..i * color_components + 3]))
+                        .collect());
+
+                    let triangle = Triangle::new(vertices, normals, texcoords, material.clone())
+                        .with_colors(colors);
+                    push_triangle(&mut triangles, &mut degenerate, triangle, Some(material_name), &object_name);
+                }
+            }
+        }
+
+        let materials = materials.into_iter()
+            .map(|(name, material)| (name.to_string(), material))
+            .collect();
+        Ok(LoadedObj { file_path: file_path.to_string(), materials, triangles, degenerate })
+    }
+}
+
+impl Node {
+    fn local_transform(&self) -> Mat4 {
+        match self.matrix {
+            Some(matrix) => Mat4::from_cols_array(&matrix),
+            None => Mat4::from_scale_rotation_translation(
+                self.scale.map_or(Vec3::ONE, Vec3::from),
+                self.rotation.map_or(Quat::IDENTITY, Quat::from_array),
+                self.translation.map_or(Vec3::ZERO, Vec3::from),
+            ),
+        }
+    }
+}
+
+impl Asset<'_> {
+    // 将 glTF 材质映射为 Material，`name` 只用于警告信息
+    fn material(&mut self, definition: &MaterialDef, name: &str) -> io::Result<Material> {
+        let pbr = &definition.pbr_metallic_roughness;
+        let unsupported = [
+            ("metallicRoughnessTexture", &pbr.metallic_roughness_texture),
+            ("normalTexture", &definition.normal_texture),
+            ("occlusionTexture", &definition.occlusion_texture),
+            ("emissiveTexture", &definition.emissive_texture),
+        ];
+        for (texture, _) in unsupported.iter().filter(|(_, info)| info.is_some()) {
+            eprintln!("Warning: {texture} of glTF material {name} is not supported, ignored");
+        }
+
+        let [r, g, b, alpha] = pbr.base_color_factor;
+        let diffuse_texture = match &pbr.base_color_texture {
+            Some(info) if info.tex_coord != 0 => {
+                eprintln!("Warning: baseColorTexture of glTF material {name} uses TEXCOORD_{}, ignored", info.tex_coord);
+                None
+            }
+            Some(info) => Some(self.texture(info.index)?),
+            None => None,
+        };
+        let (alpha_texture, alpha_cutoff, dissolve) = match definition.alpha_mode.as_deref() {
+            Some("MASK") => (diffuse_texture.clone(), definition.alpha_cutoff.unwrap_or(Material::ALPHA_CUTOFF), 1.0),
+            Some("BLEND") => (diffuse_texture.clone(), Material::ALPHA_CUTOFF, alpha.clamp(0.0, 1.0)),
+            _ => (None, Material::ALPHA_CUTOFF, 1.0),
+        };
+        let emissive_strength = definition.extensions.emissive_strength.as_ref()
+            .map_or(1.0, |extension| extension.emissive_strength);
+
+        Ok(Material {
+            ambient: Vec3::ZERO,
+            diffuse: Vec3::new(r, g, b),
+            diffuse_roughness: 0.0,
+            diffuse_texture,
+            solid_texture: None,
+            ambient_texture: None,
+            specular_texture: None,
+            bump_texture: None,
+            bump_multiplier: 1.0,
+            alpha_texture,
+            alpha_cutoff,
+            specular: Vec3::splat(0.04), // 非金属的法向反射率，对应折射率 1.5
+            emissive: Vec3::from(definition.emissive_factor),
+            emissive_strength,
+            transmission_filter: Vec3::ONE,
+            dissolve,
+            specular_exponent: 0.0,
+            roughness: Some(pbr.roughness_factor.clamp(0.0, 1.0)),
+            metallic: pbr.metallic_factor.clamp(0.0, 1.0),
+            optical_density: 1.0, // BLEND 的半透明不折射光线
+            illumination_model: None,
+            cull_backfaces: false,
+            thin_film: None,
+            anisotropy: None,
+            subsurface_mean_free_path: None,
+            medium: None,
+            transmission_roughness: 0.0,
+            abbe_number: None,
+            two_sided: definition.double_sided,
+        })
+    }
+
+    // 第 index 个纹理引用的图像，第一次使用时解码
+    fn texture(&mut self, index: usize) -> io::Result<Texture> {
+        let source = self.document.textures.get(index)
+            .ok_or_else(|| invalid_data(format!("Texture {index} does not exist")))?
+            .source
+            .ok_or_else(|| invalid_data(format!("Texture {index} has no source image")))?;
+        if let Some(texture) = self.images.get(source).cloned().flatten() {
+            return Ok(texture);
+        }
+        let image = self.document.images.get(source)
+            .ok_or_else(|| invalid_data(format!("Image {source} does not exist")))?;
+        let bytes = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => read_uri(uri, self.base)?,
+            (None, Some(view)) => self.buffer_view(view)?.to_vec(),
+            (None, None) => return Err(invalid_data(format!("Image {source} has no data"))),
+        };
+        let texture = Texture::load_from_memory(&bytes)
+            .map_err(|error| invalid_data(format!("Failed to decode image {source}: {error}")))?;
+        self.images[source] = Some(texture.clone());
+        Ok(texture)
+    }
+
+    fn buffer_view(&self, index: usize) -> io::Result<&[u8]> {
+        let view = self.document.buffer_views.get(index)
+            .ok_or_else(|| invalid_data(format!("Buffer view {index} does not exist")))?;
+        self.buffers.get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset.checked_add(view.byte_length)?))
+            .ok_or_else(|| invalid_data(format!("Buffer view {index} is out of range")))
+    }
+
+    // 读取访问器的全部分量，返回按元素依次排列的分量值与每个元素的分量数；
+    // 整数分量在 normalized 时换算到 [0, 1] 或 [-1, 1]，否则保持原值（例如顶点索引）
+    fn read_accessor(&self, index: usize) -> io::Result<(Vec<f64>, usize)> {
+        let accessor = self.document.accessors.get(index)
+            .ok_or_else(|| invalid_data(format!("Accessor {index} does not exist")))?;
+        if accessor.sparse.is_some() {
+            return Err(invalid_data(format!("Sparse accessor {index} is not supported")));
+        }
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            kind => return Err(invalid_data(format!("Accessor type {kind} is not supported"))),
+        };
+        let (size, max) = match accessor.component_type {
+            5120 => (1, i8::MAX as f64),
+            5121 => (1, u8::MAX as f64),
+            5122 => (2, i16::MAX as f64),
+            5123 => (2, u16::MAX as f64),
+            5125 => (4, u32::MAX as f64),
+            5126 => (4, 1.0),
+            other => return Err(invalid_data(format!("Component type {other} is not supported"))),
+        };
+        // count 来自文件，计算长度与偏移时检查溢出
+        let length = accessor.count.checked_mul(components)
+            .ok_or_else(|| invalid_data(format!("Accessor {index} is out of range")))?;
+        let Some(view_index) = accessor.buffer_view else {
+            return Ok((vec![0.0; length], components));
+        };
+        let view = self.buffer_view(view_index)?;
+        let stride = self.document.buffer_views[view_index].byte_stride.unwrap_or(size * components);
+        let element_size = size * components;
+        if let Some(last) = accessor.count.checked_sub(1) {
+            let end = last.checked_mul(stride)
+                .and_then(|offset| offset.checked_add(accessor.byte_offset))
+                .and_then(|offset| offset.checked_add(element_size));
+            if end.is_none_or(|end| end > view.len()) {
+                return Err(invalid_data(format!("Accessor {index} is out of range")));
+            }
+        }
+
+        let mut values = Vec::with_capacity(length);
+        for element in 0..accessor.count {
+            let start = accessor.byte_offset + element * stride;
+            for bytes in view[start..start + element_size].chunks_exact(size) {
+                let value = match accessor.component_type {
+                    5120 => bytes[0] as i8 as f64,
+                    5121 => bytes[0] as f64,
+                    5122 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                };
+                values.push(if accessor.normalized { (value / max).max(-1.0) } else { value });
+            }
+        }
+        Ok((values, components))
+    }
+}
+
+// 拆分 .glb 文件，返回 JSON 块与可选的 BIN 块
+fn parse_glb(bytes: &[u8]) -> io::Result<(&[u8], Option<&[u8]>)> {
+    const JSON: u32 = 0x4E4F_534A;
+    const BIN: u32 = 0x004E_4942;
+    let read_u32 = |offset: usize| {
+        bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if read_u32(4) != Some(2) {
+        return Err(invalid_data("Only glTF 2.0 binary files are supported"));
+    }
+    let length = (read_u32(8).unwrap_or(0) as usize).min(bytes.len());
+    let (mut json, mut bin) = (None, None);
+    let mut offset = 12;
+    while let (Some(chunk_length), Some(chunk_type)) = (read_u32(offset), read_u32(offset + 4)) {
+        let start = offset + 8;
+        let end = start + chunk_length as usize;
+        let chunk = bytes.get(start..end.min(length))
+            .filter(|chunk| chunk.len() == chunk_length as usize)
+            .ok_or_else(|| invalid_data("Truncated .glb chunk"))?;
+        match chunk_type {
+            JSON if json.is_none() => json = Some(chunk),
+            BIN if bin.is_none() => bin = Some(chunk),
+            _ => {}
+        }
+        offset = end;
+    }
+    Ok((json.ok_or_else(|| invalid_data("Missing JSON chunk in .glb file"))?, bin))
+}
+
+// 读取 URI 指向的数据：base64 的 data URI，或相对于 glTF 文件所在目录的文件（可能包含百分号转义）
+fn read_uri(uri: &str, base: &Path) -> io::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data.split_once(";base64,")
+            .ok_or_else(|| invalid_data("Only base64 data URIs are supported"))?;
+        return decode_base64(encoded).ok_or_else(|| invalid_data("Invalid base64 data URI"));
+    }
+    fs::read(base.join(percent_decode(uri)))
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(accessor: &str) -> Asset<'static> {
+        let json = format!(
+            r#"{{ "buffers": [{{ "byteLength": 12 }}],
+                 "bufferViews": [{{ "buffer": 0, "byteLength": 12 }}],
+                 "accessors": [{accessor}] }}"#
+        );
+        let document = serde_json::from_str(&json).unwrap();
+        Asset { document, buffers: vec![vec![0; 12]], base: Path::new(""), images: vec![] }
+    }
+
+    #[test]
+    fn accessor_within_the_view_is_read() {
+        let asset = asset(r#"{ "bufferView": 0, "componentType": 5126, "count": 1, "type": "VEC3" }"#);
+        assert_eq!(asset.read_accessor(0).unwrap(), (vec![0.0; 3], 3));
+    }
+
+    #[test]
+    fn huge_accessor_count_is_an_error() {
+        for count in [2, usize::MAX / 4, usize::MAX] {
+            let asset = asset(&format!(r#"{{ "bufferView": 0, "componentType": 5126, "count": {count}, "type": "VEC3" }}"#));
+            assert_eq!(asset.read_accessor(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        let asset = asset(&format!(r#"{{ "componentType": 5126, "count": {}, "type": "VEC4" }}"#, usize::MAX / 2));
+        assert_eq!(asset.read_accessor(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use glam::{Mat4, Vec3};
+use rayon::prelude::*;
+use serde::Deserialize;
+use crate::material::Material;
+use super::Scene;
+
+/// JSON 格式的场景图，由带有局部变换的节点组成，子节点的变换相对于父节点，
+/// 加入场景时逐层相乘得到世界变换并展开为普通的物体，不需要手动预先相乘变换矩阵
+///
+/// ```json
+/// {
+///   "nodes": [
+///     { "obj": "CornellBoxName.obj" },
+///     {
+///       "transform": { "translate": [0.0, 0.5, -0.5], "rotate": [45.0, 0.0, 0.0] },
+///       "children": [{ "obj": "MyName.obj", "transform": { "scale": [1.2, 0.6, 1.2] } }]
+///     }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneGraph {
+    pub nodes: Vec<Node>,
+}
+
+/// 场景图的节点，可以同时引用几何体与包含子节点，也可以只作为分组
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Node {
+    pub transform: Transform,
+    pub obj: Option<String>, // OBJ 模型的路径，由 SceneGraph::load 读取时相对于场景图文件所在的目录
+    pub sphere: Option<SphereNode>,
+    pub children: Vec<Node>,
+}
+
+/// 节点的局部变换，依次应用缩放、旋转（角度制，依次绕 x、y、z 轴）、平移，最后左乘 `matrix`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Transform {
+    pub translate: [f32; 3],
+    pub rotate: [f32; 3],
+    pub scale: [f32; 3],
+    pub matrix: Option<[f32; 16]>, // 按列优先顺序排列的 4x4 矩阵
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { translate: [0.0; 3], rotate: [0.0; 3], scale: [1.0; 3], matrix: None }
+    }
+}
+
+impl Transform {
+    pub fn to_mat4(&self) -> Mat4 {
+        let [x, y, z] = self.rotate.map(f32::to_radians);
+        let rotation = Mat4::from_rotation_z(z) * Mat4::from_rotation_y(y) * Mat4::from_rotation_x(x);
+        let local = Mat4::from_translation(Vec3::from(self.translate))
+            * rotation
+            * Mat4::from_scale(Vec3::from(self.scale));
+        match self.matrix {
+            Some(matrix) => Mat4::from_cols_array(&matrix) * local,
+            None => local,
+        }
+    }
+}
+
+/// 球体节点，球心与半径在节点的局部坐标系中，半径按世界变换的最大缩放分量缩放，
+/// 非均匀缩放不会使球体变为椭球
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereNode {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: String, // 材质库中已加载的材质名，或预设材质名（见 Material::preset）
+}
+
+impl SceneGraph {
+    /// 从 JSON 文本解析场景图，OBJ 路径保持不变，即相对于当前工作目录
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        serde_json::from_str(text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// 读取 JSON 场景图文件，相对的 OBJ 路径转换为相对于该文件所在的目录
+    pub fn load(file_path: &str) -> io::Result<Self> {
+        let mut graph = Self::from_json(&fs::read_to_string(file_path)?)?;
+        let base = Path::new(file_path).parent().unwrap_or(Path::new(""));
+        fn resolve(nodes: &mut [Node], base: &Path) {
+            for node in nodes {
+                if let Some(obj) = &mut node.obj {
+                    *obj = base.join(&*obj).to_string_lossy().into_owned();
+                }
+                resolve(&mut node.children, base);
+            }
+        }
+        resolve(&mut graph.nodes, base);
+        Ok(graph)
+    }
+
+    /// 将场景图展开后加入场景：所有 OBJ 模型并行加载，按节点的先序遍历顺序加入场景，之后加入球体，
+    /// 返回每个模型中跳过的退化三角面数量。球体的材质依次在这些模型、场景的材质库与预设材质中查找，
+    /// 找不到时返回 InvalidData 错误；模型无法读取或解析时同样返回错误，场景不变
+    pub fn add_to(&self, scene: &mut Scene) -> io::Result<Vec<usize>> {
+        let mut objs = vec![];
+        let mut spheres = vec![];
+        fn flatten<'a>(
+            nodes: &'a [Node],
+            parent: Mat4,
+            objs: &mut Vec<(&'a str, Mat4)>,
+            spheres: &mut Vec<(&'a SphereNode, Mat4)>,
+        ) {
+            for node in nodes {
+                let world = parent * node.transform.to_mat4();
+                if let Some(obj) = &node.obj {
+                    objs.push((obj.as_str(), world));
+                }
+                if let Some(sphere) = &node.sphere {
+                    spheres.push((sphere, world));
+                }
+                flatten(&node.children, world, objs, spheres);
+            }
+        }
+        flatten(&self.nodes, Mat4::IDENTITY, &mut objs, &mut spheres);
+
+        // 先加载全部模型，球体可以引用模型中的材质；材质都存在时才修改场景
+        let loaded = objs.par_iter()
+            .map(|(file_path, transform)| Scene::load_obj(file_path, *transform))
+            .collect::<io::Result<Vec<_>>>()?;
+        let spheres = spheres.into_iter().map(|(sphere, world)| {
+            let name = &sphere.material;
+            let material = loaded.iter().rev()
+                .flat_map(|obj| obj.materials.iter().rev())
+                .find(|(material_name, _)| material_name == name)
+                .map(|(_, material)| material.clone())
+                .or_else(|| scene.materials.get(name).cloned())
+                .or_else(|| Material::preset(name).map(Arc::new))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Unknown material: {name}")))?;
+            let (scale, _, _) = world.to_scale_rotation_translation();
+            let center = world.transform_point3(Vec3::from(sphere.center));
+            Ok((center, sphere.radius * scale.abs().max_element(), material))
+        }).collect::<io::Result<Vec<_>>>()?;
+
+        let degenerate = loaded.into_iter().map(|obj| scene.merge_obj(obj)).collect();
+        for (center, radius, material) in spheres {
+            scene.add_sphere(center, radius, material);
+        }
+        Ok(degenerate)
+    }
+}
@@ -1,32 +1,156 @@
+mod batch;
 mod bvh;
+mod gltf;
+mod ply;
+mod stl;
+pub mod graph;
 pub mod primitive;
 
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use std::io;
 use std::path::Path;
 use std::sync::Arc;
-use glam::{Mat4, Vec2, Vec3};
-use crate::material::Material;
-use crate::ray::Ray;
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use rayon::prelude::*;
+use crate::material::{self, Material};
+use crate::rand_util;
+use crate::stats;
+use crate::texture::TextureMemory;
+use crate::ray::{Ray, RayPacket};
+use crate::render::T_MIN;
 use crate::scene::bvh::*;
-use primitive::Triangle;
+use primitive::{Sphere, Triangle};
+
+pub use crate::scene::bvh::AABB;
 
 // 定义一个表示光线与物体碰撞的 trait
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
     fn bounding_box(&self) -> AABB;
+
+    // 物体材质在材质库中的名字，没有名字的物体无法按名字覆盖材质
+    fn material_name(&self) -> Option<&str> {
+        None
+    }
+
+    // 物体在 OBJ 中所属的物体或组（o、g）的名字，没有名字的物体无法按组替换材质
+    fn object_name(&self) -> Option<&str> {
+        None
+    }
+
+    // 如果物体是三角面则返回自身，用于在 BVH 叶子中进行批量求交
+    fn as_triangle(&self) -> Option<&Triangle> {
+        None
+    }
+
+    // 如果物体是静止的球体则返回自身，用于在 BVH 叶子中进行批量求交
+    fn as_sphere(&self) -> Option<&Sphere> {
+        None
+    }
+
+    // 生成一个使用新材质的副本，不支持替换材质的物体返回 None
+    fn with_material(&self, _material: Arc<Material>) -> Option<Box<dyn Hittable + Sync + Send>> {
+        None
+    }
+
+    // 物体的材质，用于收集场景中的光源
+    fn material(&self) -> Option<&Material> {
+        None
+    }
+
+    // 物体的表面积，不支持表面采样的物体返回 0
+    fn area(&self) -> f32 {
+        0.0
+    }
+
+    // 在物体表面上按面积均匀采样一点，不支持表面采样的物体返回 None
+    fn sample_point(&self) -> Option<SurfaceSample> {
+        None
+    }
+
+    // 为 reference 处的直接光照采样物体表面上的一点，返回采样点及其面积概率密度，
+    // 默认按面积均匀采样，概率密度为 1 / 面积；物体可以只采样从 reference 可见的部分以减少噪点
+    fn sample_point_from(&self, _reference: Vec3) -> Option<(SurfaceSample, f32)> {
+        self.sample_point().map(|sample| (sample, 1.0 / self.area()))
+    }
+}
+
+/// 锥体追踪中细三角面的一层：三角面只覆盖像素锥体的一部分，`coverage` 为覆盖的比例，
+/// `ray` 为锥体内击中该三角面的一条光线，`hit` 为其交点，用于着色
+#[derive(Debug, Clone, Copy)]
+pub struct ConeHit<'a> {
+    pub hit: HitRecord<'a>,
+    pub ray: Ray,
+    pub coverage: f32,
+}
+
+// 物体表面上的采样点
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub point: Vec3,  // 采样点
+    pub normal: Vec3, // 采样点处的外法向量，是单位向量
 }
 
-// 记录光线与物体的碰撞信息
+// 记录光线与物体的碰撞信息，材质以引用形式借用自图元，避免每次碰撞都复制整个材质
 #[derive(Debug, Clone, Copy)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub point: Vec3,      // 交点
-    pub normal: Vec3,     // 交点处的物体表面法向量，是单位向量
+    pub normal: Vec3,     // 交点处的物体表面法向量，是单位向量，双面材质总是朝向入射光线一侧，单面材质为外法向
+    pub front_face: bool, // 光线是否从表面外侧（外法向所指的一侧）射入
     pub t: f32,           // 碰撞时间
-    pub material: Material, // 碰撞点颜色
+    pub uv: Vec2,         // 交点处的贴图坐标
+    pub color: Vec3,      // 交点处的顶点颜色，用于调制漫反射颜色，没有顶点颜色时为 1
+    pub tangent: Vec3,    // 交点处的切向量，是单位向量，用于确定各向异性材质的方向
+    pub uv_footprint: f32, // 像素在贴图坐标中覆盖的宽度，用于选择 mipmap 层级，为 0 时使用原始贴图
+    pub edge_distance: f32, // 交点到所在三角面最近一条边的距离，用于绘制线框，不是三角面时为 +inf
+    pub material: &'a Material, // 碰撞点材质
 }
 
-impl HitRecord {
-    pub fn new(point: Vec3, normal: Vec3, t: f32, material: Material) -> Self {
-        Self { point, normal: normal.normalize(), t, material }
+// 求交时 HitRecord 按值返回与比较，只借用材质，保持较小的尺寸；
+// 改为持有材质时会超过 200 字节，增加字段时需要一并检查此处
+const _: () = assert!(std::mem::size_of::<HitRecord>() <= 80);
+
+impl<'a> HitRecord<'a> {
+    /// `outward_normal` 为指向物体外侧的法向量，会根据光线方向确定 `front_face`，
+    /// 双面材质的法向翻转到光线一侧
+    pub fn new(
+        ray: &Ray,
+        point: Vec3,
+        outward_normal: Vec3,
+        t: f32,
+        uv: Vec2,
+        material: &'a Material,
+    ) -> Self {
+        let front_face = ray.direction.dot(outward_normal) < 0.0;
+        Self::with_front_face(point, outward_normal, front_face, t, uv, material)
+    }
+
+    /// 与 `new` 相同，但由调用方给出 `front_face`，例如三角面按几何法向而不是插值法向判定正反面
+    pub fn with_front_face(
+        point: Vec3,
+        outward_normal: Vec3,
+        front_face: bool,
+        t: f32,
+        uv: Vec2,
+        material: &'a Material,
+    ) -> Self {
+        let outward_normal = outward_normal.normalize();
+        let normal = if front_face || !material.two_sided { outward_normal } else { -outward_normal };
+        // 默认取任意一个与法向垂直的方向，图元可以根据贴图坐标等信息覆盖
+        let tangent = normal.any_orthonormal_vector();
+        Self {
+            point,
+            normal,
+            front_face,
+            t,
+            uv,
+            color: Vec3::ONE,
+            tangent,
+            uv_footprint: 0.0,
+            edge_distance: f32::INFINITY,
+            material,
+        }
     }
 }
 
@@ -34,22 +158,158 @@ impl HitRecord {
 pub struct Scene {
     pub objects: Vec<Arc<dyn Hittable + Sync + Send>>,
     pub bvh: Option<BVHNode>,
+    pub materials: HashMap<String, Arc<Material>>, // 材质库，以 MTL 中 newmtl 的名字为键
+    material_sources: HashMap<String, String>, // 材质库中由 .obj 加载的材质来自的文件，用于发现不同文件中的同名材质
+    pub lights: Vec<Arc<dyn Hittable + Sync + Send>>, // 光源列表，即可以进行表面采样的自发光物体，在 build_bvh 时收集
+    light_powers: Vec<f32>, // 各光源发光功率的亮度，用于按功率选择光源
+    light_power_total: f32,
+    light_cdf: Vec<f32>, // 光源功率的前缀和，用于二分查找选择光源
+    bvh_cost: f32, // 上次完整构建时 BVH 的表面积启发代价
+    replaced: HashMap<usize, Replacement>, // 上次构建或调整 BVH 之后被替换的物体，以原物体的地址为键
+}
+
+// 从 .obj 文件加载、尚未加入场景的模型
+struct LoadedObj {
+    file_path: String, // 模型文件的路径
+    materials: Vec<(String, Arc<Material>)>, // 按 MTL 中的顺序排列
+    triangles: Vec<Triangle>,
+    degenerate: usize, // 因退化而跳过的三角面数量
+}
+
+// 各模型加载器共用：将模型空间的顶点与法向变换到场景中
+struct MeshTransform {
+    transform: Mat4,
+    normal_matrix: Mat3,
+    flip: bool,
+}
+
+impl MeshTransform {
+    fn new(transform: Mat4) -> Self {
+        // 法向使用逆转置矩阵变换，非均匀缩放时仍与表面垂直；镜像变换翻转顶点顺序，保持正面朝向
+        MeshTransform {
+            transform,
+            normal_matrix: Mat3::from_mat4(transform).inverse().transpose(),
+            flip: transform.determinant() < 0.0,
+        }
+    }
+
+    // 按变换后的绕序排列一个面的三个顶点（或其索引）
+    fn face<T>(&self, [a, b, c]: [T; 3]) -> [T; 3] {
+        if self.flip { [a, c, b] } else { [a, b, c] }
+    }
+
+    fn point(&self, point: Vec3) -> Vec3 {
+        self.transform.transform_point3(point)
+    }
+
+    fn normal(&self, normal: Vec3) -> Vec3 {
+        (self.normal_matrix * normal).normalize_or_zero()
+    }
+}
+
+// 设置来源标签后加入三角形列表，退化三角面只计数、不加入
+fn push_triangle(triangles: &mut Vec<Triangle>,
+                 degenerate: &mut usize,
+                 mut triangle: Triangle,
+                 material_name: Option<&Arc<str>>,
+                 object_name: &Arc<str>) {
+    triangle.material_name = material_name.cloned();
+    triangle.object_name = Some(object_name.clone());
+    if triangle.is_degenerate() {
+        *degenerate += 1;
+        return;
+    }
+    triangles.push(triangle);
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// 被替换的物体，同时持有原物体，保证在调整 BVH 之前原物体的地址不会被复用
+struct Replacement {
+    _old: Arc<dyn Hittable + Sync + Send>,
+    new: Arc<dyn Hittable + Sync + Send>,
+}
+
+// 物体的地址，用于在 BVH 的叶子中找到被替换的物体
+fn object_address(object: &Arc<dyn Hittable + Sync + Send>) -> usize {
+    Arc::as_ptr(object) as *const () as usize
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scene {
     const MAX_OBJECTS_PER_BVH_LEAF: usize = 5;
+    const SHADOW_EPSILON: f32 = 0.001; // 阴影光线两端留出的距离，避免与端点所在的表面自相交
+    const BVH_REBUILD_RATIO: f32 = 1.5; // 调整后的 BVH 代价超过构建时的该倍数时重新构建
 
     pub fn new() -> Self {
-        Scene { objects: Vec::new(), bvh: None }
+        Scene {
+            objects: Vec::new(),
+            bvh: None,
+            materials: HashMap::new(),
+            material_sources: HashMap::new(),
+            lights: Vec::new(),
+            light_powers: Vec::new(),
+            light_power_total: 0.0,
+            light_cdf: Vec::new(),
+            bvh_cost: 0.0,
+            replaced: HashMap::new(),
+        }
+    }
+
+    // 将 .obj 模型加载到场景中，返回因退化（面积接近零）而跳过的三角面数量；文件无法读取或解析时 panic
+    pub fn add_obj(&mut self, file_path: &str, transform: Mat4) -> usize {
+        let obj = Self::load_obj(file_path, transform).expect("Failed to load .obj file");
+        self.merge_obj(obj)
     }
 
-    // 将 .obj 模型加载到场景中
-    pub fn add_obj(&mut self, file_path: &str, transform: Mat4) {
-        // 读取并解析 .obj 文件
-        let obj_data = tobj::load_obj(file_path, &tobj::GPU_LOAD_OPTIONS)
-            .expect("Failed to load .obj file");
-        let (models, materials) = obj_data;
-        let materials = materials.expect("Fail to load .mtl file");
+    // 并行加载多个 .obj 模型，每个模型为 (路径, 变换)，之后按列表顺序加入场景，
+    // 物体顺序与依次调用 add_obj 相同，同名材质以后加载的为准，来自不同文件时给出警告；返回每个模型中跳过的退化三角面数量
+    pub fn add_objs_parallel(&mut self, objs: &[(&str, Mat4)]) -> Vec<usize> {
+        let loaded = objs.par_iter()
+            .map(|(file_path, transform)| Self::load_obj(file_path, *transform).expect("Failed to load .obj file"))
+            .collect::<Vec<_>>();
+        loaded.into_iter().map(|obj| self.merge_obj(obj)).collect()
+    }
+
+    // 将已加载的模型的材质登记到材质库，三角面加入场景，返回退化三角面数量。
+    // 材质库以名字为键，其他文件中已有的同名材质被替换时给出警告，之后按名字覆盖材质时两者都会受影响
+    fn merge_obj(&mut self, obj: LoadedObj) -> usize {
+        for (name, material) in obj.materials {
+            if self.materials.contains_key(&name) && self.material_sources.get(&name) != Some(&obj.file_path) {
+                let previous = self.material_sources.get(&name).map_or("the scene", String::as_str);
+                eprintln!("Warning: material {name} from {} replaces the one from {previous}", obj.file_path);
+            }
+            self.material_sources.insert(name.clone(), obj.file_path.clone());
+            self.materials.insert(name, material);
+        }
+        for triangle in obj.triangles {
+            self.add(Box::new(triangle));
+        }
+        obj.degenerate
+    }
+
+    // 读取并解析 .obj 文件，转换为三角形，不修改场景，可以在多个线程中同时进行；
+    // 文件无法打开时返回 NotFound 错误，无法解析时返回 InvalidData 错误
+    fn load_obj(file_path: &str, transform: Mat4) -> io::Result<LoadedObj> {
+        let (models, materials) = tobj::load_obj(file_path, &tobj::GPU_LOAD_OPTIONS).map_err(|error| {
+            let kind = match error {
+                tobj::LoadError::OpenFileFailed => io::ErrorKind::NotFound,
+                _ => io::ErrorKind::InvalidData,
+            };
+            io::Error::new(kind, format!("Failed to load {file_path}: {error}"))
+        })?;
+        // 缺少或无法解析 .mtl 文件时仍然加载几何体，所有面使用默认材质
+        let materials = materials.unwrap_or_else(|error| {
+            eprintln!("Warning: failed to load the .mtl file of {file_path} ({error}), using the default material");
+            vec![]
+        });
         let path = Path::new(file_path);
         let base_path = if let Some(base) = path.parent() {
             base.to_str().unwrap()
@@ -57,58 +317,62 @@ impl Scene {
             "/"
         };
 
+        // 每个材质只解析一次
+        let materials = materials.iter().map(|material| {
+            let name: Arc<str> = Arc::from(material.name.as_str());
+            (name, Arc::new(Material::from_mtl(material, base_path)))
+        }).collect::<Vec<_>>();
+        let default_material = (Arc::<str>::from("default"), Arc::new(Material::PLASTER));
+
         // 将 .obj 中的每个面转换为三角形
-        for mesh in models.iter().map(|model| { &model.mesh }) {
+        let mesh_transform = MeshTransform::new(transform);
+        let mut triangles = vec![];
+        let mut degenerate = 0;
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let object_name: Arc<str> = Arc::from(model.name.as_str());
             for index in mesh.indices.chunks(3) {
                 // 使用 GPU_LOAD_OPTIONS 的情况下会将 single_index 设为 true，
                 // 因此不需要使用 normal_indices、texcoord_indices 等
-                let i0 = index[0] as usize;
-                let i1 = index[1] as usize;
-                let i2 = index[2] as usize;
-                let material_id = mesh.material_id.expect("Material id not found!");
-                let material = Material::from_mtl(
-                    &materials[material_id],
-                    base_path,
-                );
-                // 转换为 Vec3
-                let v0 = Vec3::from_slice(&mesh.positions[i0 * 3..i0 * 3 + 3]);
-                let v1 = Vec3::from_slice(&mesh.positions[i1 * 3..i1 * 3 + 3]);
-                let v2 = Vec3::from_slice(&mesh.positions[i2 * 3..i2 * 3 + 3]);
-
-                let v0 = transform.transform_point3(v0);
-                let v1 = transform.transform_point3(v1);
-                let v2 = transform.transform_point3(v2);
+                // 面中的负数（相对）索引在 tobj 解析时已经按当时的顶点数量换算为绝对索引，
+                // 例如 scenes 中的 Cornell Box 使用 `f -4 -3 -2 -1`，这里拿到的总是非负的绝对索引
+                let face = mesh_transform.face([index[0] as usize, index[1] as usize, index[2] as usize]);
+                // 没有指定材质或材质不存在的面使用默认材质
+                let (material_name, material) = mesh.material_id
+                    .and_then(|id| materials.get(id))
+                    .unwrap_or(&default_material);
 
+                let vertices = face.iter()
+                    .map(|i| mesh_transform.point(Vec3::from_slice(&mesh.positions[i * 3..i * 3 + 3])))
+                    .collect();
                 let normals = if mesh.normals.is_empty() {
                     vec![]
                 } else {
-                    let n0 = Vec3::from_slice(&mesh.normals[i0 * 3..i0 * 3 + 3]);
-                    let n1 = Vec3::from_slice(&mesh.normals[i1 * 3..i1 * 3 + 3]);
-                    let n2 = Vec3::from_slice(&mesh.normals[i2 * 3..i2 * 3 + 3]);
-
-                    let n0 = transform.transform_vector3(n0).normalize();
-                    let n1 = transform.transform_vector3(n1).normalize();
-                    let n2 = transform.transform_vector3(n2).normalize();
-
-                    vec![n0, n1, n2]
+                    face.iter().map(|i| mesh_transform.normal(Vec3::from_slice(&mesh.normals[i * 3..i * 3 + 3]))).collect()
+                };
+                // 部分导出工具会在顶点坐标后附带 RGB 顶点颜色
+                let colors = if mesh.vertex_color.is_empty() {
+                    vec![]
+                } else {
+                    face.iter().map(|i| Vec3::from_slice(&mesh.vertex_color[i * 3..i * 3 + 3])).collect()
                 };
                 let texcoords = if mesh.texcoords.is_empty() {
                     vec![]
                 } else {
-                    let uv0 = Vec2::from_slice(&mesh.texcoords[i0 * 2..i0 * 2 + 2]);
-                    let uv1 = Vec2::from_slice(&mesh.texcoords[i1 * 2..i1 * 2 + 2]);
-                    let uv2 = Vec2::from_slice(&mesh.texcoords[i2 * 2..i2 * 2 + 2]);
-
-                    vec![uv0, uv1, uv2]
+                    face.iter().map(|i| Vec2::from_slice(&mesh.texcoords[i * 2..i * 2 + 2])).collect()
                 };
 
                 // 创建三角形
-                let triangle = Triangle::new(
-                    vec![v0, v1, v2], normals, texcoords, material,
-                );
-                self.add(Box::new(triangle));
+                let triangle = Triangle::new(vertices, normals, texcoords, material.clone())
+                    .with_colors(colors);
+                push_triangle(&mut triangles, &mut degenerate, triangle, Some(material_name), &object_name);
             }
         }
+
+        let materials = materials.into_iter()
+            .map(|(name, material)| (name.to_string(), material))
+            .collect();
+        Ok(LoadedObj { file_path: file_path.to_string(), materials, triangles, degenerate })
     }
 
     // 将基本图元添加到场景中
@@ -117,13 +381,311 @@ impl Scene {
         self.bvh = None;
     }
 
+    // 添加一个静止的球体，BVH 叶子中只有球体时使用批量求交，适合由大量球体组成的场景
+    pub fn add_sphere(&mut self, center: Vec3, radius: f32, material: impl Into<Arc<Material>>) {
+        self.add(Box::new(Sphere::new(center, radius, material)));
+    }
+
+    // 在高度 y 处添加一块边长为 size、以 y 轴为中心的正方形地面，法向朝 +y，物体名为 ground，
+    // 贴图坐标为世界坐标 (x, -z)，贴图每个单位长度重复一次
+    pub fn add_ground_plane(&mut self, y: f32, size: f32, material: impl Into<Arc<Material>>) {
+        self.add_ground_plane_with_uv_scale(y, size, 1.0, material);
+    }
+
+    // 与 add_ground_plane 相同，贴图坐标为世界坐标乘以 uv_scale，即每个单位长度重复 uv_scale 次
+    pub fn add_ground_plane_with_uv_scale(
+        &mut self,
+        y: f32,
+        size: f32,
+        uv_scale: f32,
+        material: impl Into<Arc<Material>>,
+    ) {
+        let material = material.into();
+        let half = size / 2.0;
+        let corners = [
+            Vec3::new(-half, y, half),
+            Vec3::new(half, y, half),
+            Vec3::new(half, y, -half),
+            Vec3::new(-half, y, -half),
+        ];
+        let uv = |corner: Vec3| Vec2::new(corner.x, -corner.z) * uv_scale;
+        let object_name: Arc<str> = Arc::from("ground");
+        for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+            let (v0, v1, v2) = (corners[a], corners[b], corners[c]);
+            let mut triangle = Triangle::new(vec![v0, v1, v2], vec![], vec![uv(v0), uv(v1), uv(v2)], material.clone());
+            triangle.object_name = Some(object_name.clone());
+            self.add(Box::new(triangle));
+        }
+    }
+
+    // 按名字覆盖已加载的材质，例如将 MyName 中的 plaster 替换为 Material::GLASS，
+    // 只影响调用前已经通过 add_obj 加载的物体，返回被替换材质的物体数量。
+    // 与 set_material 一样，已经构建的 BVH 只需调整叶子，光源随之重新收集
+    pub fn override_material(&mut self, name: &str, material: Material) -> usize {
+        let material = Arc::new(material);
+        self.materials.insert(name.to_string(), material.clone());
+        self.material_sources.remove(name);
+
+        let mut count = 0;
+        for index in 0..self.objects.len() {
+            if self.objects[index].material_name() != Some(name) {
+                continue;
+            }
+            if let Some(replaced) = self.objects[index].with_material(material.clone()) {
+                self.replace_object(index, replaced);
+                count += 1;
+            }
+        }
+        if count > 0 && self.bvh.is_some() {
+            self.refit_bvh();
+        }
+        count
+    }
+
+    // 按名字覆盖已加载材质的自发光辐亮度，强度重置为 1，材质的其他属性保持不变，
+    // 与 override_material 一样只影响已加载的物体，返回被替换材质的物体数量
+    pub fn override_emission(&mut self, name: &str, emissive: Vec3) -> usize {
+        let Some(material) = self.materials.get(name) else {
+            return 0;
+        };
+        let material = Material { emissive, emissive_strength: 1.0, ..Material::clone(material) };
+        self.override_material(name, material)
+    }
+
+    // 按名字只修改已加载材质的自发光强度，保留颜色，返回被替换材质的物体数量
+    pub fn override_emission_strength(&mut self, name: &str, emissive_strength: f32) -> usize {
+        let Some(material) = self.materials.get(name) else {
+            return 0;
+        };
+        let material = Material { emissive_strength, ..Material::clone(material) };
+        self.override_material(name, material)
+    }
+
+    // 替换 OBJ 中某个物体或组（o、g）的材质，例如将 Cornell Box 的 backWall 换成镜面，
+    // 不需要修改 MTL 或重新调用 add_obj，返回被替换材质的物体数量。
+    // 替换材质不改变包围盒，已经构建的 BVH 只需调整叶子，不会重新构建
+    pub fn set_material(&mut self, object_name: &str, material: Material) -> usize {
+        let material = Arc::new(material);
+        let mut count = 0;
+        for index in 0..self.objects.len() {
+            if self.objects[index].object_name() != Some(object_name) {
+                continue;
+            }
+            if let Some(replaced) = self.objects[index].with_material(material.clone()) {
+                self.replace_object(index, replaced);
+                count += 1;
+            }
+        }
+        if count > 0 && self.bvh.is_some() {
+            self.refit_bvh();
+        }
+        count
+    }
+
+    /// 使场景中的物体与材质库引用的所有贴图常驻内存，返回贴图数量与占用的内存，按贴图 ID 去重。
+    /// 贴图在加载模型时已经解码，这一步读入分块存储（`TextureStorage::Tiled`）贴图的所有块，
+    /// 渲染时采样不再读取临时文件、不再加锁，首次访问的开销也不会计入渲染时间；
+    /// 在加载完所有模型之后、渲染之前调用，结果与是否调用无关
+    pub fn preload_textures(&self) -> TextureMemory {
+        let materials = self.objects.iter()
+            .filter_map(|object| object.material())
+            .chain(self.materials.values().map(|material| material.as_ref()));
+        let mut loaded = HashSet::new();
+        let mut memory = TextureMemory::default();
+        for texture in materials.flat_map(Material::textures) {
+            if loaded.insert(texture.id()) {
+                memory.textures += 1;
+                memory.bytes += texture.preload();
+            }
+        }
+        memory
+    }
+
+    // 构建 BVH 并收集光源，是渲染前唯一开销较大的一次性步骤；之后场景只读，
+    // 包装为 Arc<Scene> 即可在多次渲染之间共享，更换摄像机或渲染选项都不需要重新构建
     pub fn build_bvh(&mut self) {
-        self.bvh = Some(BVHNode::build(&mut self.objects, Self::MAX_OBJECTS_PER_BVH_LEAF));
+        // 构建时会对物体排序，使用副本以保持 objects 中的顺序，replace_object 依赖这一顺序
+        let bvh = BVHNode::build(&mut self.objects.clone(), Self::MAX_OBJECTS_PER_BVH_LEAF);
+        self.bvh_cost = bvh.sah_cost();
+        self.bvh = Some(bvh);
+        self.replaced.clear();
+        self.collect_lights();
+    }
+
+    // 将第 index 个物体替换为 object，例如动画中移动后的物体；
+    // 与 add 不同，不会丢弃已经构建的 BVH，之后调用 refit_bvh 更新即可
+    pub fn replace_object(&mut self, index: usize, object: Box<dyn Hittable + Sync + Send>) {
+        let new: Arc<dyn Hittable + Sync + Send> = object.into();
+        let old = std::mem::replace(&mut self.objects[index], new.clone());
+        if self.bvh.is_some() {
+            self.replaced.insert(object_address(&old), Replacement { _old: old, new });
+        }
+    }
+
+    // 在少量物体移动后更新 BVH：保持树的结构，只重新计算包围盒，比重新构建快得多；
+    // 移动幅度较大、调整后的 BVH 质量明显下降时自动重新构建
+    pub fn refit_bvh(&mut self) {
+        let Some(bvh) = &mut self.bvh else {
+            return self.build_bvh();
+        };
+        let replaced = std::mem::take(&mut self.replaced);
+        bvh.refit(&|object| {
+            // 同一个物体可能在两次调整之间被替换了多次
+            let mut current = replaced.get(&object_address(object))?;
+            while let Some(next) = replaced.get(&object_address(&current.new)) {
+                current = next;
+            }
+            Some(current.new.clone())
+        });
+        if bvh.sah_cost() > self.bvh_cost * Self::BVH_REBUILD_RATIO {
+            return self.build_bvh();
+        }
+        self.collect_lights();
+    }
+
+    // 收集光源并计算各光源的功率
+    //
+    // 场景中没有实例：场景图中被多次引用的模型在加入场景时已经按各自的世界变换展开为独立的物体，
+    // 因此这里收集到的光源都位于世界坐标下，面积和表面采样无需再考虑变换
+    fn collect_lights(&mut self) {
+        self.lights = self.objects.iter()
+            .filter(|object| {
+                let emissive = object.material().is_some_and(|m| m.emissive_color().max_element() > 0.0);
+                emissive && object.area() > 0.0
+            })
+            .cloned()
+            .collect();
+        // 每个发光面的功率为 π * 面积 * 辐亮度
+        self.light_powers = self.lights.iter().map(|light| {
+            let material = light.material().unwrap();
+            material.emitting_sides() * PI * light.area() * material::luminance(material.emissive_color())
+        }).collect();
+        self.light_cdf = self.light_powers.iter()
+            .scan(0.0, |sum, power| {
+                *sum += power;
+                Some(*sum)
+            })
+            .collect();
+        self.light_power_total = self.light_cdf.last().copied().unwrap_or(0.0);
+    }
+
+    // 按发光功率随机选择一个光源，返回光源及其被选中的概率，没有光源时返回 None
+    pub fn pick_light(&self) -> Option<(&Arc<dyn Hittable + Sync + Send>, f32)> {
+        self.pick_light_with(rand_util::random())
+    }
+
+    // 与 pick_light 相同，但使用给定的随机数 u（属于 [0.0, 1.0)）选择光源，
+    // 多次选择时传入分层的随机数可以让各光源被选中的次数更接近其功率比例。
+    // 在功率前缀和上二分查找，光源数量很多时也只需 O(log n)
+    pub fn pick_light_with(&self, u: f32) -> Option<(&Arc<dyn Hittable + Sync + Send>, f32)> {
+        if self.light_power_total <= 0.0 {
+            return None;
+        }
+        let pick = u * self.light_power_total;
+        let index = self.light_cdf.partition_point(|&sum| sum <= pick)
+            .min(self.lights.len() - 1);
+        Some((&self.lights[index], self.light_powers[index] / self.light_power_total))
+    }
+
+    // 使用 pick_light 选择光源再在其表面均匀采样时，得到材质为 material 的光源上的点的面积概率密度，
+    // 即 (功率 / 总功率) / 面积，与光源面积无关
+    pub fn light_pdf(&self, material: &Material) -> f32 {
+        if self.light_power_total <= 0.0 {
+            return 0.0;
+        }
+        material.emitting_sides() * PI * material::luminance(material.emissive_color()) / self.light_power_total
+    }
+
+    // 检查时刻 time 两点之间是否有物体遮挡
+    pub fn occluded(&self, from: Vec3, to: Vec3, time: f32) -> bool {
+        let distance = from.distance(to);
+        let ray = Ray::new(from, to - from).with_time(time);
+        self.hit(&ray, Self::SHADOW_EPSILON, distance - Self::SHADOW_EPSILON).is_some()
+    }
+
+    // 获取整个场景的包围盒，空场景返回位于原点的空包围盒
+    pub fn bounds(&self) -> AABB {
+        if let Some(bvh) = &self.bvh {
+            return bvh.bbox();
+        }
+        self.objects.iter()
+            .map(|object| object.bounding_box())
+            .reduce(|a, b| a.merge(&b))
+            .unwrap_or(AABB::new(Vec3::ZERO, Vec3::ZERO))
+    }
+
+    /// 求光线与场景的最近交点，供拾取、碰撞检测、自定义积分器等外部工具使用。
+    ///
+    /// 坐标约定：世界坐标系为右手系，y 轴向上，与 OBJ 文件一致；`Ray::new` 会将方向归一化，
+    /// 因此 `HitRecord::t` 即为交点到光线起点的距离，只返回距离在 (0.001, +inf) 内的交点。
+    /// 双面材质的 `HitRecord::normal` 总是朝向光线来的一侧，单面材质为外法向，`front_face` 指明光线是否从外侧射入；
+    /// `uv` 为 OBJ 中的贴图坐标，v = 0 对应贴图底部。
+    /// 已经调用过 `build_bvh` 时使用 BVH 加速，否则逐个物体求交
+    pub fn intersect(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        if self.bvh.is_some() {
+            return self.hit(ray, T_MIN, f32::INFINITY);
+        }
+        self.objects.iter()
+            .filter_map(|object| object.hit(ray, T_MIN, f32::INFINITY))
+            .min_by(|a, b| a.t.total_cmp(&b.t))
+    }
+
+    /// 成组求主光线与场景的最近交点，结果的顺序与组中光线的顺序相同，交点的约定与 `intersect` 相同。
+    /// 光线组通常由 `Camera::get_rays` 为一个图块生成，相邻光线经过的 BVH 节点大多相同，
+    /// 成组遍历时每个节点的包围盒只需对整组检查一次；需要先调用 `build_bvh`
+    pub fn hit_packet(&self, packet: &RayPacket) -> Vec<Option<HitRecord<'_>>> {
+        let bvh = self.bvh.as_ref().expect("build_bvh must be called before hit_packet");
+        let rays = (0..packet.len()).map(|index| packet.ray(index)).collect::<Vec<_>>();
+        let mut closest = vec![f32::INFINITY; packet.len()];
+        let mut hits = vec![None; packet.len()];
+        bvh.hit_packet(packet, &rays, 0, T_MIN, &mut closest, &mut hits);
+        hits
+    }
+
+    /// 求光线与场景的前 k 个交点，按距离升序排列，交点少于 k 个时全部返回，供顺序无关透明、
+    /// 检查重叠几何体等需要多个交点的工具使用；正常渲染只需要最近的交点，应使用更快的 `intersect`。
+    ///
+    /// 交点的约定与 `intersect` 相同。球体等封闭物体的入射点和出射点分别计入，
+    /// 位于同一位置的重叠物体各自计入；同一物体上距离小于 0.001 的交点只计一次
+    pub fn hit_k(&self, ray: &Ray, k: usize) -> Vec<HitRecord<'_>> {
+        let mut hits = Vec::with_capacity(k.min(16));
+        if let Some(bvh) = &self.bvh {
+            bvh.hit_k(ray, T_MIN, f32::INFINITY, k, &mut hits);
+        } else if k > 0 {
+            for object in &self.objects {
+                bvh::collect_hits(object.as_ref(), ray, T_MIN, f32::INFINITY, k, &mut hits);
+            }
+        }
+        hits
+    }
+
+    /// 光线求最近交点时遍历 BVH 检查过包围盒的节点数，即该光线的深度复杂度，数值大说明加速结构在该处开销大；
+    /// 需要先调用 `build_bvh`
+    pub fn depth_complexity(&self, ray: &Ray) -> u32 {
+        let bvh = self.bvh.as_ref().expect("build_bvh must be called before depth_complexity");
+        let mut nodes = 0;
+        bvh.hit_counted(ray, T_MIN, f32::INFINITY, &mut nodes);
+        nodes
+    }
+
+    /// 以 `ray` 为轴、张角为 `spread`（弧度）的锥体与场景求交，用于主光线的抗锯齿。
+    /// 在锥体处比锥体截面更细的三角面不参与普通求交，而是按覆盖锥体截面的比例返回，按距离升序排列，
+    /// 只保留比普通交点更近的；普通交点为光线与其余物体的最近交点。需要先调用 `build_bvh`
+    pub fn hit_cone(&self, ray: &Ray, spread: f32) -> (Option<HitRecord<'_>>, Vec<ConeHit<'_>>) {
+        let bvh = self.bvh.as_ref().expect("build_bvh must be called before hit_cone");
+        stats::count(|stats| stats.rays += 1);
+        let (mut hit, mut layers) = (None, vec![]);
+        bvh.hit_cone(ray, spread, T_MIN, &mut hit, &mut layers);
+        let t_max = hit.map_or(f32::INFINITY, |hit: HitRecord| hit.t);
+        layers.retain(|layer| layer.hit.t < t_max);
+        layers.sort_by(|a, b| a.hit.t.total_cmp(&b.hit.t));
+        (hit, layers)
     }
 
     // 检查光线与场景中的物体是否碰撞，返回最早发生的碰撞
-    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        assert!(!self.bvh.is_none());
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        assert!(self.bvh.is_some());
+        stats::count(|stats| stats.rays += 1);
         if let Some(bvh) = &self.bvh {
             bvh.hit(ray, t_min, t_max)
         } else {
@@ -131,3 +693,226 @@ impl Scene {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[test]
+    fn relative_indices_match_absolute_indices() {
+        let relative = Scene::load_obj(&fixture("relative_indices.obj"), Mat4::IDENTITY).unwrap();
+        let absolute = Scene::load_obj(&fixture("absolute_indices.obj"), Mat4::IDENTITY).unwrap();
+
+        // 四边形被拆为两个三角形，另有一个三角形
+        let expected = [
+            ([Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0)], "quad", "red"),
+            ([Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)], "quad", "red"),
+            ([Vec3::new(0.0, 0.0, -1.0), Vec3::new(2.0, 0.0, -1.0), Vec3::new(0.0, 2.0, -1.0)], "triangle", "blue"),
+        ];
+        for loaded in [&relative, &absolute] {
+            assert_eq!(loaded.triangles.len(), expected.len());
+            assert_eq!(loaded.degenerate, 0);
+            for (triangle, (vertices, object, material)) in loaded.triangles.iter().zip(&expected) {
+                assert_eq!([triangle.v0, triangle.v1, triangle.v2], *vertices);
+                assert_eq!(triangle.object_name.as_deref(), Some(*object));
+                assert_eq!(triangle.material_name.as_deref(), Some(*material));
+            }
+            let names = loaded.materials.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+            assert_eq!(names, ["red", "blue"]);
+        }
+
+        // 法向、贴图坐标和材质也应一致
+        for (a, b) in relative.triangles.iter().zip(&absolute.triangles) {
+            assert_eq!([a.n0, a.n1, a.n2], [b.n0, b.n1, b.n2]);
+            assert_eq!([a.uv0, a.uv1, a.uv2], [b.uv0, b.uv1, b.uv2]);
+            assert_eq!(a.material.diffuse, b.material.diffuse);
+        }
+        assert_eq!(relative.triangles[0].n0, Vec3::Z);
+        assert_eq!(relative.triangles[2].n0, Vec3::Y);
+        assert_eq!(relative.triangles[2].uv1, Vec2::new(1.0, 0.5));
+        assert_eq!(relative.triangles[0].material.diffuse, Vec3::new(0.8, 0.1, 0.1));
+        assert_eq!(relative.triangles[2].material.diffuse, Vec3::new(0.1, 0.1, 0.8));
+    }
+
+    #[test]
+    fn degenerate_faces_are_skipped_and_counted() {
+        let loaded = Scene::load_obj(&fixture("degenerate.obj"), Mat4::IDENTITY).unwrap();
+        assert_eq!(loaded.degenerate, 1);
+        assert_eq!(loaded.triangles.len(), 1);
+        assert_eq!([loaded.triangles[0].v0, loaded.triangles[0].v1, loaded.triangles[0].v2], [Vec3::ZERO, Vec3::X, Vec3::Y]);
+    }
+
+    // 从 +z 方向射向三角面，返回是否击中正面
+    fn hits_front_face(triangle: &Triangle) -> bool {
+        let center = (triangle.v0 + triangle.v1 + triangle.v2) / 3.0;
+        let ray = Ray::new(center + Vec3::Z, -Vec3::Z);
+        triangle.hit(&ray, T_MIN, f32::INFINITY).unwrap().front_face
+    }
+
+    #[test]
+    fn mirrored_transform_keeps_front_face() {
+        let mirror = Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0));
+        for transform in [Mat4::IDENTITY, mirror] {
+            let mut ply = Scene::new();
+            ply.add_ply(&fixture("triangle.ply"), transform).unwrap();
+            let mut stl = Scene::new();
+            stl.add_stl(&fixture("triangle.stl"), transform, Material::PLASTER, false).unwrap();
+            let obj = Scene::load_obj(&fixture("relative_indices.obj"), transform).unwrap();
+            assert!(hits_front_face(ply.objects[0].as_triangle().unwrap()));
+            assert!(hits_front_face(stl.objects[0].as_triangle().unwrap()));
+            assert!(hits_front_face(&obj.triangles[0]));
+            assert_eq!(obj.triangles[0].n0, Vec3::Z);
+        }
+    }
+
+    #[test]
+    fn set_material_reshades_one_object() {
+        let mut scene = Scene::new();
+        scene.add_obj(&fixture("relative_indices.obj"), Mat4::IDENTITY);
+        scene.build_bvh();
+        let mut scene = Arc::new(scene);
+        let camera = Arc::new(crate::camera::Camera::new(
+            Vec3::new(0.5, 0.5, 3.0), Vec3::new(0.5, 0.5, 0.0), Vec3::Y, 5.0, 1.0, 1.0, 0.0,
+        ));
+        let brightness = |scene: &Arc<Scene>| {
+            let options = crate::render::RenderOptions { seed: Some(1), ..Default::default() };
+            let image = crate::render::render_hdr(scene.clone(), camera.clone(), 2, 2, 2, 4, &options);
+            image.iter().sum::<f32>()
+        };
+        // 没有光源与环境光，漫反射的四边形是黑色的
+        assert_eq!(brightness(&scene), 0.0);
+
+        // 四边形的两个三角面换成发光材质，另一个物体不变
+        let reshaded = Arc::get_mut(&mut scene).unwrap().set_material("quad", Material::LUMINOUS);
+        assert_eq!(reshaded, 2);
+        assert!(brightness(&scene) > 0.0);
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 3.0), Vec3::NEG_Z);
+        assert_eq!(scene.hit(&ray, 0.001, f32::INFINITY).unwrap().material.emissive, Material::LUMINOUS.emissive);
+        let behind = Ray::new(Vec3::new(0.2, 0.2, -3.0), Vec3::Z);
+        assert_eq!(scene.hit(&behind, 0.001, f32::INFINITY).unwrap().material.diffuse, Vec3::new(0.1, 0.1, 0.8));
+        assert_eq!(Arc::get_mut(&mut scene).unwrap().set_material("missing", Material::LUMINOUS), 0);
+    }
+
+    #[test]
+    fn missing_mtl_still_loads_geometry() {
+        let loaded = Scene::load_obj(&fixture("missing_mtl.obj"), Mat4::IDENTITY).unwrap();
+        assert!(loaded.materials.is_empty());
+        assert_eq!(loaded.triangles.len(), 2);
+        for triangle in &loaded.triangles {
+            assert_eq!(triangle.material_name.as_deref(), Some("default"));
+            assert_eq!(triangle.material.diffuse, Material::PLASTER.diffuse);
+        }
+
+        let mut scene = Scene::new();
+        assert_eq!(scene.add_obj(&fixture("missing_mtl.obj"), Mat4::IDENTITY), 0);
+        assert_eq!(scene.objects.len(), 2);
+        assert!(scene.materials.is_empty());
+    }
+
+    #[test]
+    fn override_material_after_build_bvh() {
+        let mut scene = Scene::new();
+        scene.add_obj(&fixture("relative_indices.obj"), Mat4::IDENTITY);
+        scene.build_bvh();
+        let mirror = Material { diffuse: Vec3::ZERO, ..Material::MIRROR };
+        assert!(scene.override_material("red", mirror) > 0);
+        assert!(scene.bvh.is_some());
+        let red = scene.objects.iter().find(|object| object.material_name() == Some("red")).unwrap();
+        let center = red.bounding_box().center();
+        let hit = scene.intersect(&Ray::new(center + Vec3::Z, Vec3::NEG_Z)).unwrap();
+        assert_eq!(hit.material.diffuse, Vec3::ZERO);
+    }
+
+    #[test]
+    fn override_emission_updates_lights() {
+        let mut scene = Scene::new();
+        scene.add_obj(&fixture("emissive_quad.obj"), Mat4::IDENTITY);
+        scene.add_obj(&fixture("relative_indices.obj"), Mat4::IDENTITY);
+        scene.build_bvh();
+        let light = scene.materials["light"].clone();
+        let pdf = scene.light_pdf(&light);
+        assert!((pdf - 1.0).abs() < 1e-5);
+
+        // 红色正方形与原光源面积、辐亮度相同，总功率加倍
+        scene.override_emission("red", Vec3::new(17.0, 12.0, 4.0));
+        assert_eq!(scene.lights.len(), 4);
+        assert!((scene.light_pdf(&light) - 0.5 * pdf).abs() < 1e-5);
+        let (picked, probability) = scene.pick_light_with(0.99).unwrap();
+        assert_eq!(picked.material_name(), Some("red"));
+        assert!((probability - 0.25).abs() < 1e-6);
+
+        // 关闭原光源后只剩红色正方形
+        scene.override_emission("light", Vec3::ZERO);
+        assert_eq!(scene.lights.len(), 2);
+        let (picked, probability) = scene.pick_light_with(0.0).unwrap();
+        assert_eq!(picked.material_name(), Some("red"));
+        assert!((probability - 0.5).abs() < 1e-6);
+        assert_eq!(scene.light_pdf(&scene.materials["light"]), 0.0);
+    }
+
+    #[test]
+    fn ke_intensity_changes_light_power() {
+        let mtl = "newmtl dim\nKa 0 0 0\nKd 0 0 0\nKs 0 0 0\nNs 10\nKe 17 12 4\nKe_intensity 1\n\
+                   newmtl bright\nKa 0 0 0\nKd 0 0 0\nKs 0 0 0\nNs 10\nKe 17 12 4\nKe_intensity 3\n";
+        let (materials, _) = tobj::load_mtl_buf(&mut mtl.as_bytes()).unwrap();
+        let mut scene = Scene::new();
+        for (material, x) in materials.iter().zip([0.0, 2.0]) {
+            let vertices = vec![Vec3::new(x, 0.0, 0.0), Vec3::new(x + 1.0, 0.0, 0.0), Vec3::new(x, 1.0, 0.0)];
+            scene.add(Box::new(Triangle::new(vertices, vec![], vec![], Material::from_mtl(material, "."))));
+        }
+        scene.build_bvh();
+        // 颜色与面积相同，功率之比等于强度之比
+        let (dim, dim_probability) = scene.pick_light_with(0.0).unwrap();
+        let (bright, bright_probability) = scene.pick_light_with(0.99).unwrap();
+        assert_eq!(dim.material().unwrap().emissive_color(), Vec3::new(17.0, 12.0, 4.0));
+        assert_eq!(bright.material().unwrap().emissive_color(), Vec3::new(51.0, 36.0, 12.0));
+        assert!((dim_probability - 0.25).abs() < 1e-6);
+        assert!((bright_probability - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn instanced_emitters_both_illuminate() {
+        let json = format!(
+            r#"{{ "nodes": [
+                {{ "obj": "{0}", "transform": {{ "translate": [-2.0, 0.0, 0.0] }} }},
+                {{ "obj": "{0}", "transform": {{ "translate": [2.0, 0.0, 0.0] }} }}
+            ] }}"#,
+            fixture("emissive_quad.obj"),
+        );
+        let mut scene = Scene::new();
+        graph::SceneGraph::from_json(&json).unwrap().add_to(&mut scene).unwrap();
+        scene.build_bvh();
+
+        // 每个实例的两个三角面都是独立的光源，功率相同
+        assert_eq!(scene.lights.len(), 4);
+        let mut picked = [0.0; 2];
+        for u in [0.1, 0.3, 0.6, 0.9] {
+            let (light, probability) = scene.pick_light_with(u).unwrap();
+            assert!((probability - 0.25).abs() < 1e-6);
+            picked[(light.bounding_box().center().x > 0.0) as usize] += probability;
+        }
+        assert_eq!(picked, [0.5, 0.5]);
+
+        // 从地面看向两个实例，都能看到朝下的发光面
+        for x in [-2.0, 2.0] {
+            let ray = Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::Y);
+            let hit = scene.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            assert!(hit.point.abs_diff_eq(Vec3::new(x, 1.0, 0.0), 1e-5));
+            assert_eq!(hit.material.emitted(&hit), Vec3::new(17.0, 12.0, 4.0));
+        }
+    }
+
+    #[test]
+    fn missing_obj_in_scene_graph_is_an_error() {
+        let json = format!(r#"{{ "nodes": [{{ "obj": "{}" }}] }}"#, fixture("does_not_exist.obj"));
+        let graph = graph::SceneGraph::from_json(&json).unwrap();
+        let mut scene = Scene::new();
+        let error = graph.add_to(&mut scene).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(scene.objects.is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use glam::{Mat4, Vec3};
+use crate::material::Material;
+use super::primitive::Triangle;
+use super::{invalid_data, push_triangle, LoadedObj, MeshTransform, Scene};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+// 属性的数值类型
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Scalar {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::Int8,
+            "uchar" | "uint8" => Self::UInt8,
+            "short" | "int16" => Self::Int16,
+            "ushort" | "uint16" => Self::UInt16,
+            "int" | "int32" => Self::Int32,
+            "uint" | "uint32" => Self::UInt32,
+            "float" | "float32" => Self::Float32,
+            "double" | "float64" => Self::Float64,
+            _ => return Err(invalid_data(format!("Unknown PLY property type {name}"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::Int8 | Self::UInt8 => 1,
+            Self::Int16 | Self::UInt16 => 2,
+            Self::Int32 | Self::UInt32 | Self::Float32 => 4,
+            Self::Float64 => 8,
+        }
+    }
+
+    // 整数颜色分量的最大值，用于换算到 [0.0, 1.0]，浮点颜色保持原值
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::Int8 => i8::MAX as f64,
+            Self::UInt8 => u8::MAX as f64,
+            Self::Int16 => i16::MAX as f64,
+            Self::UInt16 => u16::MAX as f64,
+            Self::Int32 => i32::MAX as f64,
+            Self::UInt32 => u32::MAX as f64,
+            Self::Float32 | Self::Float64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: Scalar,
+    list: Option<Scalar>, // 列表属性的长度类型，例如面的 `property list uchar int vertex_indices`
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// 文件头之后的数据，按属性类型依次读取数值
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], offset: usize, big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, kind: Scalar) -> io::Result<f64> {
+        match self {
+            Body::Ascii(tokens) => tokens.next()
+                .and_then(|token| token.parse::<f64>().ok())
+                .ok_or_else(|| invalid_data("Unexpected end or invalid number in PLY data")),
+            Body::Binary { bytes, offset, big_endian } => {
+                let size = kind.size();
+                let data = bytes.get(*offset..*offset + size)
+                    .ok_or_else(|| invalid_data("Unexpected end of PLY data"))?;
+                *offset += size;
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(data);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                Ok(match kind {
+                    Scalar::Int8 => buffer[0] as i8 as f64,
+                    Scalar::UInt8 => buffer[0] as f64,
+                    Scalar::Int16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    Scalar::UInt16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    Scalar::Int32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    Scalar::UInt32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    Scalar::Float32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    Scalar::Float64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// 将 .ply 模型（ASCII 或二进制，小端或大端）加载到场景中，返回因退化而跳过的三角面数量
+    ///
+    /// 读取顶点的 x、y、z，可选的法向 nx、ny、nz 与顶点颜色 red、green、blue（整数按类型的最大值换算到
+    /// [0.0, 1.0]），面由 vertex_indices（或 vertex_index）列表给出，多边形按扇形拆分为三角形。
+    /// 所有面使用默认材质 `Material::PLASTER`（材质名为 `default`），顶点颜色与漫反射颜色相乘。
+    /// 没有面的点云会给出警告并跳过，不加入任何物体
+    pub fn add_ply(&mut self, file_path: &str, transform: Mat4) -> io::Result<usize> {
+        let ply = Self::load_ply(file_path, transform)?;
+        Ok(self.merge_obj(ply))
+    }
+
+    // 读取并解析 .ply 文件，转换为三角形，不修改场景
+    fn load_ply(file_path: &str, transform: Mat4) -> io::Result<LoadedObj> {
+        let bytes = fs::read(file_path)?;
+        let (format, elements, body_start) = parse_header(&bytes)?;
+        let body = &bytes[body_start..];
+        let mut body = match format {
+            Format::Ascii => Body::Ascii(
+                std::str::from_utf8(body).map_err(|error| invalid_data(error.to_string()))?.split_ascii_whitespace(),
+            ),
+            Format::BinaryLittleEndian => Body::Binary { bytes: body, offset: 0, big_endian: false },
+            Format::BinaryBigEndian => Body::Binary { bytes: body, offset: 0, big_endian: true },
+        };
+
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut colors = vec![];
+        let mut faces = vec![];
+        for element in &elements {
+            let find = |name: &str| element.properties.iter()
+                .position(|property| property.name == name && property.list.is_none());
+            let position_index = ["x", "y", "z"].map(find);
+            let normal_index = ["nx", "ny", "nz"].map(find);
+            let color_index = ["red", "green", "blue"].map(find);
+            let face_index = element.properties.iter().position(|property| {
+                property.list.is_some() && matches!(property.name.as_str(), "vertex_indices" | "vertex_index")
+            });
+            let has_normals = normal_index.iter().all(Option::is_some);
+            let has_colors = color_index.iter().all(Option::is_some);
+            if element.name == "vertex" && position_index.iter().any(Option::is_none) {
+                return Err(invalid_data("PLY vertices have no x, y, z properties"));
+            }
+
+            let mut values = vec![0.0; element.properties.len()];
+            for _ in 0..element.count {
+                for (i, property) in element.properties.iter().enumerate() {
+                    match property.list {
+                        Some(count_kind) => {
+                            let count = body.read(count_kind)? as usize;
+                            let items = (0..count).map(|_| body.read(property.kind))
+                                .collect::<io::Result<Vec<_>>>()?;
+                            if element.name == "face" && Some(i) == face_index {
+                                // 多边形按扇形拆分为三角形
+                                let items = items.into_iter().map(|item| item as usize).collect::<Vec<_>>();
+                                for k in 1..items.len().saturating_sub(1) {
+                                    faces.push([items[0], items[k], items[k + 1]]);
+                                }
+                            }
+                        }
+                        None => values[i] = body.read(property.kind)?,
+                    }
+                }
+                if element.name != "vertex" {
+                    continue;
+                }
+                let vector = |index: [Option<usize>; 3], scale: [f64; 3]| Vec3::new(
+                    (values[index[0].unwrap()] / scale[0]) as f32,
+                    (values[index[1].unwrap()] / scale[1]) as f32,
+                    (values[index[2].unwrap()] / scale[2]) as f32,
+                );
+                positions.push(vector(position_index, [1.0; 3]));
+                if has_normals {
+                    normals.push(vector(normal_index, [1.0; 3]));
+                }
+                if has_colors {
+                    let scale = color_index.map(|index| element.properties[index.unwrap()].kind.color_scale());
+                    colors.push(vector(color_index, scale));
+                }
+            }
+        }
+
+        let object_name: Arc<str> = Arc::from(
+            Path::new(file_path).file_stem().map_or("ply".into(), |stem| stem.to_string_lossy()).as_ref(),
+        );
+        if faces.is_empty() {
+            eprintln!("Warning: {file_path} has no faces (point cloud?), skipped");
+        }
+        if let Some(index) = faces.iter().flatten().find(|index| **index >= positions.len()) {
+            return Err(invalid_data(format!("Vertex index {index} is out of range")));
+        }
+
+        let mesh_transform = MeshTransform::new(transform);
+        let material_name = Arc::<str>::from("default");
+        let material = Arc::new(Material::PLASTER);
+        let mut triangles = vec![];
+        let mut degenerate = 0;
+        for face in faces {
+            let face = mesh_transform.face(face);
+            let vertices = face.iter().map(|i| mesh_transform.point(positions[*i])).collect();
+            let normals = if normals.is_empty() {
+                vec![]
+            } else {
+                face.iter().map(|i| mesh_transform.normal(normals[*i])).collect()
+            };
+            let colors = if colors.is_empty() {
+                vec![]
+            } else {
+                face.iter().map(|i| colors[*i]).collect()
+            };
+            let triangle = Triangle::new(vertices, normals, vec![], material.clone())
+                .with_colors(colors);
+            push_triangle(&mut triangles, &mut degenerate, triangle, Some(&material_name), &object_name);
+        }
+
+        Ok(LoadedObj { file_path: file_path.to_string(), materials: vec![], triangles, degenerate })
+    }
+}
+
+// 解析文件头，返回数据格式、各元素的定义与数据开始的位置
+fn parse_header(bytes: &[u8]) -> io::Result<(Format, Vec<Element>, usize)> {
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut offset = 0;
+    let mut first = true;
+    loop {
+        let end = bytes[offset..].iter().position(|byte| *byte == b'\n')
+            .ok_or_else(|| invalid_data("PLY header has no end_header"))?;
+        let line = String::from_utf8_lossy(&bytes[offset..offset + end]);
+        offset += end + 1;
+        let words = line.split_ascii_whitespace().collect::<Vec<_>>();
+        if first {
+            if words != ["ply"] {
+                return Err(invalid_data("Not a PLY file"));
+            }
+            first = false;
+            continue;
+        }
+        match words.as_slice() {
+            ["format", kind, _version] => format = Some(match *kind {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::BinaryLittleEndian,
+                "binary_big_endian" => Format::BinaryBigEndian,
+                _ => return Err(invalid_data(format!("Unknown PLY format {kind}"))),
+            }),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid_data(format!("Invalid element count {count}")))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_kind, kind, name] => elements.last_mut()
+                .ok_or_else(|| invalid_data("PLY property outside of an element"))?
+                .properties.push(Property {
+                    name: name.to_string(),
+                    kind: Scalar::parse(kind)?,
+                    list: Some(Scalar::parse(count_kind)?),
+                }),
+            ["property", kind, name] => elements.last_mut()
+                .ok_or_else(|| invalid_data("PLY property outside of an element"))?
+                .properties.push(Property { name: name.to_string(), kind: Scalar::parse(kind)?, list: None }),
+            ["end_header"] => break,
+            // comment、obj_info 与空行
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid_data("PLY header has no format"))?;
+    Ok((format, elements, offset))
+}
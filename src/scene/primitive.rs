@@ -1,60 +1,215 @@
+use std::sync::Arc;
 use glam::{Vec2, Vec3};
-use crate::material::Material;
+use crate::material::{luminance, Material};
+use crate::rand_util;
+use crate::stats;
 use crate::ray::Ray;
-use crate::scene::{HitRecord, Hittable};
+use crate::render::T_MIN;
+use crate::scene::{ConeHit, HitRecord, Hittable, SurfaceSample};
 use crate::scene::bvh::AABB;
+use crate::texture::{self, Texture};
 
 /// 球体
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Sphere {
     pub center: Vec3,  // 球心
     pub radius: f32,   // 半径
-    pub material: Material, // 材质
+    pub material: Arc<Material>, // 材质
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f32, material: Material) -> Self {
-        Sphere { center, radius, material }
+    pub fn new(center: Vec3, radius: f32, material: impl Into<Arc<Material>>) -> Self {
+        Sphere { center, radius, material: material.into() }
+    }
+
+    /// 由碰撞时间生成碰撞记录
+    pub fn hit_record(&self, ray: &Ray, t: f32) -> HitRecord<'_> {
+        sphere_hit_record(self.center, self.radius, &self.material, ray, t)
     }
 }
 
 impl Hittable for Sphere {
-    /// 在时刻 `t` 光线到达的点为 `t * ray.direction`，
-    /// 设圆心到光线始点的向量为 `oc = ray.origin - self.center`，
-    /// 则交点满足方程 `|(t * ray.direction + oc)| = self.radius`。
-    /// 求解交点即为求解此一元二次方程。
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let half_b = oc.dot(ray.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - c;
-        if discriminant > 0.0 {
-            let sqrt_d = discriminant.sqrt();
-            let mut root = -half_b - sqrt_d;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        AABB::new(
+            self.center - Vec3::new(self.radius, self.radius, self.radius),
+            self.center + Vec3::new(self.radius, self.radius, self.radius),
+        )
+    }
+
+    fn as_sphere(&self) -> Option<&Sphere> {
+        Some(self)
+    }
+
+    fn with_material(&self, material: Arc<Material>) -> Option<Box<dyn Hittable + Sync + Send>> {
+        Some(Box::new(Sphere { material, ..self.clone() }))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
+
+    /// 球面上按面积均匀采样，z 在 [-1, 1] 上均匀分布
+    fn sample_point(&self) -> Option<SurfaceSample> {
+        let z = 1.0 - 2.0 * rand_util::random::<f32>();
+        let phi = 2.0 * std::f32::consts::PI * rand_util::random::<f32>();
+        let r = (1.0 - z * z).sqrt();
+        let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        Some(SurfaceSample { point: self.center + self.radius * normal, normal })
+    }
+
+    /// 在 `reference` 看向球体的立体角圆锥内均匀采样方向，取其与球面的第一个交点，
+    /// 只采样可见的球冠，球体小而远时比均匀采样整个球面的噪点少得多（PBRT 中的方法）；
+    /// `reference` 在球内时退回均匀采样
+    fn sample_point_from(&self, reference: Vec3) -> Option<(SurfaceSample, f32)> {
+        let to_center = self.center - reference;
+        let distance_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared * 1.0001 {
+            return self.sample_point().map(|sample| (sample, 1.0 / self.area()));
+        }
+
+        // 在 [cos_theta_max, 1] 上均匀采样圆锥内方向与球心方向夹角的余弦
+        let distance = distance_squared.sqrt();
+        let sin_theta_max_squared = radius_squared / distance_squared;
+        let cos_theta_max = (1.0 - sin_theta_max_squared).max(0.0).sqrt();
+        let cos_theta = 1.0 - rand_util::random::<f32>() * (1.0 - cos_theta_max);
+        let sin_theta_squared = (1.0 - cos_theta * cos_theta).max(0.0);
+        let phi = 2.0 * std::f32::consts::PI * rand_util::random::<f32>();
+
+        // 由方向的夹角求交点相对球心的夹角 alpha，直接构造交点，避免求交的数值误差
+        let sample_distance = distance * cos_theta
+            - (radius_squared - distance_squared * sin_theta_squared).max(0.0).sqrt();
+        let cos_alpha = ((distance_squared + radius_squared - sample_distance * sample_distance)
+            / (2.0 * distance * self.radius)).clamp(-1.0, 1.0);
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+        let axis = -to_center / distance;
+        let (tangent, bitangent) = axis.any_orthonormal_pair();
+        let normal = (tangent * phi.cos() + bitangent * phi.sin()) * sin_alpha + axis * cos_alpha;
+        let point = self.center + self.radius * normal;
+
+        // 立体角概率密度为 1 / (2π (1 - cos_theta_max))，换算为面积概率密度
+        let pdf_solid_angle = 1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max));
+        let to_point = point - reference;
+        let cos_light = normal.dot(-to_point.normalize()).abs();
+        let pdf = pdf_solid_angle * cos_light / to_point.length_squared();
+        // 采样点恰好落在轮廓上时 cos_light 为 0，面积概率密度为 0，不能用于估计
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((SurfaceSample { point, normal }, pdf))
+    }
+}
+
+/// 在时刻 `t` 光线到达的点为 `t * ray.direction`，
+/// 设圆心到光线始点的向量为 `oc = ray.origin - center`，
+/// 则交点满足方程 `|(t * ray.direction + oc)| = radius`。
+/// 求解交点即为求解此一元二次方程。
+fn hit_sphere<'a>(
+    center: Vec3,
+    radius: f32,
+    material: &'a Material,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'a>> {
+    let oc = ray.origin - center;
+    let half_b = oc.dot(ray.direction);
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = half_b * half_b - c;
+    if discriminant > 0.0 {
+        let sqrt_d = discriminant.sqrt();
+        let mut root = -half_b - sqrt_d;
+        if root < t_min || root > t_max {
+            root = -half_b + sqrt_d;
             if root < t_min || root > t_max {
-                root = -half_b + sqrt_d;
-                if root < t_min || root > t_max {
-                    return None;
-                }
+                return None;
             }
+        }
+        return Some(sphere_hit_record(center, radius, material, ray, root));
+    }
+    None
+}
+
+fn sphere_hit_record<'a>(center: Vec3, radius: f32, material: &'a Material, ray: &Ray, t: f32) -> HitRecord<'a> {
+    let point = ray.at(t);
+    let normal = (point - center) / radius;
+    let mut hit_record = HitRecord::new(ray, point, normal, t, texture::spherical_uv(normal), material);
+    // 切向量沿纬线方向，即 u 增大的方向，在两极处退化时保留默认值
+    let tangent = Vec3::Y.cross(normal);
+    if tangent.length_squared() > f32::EPSILON {
+        hit_record.tangent = tangent.normalize();
+    }
+    hit_record
+}
+
+/// 运动的球体，球心在 `time0` 到 `time1` 之间从 `center0` 匀速移动到 `center1`，
+/// 配合摄像机的快门时间产生运动模糊
+#[derive(Debug, Clone)]
+pub struct MovingSphere {
+    pub center0: Vec3, // time0 时刻的球心
+    pub center1: Vec3, // time1 时刻的球心
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Arc<Material>,
+}
 
-            let point = ray.at(root);
-            let normal = (point - self.center) / self.radius;
-            return Some(HitRecord::new(point, normal, root, self.material));
+impl MovingSphere {
+    pub fn new(
+        center0: Vec3,
+        center1: Vec3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+        material: impl Into<Arc<Material>>,
+    ) -> Self {
+        MovingSphere { center0, center1, time0, time1, radius, material: material.into() }
+    }
+
+    /// 时刻 `time` 的球心，时间区间之外保持在端点处
+    pub fn center(&self, time: f32) -> Vec3 {
+        if self.time1 == self.time0 {
+            return self.center0;
         }
-        None
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0.lerp(self.center1, t)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center(ray.time), self.radius, &self.material, ray, t_min, t_max)
     }
 
+    /// 包含整个运动过程扫过的范围
     fn bounding_box(&self) -> AABB {
+        let radius = Vec3::splat(self.radius);
         AABB::new(
-            self.center - Vec3::new(self.radius, self.radius, self.radius),
-            self.center + Vec3::new(self.radius, self.radius, self.radius),
+            self.center0.min(self.center1) - radius,
+            self.center0.max(self.center1) + radius,
         )
     }
+
+    fn with_material(&self, material: Arc<Material>) -> Option<Box<dyn Hittable + Sync + Send>> {
+        Some(Box::new(MovingSphere { material, ..self.clone() }))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
 }
 
 /// 三角面
+#[derive(Clone)]
 pub struct Triangle {
     pub v0: Vec3, // 顶点
     pub v1: Vec3,
@@ -65,22 +220,28 @@ pub struct Triangle {
     pub uv0: Vec2, // 顶点贴图坐标
     pub uv1: Vec2,
     pub uv2: Vec2,
-    pub material: Material,
+    pub c0: Vec3, // 顶点颜色
+    pub c1: Vec3,
+    pub c2: Vec3,
+    pub material: Arc<Material>,
+    pub material_name: Option<Arc<str>>, // 来源材质名，由 Scene::add_obj 设置
+    pub object_name: Option<Arc<str>>, // 所属的物体或组名（OBJ 中的 o、g），由 Scene::add_obj 设置
 }
 
 impl Triangle {
     pub fn new(vertices: Vec<Vec3>,
                normals: Vec<Vec3>,
                texcoords: Vec<Vec2>,
-               material: Material) -> Self {
+               material: impl Into<Arc<Material>>) -> Self {
         assert_eq!(vertices.len(), 3);
         let (v0, v1, v2) = (vertices[0], vertices[1], vertices[2]);
         let edge1 = v1 - v0;
         let edge2 = v2 - v0;
 
         let normals = if normals.is_empty() {
-            // 没有提供顶点法向的情况下，按 v0 v1 v2 顺序使用右手法则确定法线方向
-            let normal = edge1.cross(edge2).normalize();
+            // 没有提供顶点法向的情况下，按 v0 v1 v2 顺序使用右手法则确定法线方向，
+            // 退化三角面没有确定的法向，取任意方向以免出现 NaN
+            let normal = edge1.cross(edge2).try_normalize().unwrap_or(Vec3::Y);
             vec![normal, normal, normal]
         } else {
             assert_eq!(normals.len(), 3);
@@ -108,69 +269,189 @@ impl Triangle {
             uv0,
             uv1,
             uv2,
-            material,
+            // 没有提供顶点颜色的情况下，全都写 1，不影响材质颜色
+            c0: Vec3::ONE,
+            c1: Vec3::ONE,
+            c2: Vec3::ONE,
+            material: material.into(),
+            material_name: None,
+            object_name: None,
         }
     }
-}
 
-impl Hittable for Triangle {
-    /// 使用 Moller-Trumbore 方法判定光线与三角面的相交情况，
-    /// 即解方程 `[-ray.direction, edge1, edge2][t, v, w]^T=[ray.origin-v0]`。
-    /// 使用 Cramer's Rule 求解。
-    ///
-    /// 交点 `p` 满足 `p=u*v0+v*v1+w*v2`，其中 `u+v+w=1`
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let edge1 = self.v1 - self.v0;
-        let edge2 = self.v2 - self.v0;
+    /// 设置三个顶点的颜色，用于调制漫反射颜色，为空时保持白色
+    pub fn with_colors(self, colors: Vec<Vec3>) -> Self {
+        if colors.is_empty() {
+            return self;
+        }
+        assert_eq!(colors.len(), 3);
+        Self { c0: colors[0], c1: colors[1], c2: colors[2], ..self }
+    }
 
-        // edge1.dot(h) = det([-ray.direction, edge1, edge2])，使用混合积计算，下同
-        let h = ray.direction.cross(edge2);
-        let a = edge1.dot(h);
+    /// 是否为面积接近零的退化三角面，例如三个顶点共线，这样的三角面不会与光线相交
+    pub fn is_degenerate(&self) -> bool {
+        let (edge1, edge2) = (self.v1 - self.v0, self.v2 - self.v0);
+        let cross = edge1.cross(edge2);
+        // 与边长相比叉积很小时，三条边几乎共线
+        let scale = edge1.length_squared().max(edge2.length_squared());
+        !cross.is_finite() || cross.length_squared() <= (f32::EPSILON * scale).powi(2)
+    }
 
-        // 判断是否平行于三角面
-        if a.abs() < f32::EPSILON {
-            return None;
-        }
-        let f = 1.0 / a;
+    /// 能否在 BVH 叶子中批量求交，带镂空贴图的三角面需要逐个判定
+    pub fn batchable(&self) -> bool {
+        self.material.alpha_texture.is_none()
+    }
 
-        // s.dot(h) = det([-ray.direction, ray.origin - v0, edge2])
-        let s = ray.origin - self.v0;
-        let v = f * s.dot(h);
+    /// 由碰撞时间与重心坐标 v、w 生成碰撞记录
+    pub fn hit_record(&self, ray: &Ray, t: f32, v: f32, w: f32) -> HitRecord<'_> {
+        let u = 1.0 - v - w;
+        let uv = u * self.uv0 + v * self.uv1 + w * self.uv2;
+        let normal = u * self.n0 + v * self.n1 + w * self.n2;
 
-        // 检查参数 v 是否在 [0, 1] 范围内
-        if v < 0.0 || v > 1.0 {
-            return None;
+        // 正反面由绕序决定的几何法向判定，插值法向在掠射角处可能与几何法向位于光线的不同侧；
+        // 顶点法向与绕序不一致时，将插值法向翻转到几何法向一侧
+        let face_normal = (self.v1 - self.v0).cross(self.v2 - self.v0);
+        let front_face = ray.direction.dot(face_normal) < 0.0;
+        let normal = if normal.dot(face_normal) < 0.0 { -normal } else { normal };
+
+        let mut hit_record = HitRecord::with_front_face(ray.at(t), normal, front_face, t, uv, &self.material);
+        hit_record.color = u * self.c0 + v * self.c1 + w * self.c2;
+        hit_record.tangent = self.tangent();
+        hit_record.uv_footprint = self.uv_footprint(ray, hit_record.point, uv);
+        hit_record.edge_distance = edge_distance(self.v0, self.v1, self.v2, u, v, w);
+        if self.material.bump_texture.is_some() {
+            if let Some((dpdu, dpdv)) = self.uv_derivatives() {
+                hit_record.normal = self.material.bump_normal(hit_record.normal, dpdu, dpdv, uv);
+            }
         }
+        hit_record
+    }
 
-        // ray.direction.dot(q) = det([-ray.direction, edge1, ray.origin - v0])
-        let q = s.cross(edge1);
-        let w = f * ray.direction.dot(q);
+    // 将光线微分对应的两条相邻光线与三角面所在平面求交，用交点的贴图坐标与 uv 之差估计像素的覆盖宽度，
+    // 光线没有微分或相邻光线与平面平行时返回 0
+    fn uv_footprint(&self, ray: &Ray, point: Vec3, uv: Vec2) -> f32 {
+        let Some(differentials) = ray.differentials else {
+            return 0.0;
+        };
+        let (edge1, edge2) = (self.v1 - self.v0, self.v2 - self.v0);
+        let normal = edge1.cross(edge2);
+        let area2 = normal.length_squared();
+        if area2 == 0.0 {
+            return 0.0;
+        }
+        let uv_at = |origin: Vec3, direction: Vec3| {
+            let denominator = normal.dot(direction);
+            if denominator.abs() < f32::EPSILON {
+                return None;
+            }
+            let p = origin + direction * normal.dot(point - origin) / denominator;
+            // 平面上的点的重心坐标，可以位于三角面之外
+            let d = p - self.v0;
+            let v = d.cross(edge2).dot(normal) / area2;
+            let w = edge1.cross(d).dot(normal) / area2;
+            Some((1.0 - v - w) * self.uv0 + v * self.uv1 + w * self.uv2)
+        };
+        match (
+            uv_at(differentials.rx_origin, differentials.rx_direction),
+            uv_at(differentials.ry_origin, differentials.ry_direction),
+        ) {
+            (Some(uv_x), Some(uv_y)) => (uv_x - uv).abs().max_element().max((uv_y - uv).abs().max_element()),
+            _ => 0.0,
+        }
+    }
 
-        // 检查参数 w 是否在 [0, 1] 范围内，且 v + w <= 1
-        if w < 0.0 || v + w > 1.0 {
+    /// 三角面的切向量，指向贴图坐标 u 增大的方向，没有贴图坐标时沿 v0 到 v1 的边
+    pub fn tangent(&self) -> Vec3 {
+        match self.uv_derivatives() {
+            Some((dpdu, _)) if dpdu.length_squared() > 0.0 => dpdu.normalize(),
+            _ => (self.v1 - self.v0).normalize(),
+        }
+    }
+
+    // 位置对贴图坐标的偏导数 dp/du 与 dp/dv，由两条边与对应的贴图坐标之差解出，贴图坐标退化时返回 None
+    fn uv_derivatives(&self) -> Option<(Vec3, Vec3)> {
+        let (edge1, edge2) = (self.v1 - self.v0, self.v2 - self.v0);
+        let (duv1, duv2) = (self.uv1 - self.uv0, self.uv2 - self.uv0);
+        let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+        if determinant.abs() <= f32::EPSILON {
             return None;
         }
+        let dpdu = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+        let dpdv = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+        (dpdu.is_finite() && dpdv.is_finite()).then_some((dpdu, dpdv))
+    }
 
-        // edge2.dot(q) = det([ray.origin - v0, edge1, edge2])
-        let t = f * edge2.dot(q);
+    /// 对于以 `ray` 为轴、张角为 `spread` 的锥体，三角面是否比锥体在该处的截面更细，
+    /// 即最短的高小于锥体在重心处的直径，这样的三角面按点采样求交时容易产生锯齿
+    pub fn is_thin(&self, ray: &Ray, spread: f32) -> bool {
+        let (edge0, edge1, edge2) = (self.v1 - self.v0, self.v2 - self.v1, self.v0 - self.v2);
+        let longest = edge0.length().max(edge1.length()).max(edge2.length());
+        let altitude = edge0.cross(edge2).length() / longest;
+        let distance = ((self.v0 + self.v1 + self.v2) / 3.0 - ray.origin).length();
+        altitude < spread * distance
+    }
 
-        // 检查交点是否在光线范围内
-        if t < t_min || t > t_max {
+    /// 三角面覆盖锥体截面的比例：按透视投影到垂直于光线的平面上，在截面圆内按固定的螺旋点列计数；
+    /// 返回覆盖比例与锥体内最靠近轴线的一条击中三角面的光线，没有覆盖时返回 None
+    pub fn cone_coverage(&self, ray: &Ray, spread: f32) -> Option<ConeHit<'_>> {
+        const SAMPLES: usize = 32;
+        let tangent = ray.direction.any_orthonormal_vector();
+        let bitangent = ray.direction.cross(tangent);
+        let project = |vertex: Vec3| {
+            let offset = vertex - ray.origin;
+            let depth = offset.dot(ray.direction);
+            (depth > 0.0).then(|| Vec2::new(offset.dot(tangent), offset.dot(bitangent)) / depth)
+        };
+        let (p0, p1, p2) = (project(self.v0)?, project(self.v1)?, project(self.v2)?);
+        let radius = 0.5 * spread;
+        if p0.min(p1).min(p2).max_element() > radius || p0.max(p1).max(p2).min_element() < -radius {
             return None;
         }
 
-        let hit_point = ray.at(t);
+        // 点在投影三角形内时三个边函数同号
+        let edge = |a: Vec2, b: Vec2, p: Vec2| (b - a).perp_dot(p - a);
+        let inside = |p: Vec2| {
+            let (e0, e1, e2) = (edge(p0, p1, p), edge(p1, p2, p), edge(p2, p0, p));
+            (e0 >= 0.0 && e1 >= 0.0 && e2 >= 0.0) || (e0 <= 0.0 && e1 <= 0.0 && e2 <= 0.0)
+        };
+        // 黄金角螺旋，点在圆内近似均匀分布，由内向外排列
+        let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        let covered = (0..SAMPLES)
+            .map(|k| {
+                let r = radius * ((k as f32 + 0.5) / SAMPLES as f32).sqrt();
+                let (sin, cos) = (k as f32 * golden_angle).sin_cos();
+                Vec2::new(r * cos, r * sin)
+            })
+            .filter(|p| inside(*p))
+            .collect::<Vec<_>>();
+        let nearest = *covered.first()?;
+        let direction = (ray.direction + nearest.x * tangent + nearest.y * bitangent).normalize();
+        let cone_ray = Ray { direction, inv_direction: direction.recip(), ..*ray };
+        let hit = self.hit(&cone_ray, T_MIN, f32::INFINITY)?;
+        Some(ConeHit { hit, ray: cone_ray, coverage: covered.len() as f32 / SAMPLES as f32 })
+    }
+}
+
+impl Hittable for Triangle {
+    /// 交点 `p` 满足 `p=u*v0+v*v1+w*v2`，其中 `u+v+w=1`
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let cull = self.material.culls_backfaces();
+        let intersection = intersect_triangle(ray, self.v0, self.v1, self.v2, t_min, t_max, cull);
+        stats::count(|stats| {
+            stats.triangle_tests += 1;
+            stats.triangle_hits += intersection.is_some() as u64;
+        });
+        let (t, v, w) = intersection?;
+
         // 使用重心坐标进行插值
-        let u = 1.0 - v - w;
-        let normal = u * self.n0 + v * self.n1 + w * self.n2;
-        let uv = v * self.uv0 + v * self.uv1 + w * self.uv2;
+        let hit_record = self.hit_record(ray, t, v, w);
+
+        // 镂空处视为未击中，光线继续寻找更远的交点
+        if !self.material.alpha_test(hit_record.uv[0], hit_record.uv[1]) {
+            return None;
+        }
 
-        Some(HitRecord {
-            point: hit_point,
-            normal,
-            t,
-            material: self.material.sample(uv[0], uv[1]),
-        })
+        Some(hit_record)
     }
 
     fn bounding_box(&self) -> AABB {
@@ -178,4 +459,409 @@ impl Hittable for Triangle {
         let min = self.v0.min(self.v1).min(self.v2);
         AABB::new(min, max)
     }
-}
\ No newline at end of file
+
+    fn material_name(&self) -> Option<&str> {
+        self.material_name.as_deref()
+    }
+
+    fn object_name(&self) -> Option<&str> {
+        self.object_name.as_deref()
+    }
+
+    fn as_triangle(&self) -> Option<&Triangle> {
+        Some(self)
+    }
+
+    fn with_material(&self, material: Arc<Material>) -> Option<Box<dyn Hittable + Sync + Send>> {
+        Some(Box::new(Triangle { material, ..self.clone() }))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
+
+    fn area(&self) -> f32 {
+        0.5 * (self.v1 - self.v0).cross(self.v2 - self.v0).length()
+    }
+
+    /// 使用重心坐标均匀采样，外法向取几何法向，并翻转到与顶点法向相同的一侧
+    fn sample_point(&self) -> Option<SurfaceSample> {
+        let (r1, r2) = (rand_util::random::<f32>().sqrt(), rand_util::random::<f32>());
+        let (u, v, w) = (1.0 - r1, r1 * (1.0 - r2), r1 * r2);
+        let point = u * self.v0 + v * self.v1 + w * self.v2;
+
+        let normal = (self.v1 - self.v0).cross(self.v2 - self.v0).normalize();
+        let shading_normal = u * self.n0 + v * self.n1 + w * self.n2;
+        let normal = if normal.dot(shading_normal) < 0.0 { -normal } else { normal };
+        Some(SurfaceSample { point, normal })
+    }
+}
+
+// 重心坐标为 (u, v, w) 的点到三角面最近一条边的距离，
+// 到某个顶点对边的距离等于该顶点的重心坐标乘以该边上的高
+fn edge_distance(v0: Vec3, v1: Vec3, v2: Vec3, u: f32, v: f32, w: f32) -> f32 {
+    let area2 = (v1 - v0).cross(v2 - v0).length();
+    let height = |a: Vec3, b: Vec3| area2 / a.distance(b).max(f32::MIN_POSITIVE);
+    (u * height(v1, v2)).min(v * height(v2, v0)).min(w * height(v0, v1))
+}
+
+/// 使用 Moller-Trumbore 方法判定光线与三角面的相交情况，
+/// 即解方程 `[-ray.direction, edge1, edge2][t, v, w]^T=[ray.origin-v0]`。
+/// 使用 Cramer's Rule 求解，返回碰撞时间与 v1、v2 的重心坐标；`cull_backfaces` 为真时忽略从背面射入的光线
+fn intersect_triangle(
+    ray: &Ray,
+    v0: Vec3,
+    v1: Vec3,
+    v2: Vec3,
+    t_min: f32,
+    t_max: f32,
+    cull_backfaces: bool,
+) -> Option<(f32, f32, f32)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+
+    // edge1.dot(h) = det([-ray.direction, edge1, edge2])，使用混合积计算，下同
+    let h = ray.direction.cross(edge2);
+    let a = edge1.dot(h);
+
+    // 判断是否平行于三角面
+    if a.abs() < f32::EPSILON {
+        return None;
+    }
+    // a = -ray.direction.dot(edge1.cross(edge2))，小于零说明光线从背面射入
+    if a < 0.0 && cull_backfaces {
+        return None;
+    }
+    let f = 1.0 / a;
+
+    // s.dot(h) = det([-ray.direction, ray.origin - v0, edge2])
+    let s = ray.origin - v0;
+    let v = f * s.dot(h);
+
+    // 检查参数 v 是否在 [0, 1] 范围内
+    if !(0.0..=1.0).contains(&v) {
+        return None;
+    }
+
+    // ray.direction.dot(q) = det([-ray.direction, edge1, ray.origin - v0])
+    let q = s.cross(edge1);
+    let w = f * ray.direction.dot(q);
+
+    // 检查参数 w 是否在 [0, 1] 范围内，且 v + w <= 1
+    if w < 0.0 || v + w > 1.0 {
+        return None;
+    }
+
+    // edge2.dot(q) = det([ray.origin - v0, edge1, edge2])
+    let t = f * edge2.dot(q);
+
+    // 检查交点是否在光线范围内
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, v, w))
+}
+
+/// 高度场（地形），在 xz 平面上的矩形内按网格排列高度，每个网格单元由两个三角形组成，
+/// 求交时沿光线在网格中逐格前进（2D DDA），只测试光线经过的单元，不需要为每个三角形建立 BVH 节点
+#[derive(Debug, Clone)]
+pub struct HeightField {
+    pub origin: Vec3, // 网格第 0 行第 0 列的顶点在高度为 0 时的位置，网格沿 +x 与 +z 方向延伸
+    pub size: Vec2,   // 网格在 x 与 z 方向上的长度
+    pub material: Arc<Material>,
+    columns: usize,     // 每行的顶点数
+    rows: usize,        // 行数
+    heights: Vec<f32>,  // 各顶点相对 origin 的高度，按行优先排列，第 j 行的 z 坐标随 j 增大
+    normals: Vec<Vec3>, // 由高度的中心差分得到的顶点法向
+    min_height: f32,
+    max_height: f32,
+}
+
+impl HeightField {
+    const HEIGHT_EPSILON: f32 = 1e-4; // 跳过单元时高度比较的余量
+
+    /// 由按行优先排列的高度创建高度场，每行 `columns` 个顶点，行列数都至少为 2
+    pub fn new(
+        heights: Vec<f32>,
+        columns: usize,
+        origin: Vec3,
+        size: Vec2,
+        material: impl Into<Arc<Material>>,
+    ) -> Self {
+        assert!(
+            columns >= 2 && heights.len() % columns == 0 && heights.len() / columns >= 2,
+            "A height field needs at least 2 x 2 vertices"
+        );
+        let rows = heights.len() / columns;
+        let (dx, dz) = (size.x / (columns - 1) as f32, size.y / (rows - 1) as f32);
+
+        // 中心差分，边缘处使用单侧差分
+        let height = |i: usize, j: usize| heights[j * columns + i];
+        let normals = (0..rows).flat_map(|j| (0..columns).map(move |i| (i, j))).map(|(i, j)| {
+            let (i0, i1) = (i.saturating_sub(1), (i + 1).min(columns - 1));
+            let (j0, j1) = (j.saturating_sub(1), (j + 1).min(rows - 1));
+            let dh_dx = (height(i1, j) - height(i0, j)) / ((i1 - i0) as f32 * dx);
+            let dh_dz = (height(i, j1) - height(i, j0)) / ((j1 - j0) as f32 * dz);
+            Vec3::new(-dh_dx, 1.0, -dh_dz).normalize()
+        }).collect();
+
+        let min_height = heights.iter().copied().fold(f32::INFINITY, f32::min);
+        let max_height = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        HeightField {
+            origin,
+            size,
+            material: material.into(),
+            columns,
+            rows,
+            heights,
+            normals,
+            min_height,
+            max_height,
+        }
+    }
+
+    /// 由灰度贴图（例如 `Texture::load_from_file` 加载的高度图）创建高度场，每个像素为一个顶点，
+    /// 亮度 0 到 1 对应高度 0 到 `size.y`；图像的上方对应 -z 一侧（第 0 行）
+    pub fn from_texture(texture: &Texture, origin: Vec3, size: Vec3, material: impl Into<Arc<Material>>) -> Self {
+        let (width, height) = texture.dimensions();
+        let heights = (0..height).flat_map(|j| (0..width).map(move |i| (i, j))).map(|(i, j)| {
+            let u = (i as f32 + 0.5) / width as f32;
+            let v = 1.0 - (j as f32 + 0.5) / height as f32;
+            luminance(texture.sample(u, v)) * size.y
+        }).collect();
+        Self::new(heights, width as usize, origin, Vec2::new(size.x, size.z), material)
+    }
+
+    // 网格顶点 (i, j) 的位置
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        self.origin + Vec3::new(i as f32 * dx, self.heights[j * self.columns + i], j as f32 * dz)
+    }
+
+    fn cell_size(&self) -> (f32, f32) {
+        (self.size.x / (self.columns - 1) as f32, self.size.y / (self.rows - 1) as f32)
+    }
+
+    // 与单元 (i, j) 中的两个三角形求交
+    fn hit_cell(&self, ray: &Ray, i: usize, j: usize, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let corners = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)];
+        // 两个三角形的绕序使几何法向朝 +y
+        let mut closest = None;
+        let mut t_max = t_max;
+        for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+            let (ka, kb, kc) = (corners[a], corners[b], corners[c]);
+            let (v0, v1, v2) = (self.vertex(ka.0, ka.1), self.vertex(kb.0, kb.1), self.vertex(kc.0, kc.1));
+            let cull = self.material.culls_backfaces();
+            let Some((t, v, w)) = intersect_triangle(ray, v0, v1, v2, t_min, t_max, cull) else {
+                continue;
+            };
+            t_max = t;
+            closest = Some((t, v, w, [ka, kb, kc]));
+        }
+        let (t, v, w, vertices) = closest?;
+
+        let u = 1.0 - v - w;
+        let weights = [u, v, w];
+        let normal = vertices.iter().zip(weights)
+            .map(|((i, j), weight)| self.normals[j * self.columns + i] * weight)
+            .sum::<Vec3>();
+        let grid = vertices.iter().zip(weights)
+            .map(|((i, j), weight)| Vec2::new(*i as f32, *j as f32) * weight)
+            .sum::<Vec2>();
+        let uv = Vec2::new(grid.x / (self.columns - 1) as f32, 1.0 - grid.y / (self.rows - 1) as f32);
+
+        // 与三角面相同，正反面由几何法向判定，插值法向翻转到几何法向一侧
+        let [(i0, j0), (i1, j1), (i2, j2)] = vertices;
+        let (v0, v1, v2) = (self.vertex(i0, j0), self.vertex(i1, j1), self.vertex(i2, j2));
+        let face_normal = (v1 - v0).cross(v2 - v0);
+        let front_face = ray.direction.dot(face_normal) < 0.0;
+        let normal = if normal.dot(face_normal) < 0.0 { -normal } else { normal };
+
+        let mut hit_record = HitRecord::with_front_face(ray.at(t), normal.normalize(), front_face, t, uv, &self.material);
+        hit_record.edge_distance = edge_distance(v0, v1, v2, u, v, w);
+        // 切向量沿 +x 方向
+        if let Some(tangent) = (Vec3::X - hit_record.normal * hit_record.normal.x).try_normalize() {
+            hit_record.tangent = tangent;
+        }
+        if !self.material.alpha_test(uv.x, uv.y) {
+            return None;
+        }
+        Some(hit_record)
+    }
+}
+
+impl Hittable for HeightField {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // 先用包围盒裁剪光线
+        let bbox = self.bounding_box();
+        let t0 = (bbox.min - ray.origin) * ray.inv_direction;
+        let t1 = (bbox.max - ray.origin) * ray.inv_direction;
+        let t_enter = t0.min(t1).max_element().max(t_min);
+        let t_exit = t0.max(t1).min_element().min(t_max);
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // 从进入点所在的单元开始，每次走到光线先到达的 x 或 z 方向的单元边界
+        let (dx, dz) = self.cell_size();
+        let (last_i, last_j) = ((self.columns - 2) as i64, (self.rows - 2) as i64);
+        let start = ray.at(t_enter) - self.origin;
+        let mut i = ((start.x / dx).floor() as i64).clamp(0, last_i);
+        let mut j = ((start.z / dz).floor() as i64).clamp(0, last_j);
+
+        // 每个方向上的步进方向、到达下一条单元边界的时间、穿过一个单元所需的时间
+        let axis = |direction: f32, origin: f32, cell: i64, size: f32| {
+            if direction > 0.0 {
+                (1, ((cell + 1) as f32 * size - origin) / direction, size / direction)
+            } else if direction < 0.0 {
+                (-1, (cell as f32 * size - origin) / direction, -size / direction)
+            } else {
+                (0, f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let local_origin = ray.origin - self.origin;
+        let (step_i, mut next_i, delta_i) = axis(ray.direction.x, local_origin.x, i, dx);
+        let (step_j, mut next_j, delta_j) = axis(ray.direction.z, local_origin.z, j, dz);
+
+        let mut cell_enter = t_enter;
+        loop {
+            let cell_exit = next_i.min(next_j).min(t_exit);
+            // 光线在该单元内的高度范围与单元的高度范围不重叠时跳过，
+            // 留出少许余量，避免平坦的单元因舍入误差被跳过
+            let (y0, y1) = (ray.at(cell_enter).y, ray.at(cell_exit).y);
+            let (ci, cj) = (i as usize, j as usize);
+            let corners = [(ci, cj), (ci + 1, cj), (ci, cj + 1), (ci + 1, cj + 1)]
+                .map(|(i, j)| self.heights[j * self.columns + i] + self.origin.y);
+            let low = corners.into_iter().fold(f32::INFINITY, f32::min);
+            let high = corners.into_iter().fold(f32::NEG_INFINITY, f32::max);
+            if y0.min(y1) <= high + Self::HEIGHT_EPSILON && y0.max(y1) >= low - Self::HEIGHT_EPSILON {
+                if let Some(hit_record) = self.hit_cell(ray, ci, cj, t_min, t_max) {
+                    return Some(hit_record);
+                }
+            }
+
+            if cell_exit >= t_exit {
+                return None;
+            }
+            cell_enter = cell_exit;
+            if next_i < next_j {
+                i += step_i;
+                next_i += delta_i;
+            } else {
+                j += step_j;
+                next_j += delta_j;
+            }
+            if !(0..=last_i).contains(&i) || !(0..=last_j).contains(&j) {
+                return None;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> AABB {
+        AABB::new(
+            self.origin + Vec3::new(0.0, self.min_height, 0.0),
+            self.origin + Vec3::new(self.size.x, self.max_height, self.size.y),
+        )
+    }
+
+    fn with_material(&self, material: Arc<Material>) -> Option<Box<dyn Hittable + Sync + Send>> {
+        Some(Box::new(HeightField { material, ..self.clone() }))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Scene;
+
+    #[test]
+    fn moving_sphere_hits_follow_time() {
+        let sphere = MovingSphere::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(2.0, 0.0, -5.0), 0.0, 1.0, 0.5, Material::PLASTER);
+        let towards = |x: f32, time: f32| Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::NEG_Z).with_time(time);
+
+        let start = sphere.hit(&towards(0.0, 0.0), 0.001, f32::INFINITY).unwrap();
+        assert!(start.point.abs_diff_eq(Vec3::new(0.0, 0.0, -4.5), 1e-5), "{}", start.point);
+        assert!(sphere.hit(&towards(2.0, 0.0), 0.001, f32::INFINITY).is_none());
+
+        let end = sphere.hit(&towards(2.0, 1.0), 0.001, f32::INFINITY).unwrap();
+        assert!(end.point.abs_diff_eq(Vec3::new(2.0, 0.0, -4.5), 1e-5), "{}", end.point);
+        assert!(sphere.hit(&towards(0.0, 1.0), 0.001, f32::INFINITY).is_none());
+
+        // 场景的 BVH 使用整个运动过程扫过的包围盒，中间时刻同样能击中
+        let mut scene = Scene::new();
+        scene.add(Box::new(sphere));
+        scene.build_bvh();
+        let middle = scene.hit(&towards(1.0, 0.5), 0.001, f32::INFINITY).unwrap();
+        assert!(middle.point.abs_diff_eq(Vec3::new(1.0, 0.0, -4.5), 1e-5), "{}", middle.point);
+        assert!(scene.hit(&towards(2.0, 0.5), 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn collinear_triangle_is_degenerate_and_never_hit() {
+        let vertices = vec![Vec3::ZERO, Vec3::X, Vec3::X * 2.0];
+        let triangle = Triangle::new(vertices, vec![], vec![], Material::PLASTER);
+        assert!(triangle.is_degenerate());
+        for normal in [triangle.n0, triangle.n1, triangle.n2] {
+            assert!(normal.is_finite() && normal.is_normalized(), "{normal}");
+        }
+        // 沿各个方向射向三角面所在的线段
+        for direction in [Vec3::NEG_Y, Vec3::NEG_Z, Vec3::new(0.0, -1.0, -1.0)] {
+            let ray = Ray::new(Vec3::new(1.0, 0.0, 0.0) - direction, direction);
+            assert!(triangle.hit(&ray, 0.001, f32::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn triangle_reports_the_side_it_was_hit_from() {
+        let vertices = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+        let glass = Triangle::new(vertices.clone(), vec![], vec![], Material::GLASS);
+        let wall = Triangle::new(vertices, vec![], vec![], Material { two_sided: false, ..Material::PLASTER });
+        let from_front = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::NEG_Z);
+        let from_back = Ray::new(Vec3::new(0.25, 0.25, -1.0), Vec3::Z);
+
+        let front = glass.hit(&from_front, 0.001, f32::INFINITY).unwrap();
+        assert!(front.front_face);
+        assert_eq!(front.normal, Vec3::Z);
+        // 双面材质的法向朝向入射光线一侧，折射时据此判断光线是射入还是射出
+        let back = glass.hit(&from_back, 0.001, f32::INFINITY).unwrap();
+        assert!(!back.front_face);
+        assert_eq!(back.normal, Vec3::NEG_Z);
+        assert_eq!(front.point, back.point);
+
+        // 单面材质保持外法向
+        let back = wall.hit(&from_back, 0.001, f32::INFINITY).unwrap();
+        assert!(!back.front_face);
+        assert_eq!(back.normal, Vec3::Z);
+    }
+
+    // 从 +z 一侧看向 -z，+x 为右、+y 为上；贴图正立时 u 向右增大，v 向上增大
+    fn uv_seen_from_front(object: &dyn Hittable, x: f32, y: f32) -> Vec2 {
+        let ray = Ray::new(Vec3::new(x, y, 5.0), Vec3::NEG_Z);
+        object.hit(&ray, 0.001, f32::INFINITY).unwrap().uv
+    }
+
+    #[test]
+    fn triangle_uv_is_upright_from_the_front() {
+        let vertices = vec![Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0)];
+        let texcoords = vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)];
+        let triangle = Triangle::new(vertices, vec![], texcoords, Material::PLASTER);
+        assert!(uv_seen_from_front(&triangle, -1.0, -1.0).abs_diff_eq(Vec2::ZERO, 1e-5));
+        assert!(uv_seen_from_front(&triangle, 0.0, -0.5).abs_diff_eq(Vec2::new(0.5, 0.25), 1e-5));
+        assert!(uv_seen_from_front(&triangle, -0.5, 0.0).abs_diff_eq(Vec2::new(0.25, 0.5), 1e-5));
+    }
+
+    #[test]
+    fn sphere_uv_is_upright_from_the_front() {
+        let sphere = Sphere::new(Vec3::ZERO, 1.0, Material::PLASTER);
+        let center = uv_seen_from_front(&sphere, 0.0, 0.0);
+        assert!(center.abs_diff_eq(Vec2::new(0.5, 0.5), 1e-5), "{center}");
+        let right = uv_seen_from_front(&sphere, 0.5, 0.0);
+        assert!(right.x > 0.5 && (right.y - 0.5).abs() < 1e-5, "{right}");
+        let top = uv_seen_from_front(&sphere, 0.0, 0.5);
+        assert!(top.y > 0.5 && (top.x - 0.5).abs() < 1e-5, "{top}");
+    }
+}
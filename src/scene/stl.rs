@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use glam::{Mat4, Vec3};
+use crate::material::Material;
+use super::primitive::Triangle;
+use super::{invalid_data, push_triangle, LoadedObj, MeshTransform, Scene};
+
+// STL 中的一个面：面法向与三个顶点，法向可能为 0
+struct Facet {
+    normal: Vec3,
+    vertices: [Vec3; 3],
+}
+
+impl Scene {
+    /// 将 .stl 模型（ASCII 或二进制）加载到场景中，所有面使用 `material`，返回因退化而跳过的三角面数量
+    ///
+    /// STL 只有互不相连的三角面与面法向，没有材质。`smooth` 为 false 时每个面使用文件中的面法向
+    /// （为 0 时按顶点顺序计算），显示为平直的小平面；为 true 时合并坐标完全相同的顶点，
+    /// 按相邻面的面积加权平均得到顶点法向，曲面显得光滑，但立方体等模型的棱角也会被抹平
+    pub fn add_stl(&mut self,
+                   file_path: &str,
+                   transform: Mat4,
+                   material: impl Into<Arc<Material>>,
+                   smooth: bool) -> io::Result<usize> {
+        let stl = Self::load_stl(file_path, transform, material.into(), smooth)?;
+        Ok(self.merge_obj(stl))
+    }
+
+    // 读取并解析 .stl 文件，转换为三角形，不修改场景
+    fn load_stl(file_path: &str, transform: Mat4, material: Arc<Material>, smooth: bool) -> io::Result<LoadedObj> {
+        let bytes = fs::read(file_path)?;
+        // 部分二进制文件的文件头也以 solid 开头，因此先按二进制格式检查长度是否吻合
+        let binary_count = bytes.get(80..84).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+        let facets = match binary_count {
+            Some(count) if bytes.len() == 84 + count * 50 => parse_binary(&bytes[84..]),
+            _ if bytes.starts_with(b"solid") => parse_ascii(&bytes)?,
+            _ => return Err(invalid_data(format!("{file_path} is neither ASCII nor binary STL"))),
+        };
+
+        // 合并坐标相同的顶点，累加相邻面未归一化的叉积，即按面积加权；-0.0 与 0.0 视为同一坐标
+        let key = |v: Vec3| (v + Vec3::ZERO).to_array().map(f32::to_bits);
+        let mut smooth_normals = HashMap::new();
+        if smooth {
+            for facet in &facets {
+                let [v0, v1, v2] = facet.vertices;
+                let weighted = (v1 - v0).cross(v2 - v0);
+                for v in facet.vertices {
+                    *smooth_normals.entry(key(v)).or_insert(Vec3::ZERO) += weighted;
+                }
+            }
+        }
+
+        let mesh_transform = MeshTransform::new(transform);
+        let object_name: Arc<str> = Arc::from(
+            Path::new(file_path).file_stem().map_or("stl".into(), |stem| stem.to_string_lossy()).as_ref(),
+        );
+        let mut triangles = vec![];
+        let mut degenerate = 0;
+        for facet in facets {
+            let face = mesh_transform.face(facet.vertices);
+            let normals = if smooth {
+                face.iter().map(|v| smooth_normals[&key(*v)]).collect()
+            } else if facet.normal != Vec3::ZERO {
+                vec![facet.normal; 3]
+            } else {
+                vec![]
+            };
+            let mut normals = normals.into_iter()
+                .map(|normal| mesh_transform.normal(normal))
+                .collect::<Vec<_>>();
+            // 相邻面互相抵消的顶点没有确定的法向，整个面按顶点顺序计算
+            if normals.contains(&Vec3::ZERO) {
+                normals.clear();
+            }
+            let vertices = face.iter().map(|v| mesh_transform.point(*v)).collect();
+            let triangle = Triangle::new(vertices, normals, vec![], material.clone());
+            push_triangle(&mut triangles, &mut degenerate, triangle, None, &object_name);
+        }
+
+        Ok(LoadedObj { file_path: file_path.to_string(), materials: vec![], triangles, degenerate })
+    }
+}
+
+// 二进制 STL：80 字节文件头与面数之后，每个面为 12 个 f32（法向与三个顶点）与 2 字节的属性
+fn parse_binary(data: &[u8]) -> Vec<Facet> {
+    data.chunks_exact(50).map(|chunk| {
+        let value = |i: usize| f32::from_le_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+        let vector = |i: usize| Vec3::new(value(i), value(i + 1), value(i + 2));
+        Facet { normal: vector(0), vertices: [vector(3), vector(6), vector(9)] }
+    }).collect()
+}
+
+// ASCII STL：`facet normal nx ny nz`、`outer loop`、若干 `vertex x y z`、`endloop`、`endfacet`，
+// 多于三个顶点的面按扇形拆分为三角形
+fn parse_ascii(bytes: &[u8]) -> io::Result<Vec<Facet>> {
+    let text = std::str::from_utf8(bytes).map_err(|error| invalid_data(error.to_string()))?;
+    let mut tokens = text.split_ascii_whitespace();
+    fn vector(tokens: &mut std::str::SplitAsciiWhitespace) -> io::Result<Vec3> {
+        let mut next = || tokens.next()
+            .and_then(|token| token.parse::<f32>().ok())
+            .ok_or_else(|| invalid_data("Invalid number in ASCII STL"));
+        Ok(Vec3::new(next()?, next()?, next()?))
+    }
+
+    let mut facets = vec![];
+    let mut normal = Vec3::ZERO;
+    let mut vertices = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            "facet" => {
+                if tokens.next() != Some("normal") {
+                    return Err(invalid_data("Expected `normal` after `facet` in ASCII STL"));
+                }
+                normal = vector(&mut tokens)?;
+                vertices.clear();
+            }
+            "vertex" => vertices.push(vector(&mut tokens)?),
+            "endfacet" => {
+                for k in 1..vertices.len().saturating_sub(1) {
+                    facets.push(Facet { normal, vertices: [vertices[0], vertices[k], vertices[k + 1]] });
+                }
+            }
+            // solid、outer loop、endloop、endsolid 与名字
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
@@ -0,0 +1,66 @@
+use glam::{Mat3, Vec3};
+use once_cell::sync::Lazy;
+use crate::rand_util;
+
+/// 可见光波长范围（纳米）
+pub const WAVELENGTH_MIN: f32 = 380.0;
+pub const WAVELENGTH_MAX: f32 = 780.0;
+
+// RGB 到 XYZ 的矩阵的逆矩阵，第 j 列为第 j 个基函数光谱在 CIE 1931 标准观察者下的 XYZ，
+// 按 1 纳米步长数值积分得到
+static XYZ_TO_RGB: Lazy<Mat3> = Lazy::new(|| {
+    let mut columns = [Vec3::ZERO; 3];
+    let mut wavelength = WAVELENGTH_MIN + 0.5;
+    while wavelength < WAVELENGTH_MAX {
+        let xyz = cie_xyz(wavelength);
+        for (column, weight) in columns.iter_mut().zip(basis(wavelength).to_array()) {
+            *column += xyz * weight;
+        }
+        wavelength += 1.0;
+    }
+    Mat3::from_cols(columns[0], columns[1], columns[2]).inverse()
+});
+
+/// 在可见光范围内均匀采样一个波长
+pub fn sample_wavelength() -> f32 {
+    WAVELENGTH_MIN + rand_util::random::<f32>() * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// 将 RGB 系数或颜色转换为波长 `wavelength` 处的光谱值
+///
+/// 光谱为三个平滑基函数（分别集中在长、中、短波段）的线性组合，三者之和恒为 1，
+/// 因此白色与灰色对应平坦的光谱
+pub fn from_rgb(color: Vec3, wavelength: f32) -> f32 {
+    color.dot(basis(wavelength))
+}
+
+/// 将波长 `wavelength` 处的辐亮度样本转换为 RGB，其中已经除以 `sample_wavelength` 的概率密度，
+/// 对所有波长取平均即为 RGB 颜色；`from_rgb` 得到的光谱转换回来与原颜色一致
+pub fn to_rgb(radiance: f32, wavelength: f32) -> Vec3 {
+    *XYZ_TO_RGB * cie_xyz(wavelength) * radiance * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+// 长、中、短波段的基函数
+fn basis(wavelength: f32) -> Vec3 {
+    let smoothstep = |edge0: f32, edge1: f32| {
+        let t = ((wavelength - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    let red = smoothstep(560.0, 620.0);
+    let blue = 1.0 - smoothstep(450.0, 520.0);
+    Vec3::new(red, 1.0 - red - blue, blue)
+}
+
+/// CIE 1931 标准观察者的颜色匹配函数，使用 Wyman 等人的多段高斯拟合
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    let g = |mu: f32, sigma_left: f32, sigma_right: f32| {
+        let sigma = if wavelength < mu { sigma_left } else { sigma_right };
+        let t = (wavelength - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// 求交统计，用于判断渲染慢在 BVH 遍历还是着色，以及验证遍历优化的效果。
+/// 只有启用 `stats` feature 时才会计数，否则计数代码被编译器完全移除，所有值都为 0
+///
+/// 每个线程在线程局部变量中计数，渲染的每个工作单元结束时合并，线程之间没有竞争
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntersectionStats {
+    pub rays: u64, // 与场景求交的光线数，包括阴影光线
+    pub aabb_tests: u64, // 光线与包围盒的检查次数
+    pub triangle_tests: u64, // 光线与三角面的求交次数，包括批量求交中的每个三角面
+    pub triangle_hits: u64, // 其中在光线的有效区间内相交的次数
+}
+
+impl IntersectionStats {
+    /// 是否启用了计数，即编译时是否启用了 `stats` feature
+    pub const ENABLED: bool = cfg!(feature = "stats");
+
+    pub fn merge(&mut self, other: &Self) {
+        self.rays += other.rays;
+        self.aabb_tests += other.aabb_tests;
+        self.triangle_tests += other.triangle_tests;
+        self.triangle_hits += other.triangle_hits;
+    }
+
+    /// 三角面求交中相交的比例，没有求交时为 0
+    pub fn triangle_hit_rate(&self) -> f64 {
+        self.triangle_hits as f64 / self.triangle_tests.max(1) as f64
+    }
+}
+
+impl fmt::Display for IntersectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millions = |count: u64| count as f64 / 1e6;
+        write!(
+            f,
+            "{:.2} million rays, {:.2} million AABB tests ({:.1} per ray), {:.2} million triangle tests ({:.1} per ray), {:.1}% hit rate",
+            millions(self.rays),
+            millions(self.aabb_tests),
+            self.aabb_tests as f64 / self.rays.max(1) as f64,
+            millions(self.triangle_tests),
+            self.triangle_tests as f64 / self.rays.max(1) as f64,
+            self.triangle_hit_rate() * 100.0,
+        )
+    }
+}
+
+#[cfg(feature = "stats")]
+thread_local! {
+    static COUNTERS: std::cell::Cell<IntersectionStats> = const { std::cell::Cell::new(IntersectionStats {
+        rays: 0,
+        aabb_tests: 0,
+        triangle_tests: 0,
+        triangle_hits: 0,
+    }) };
+}
+
+// 更新当前线程的计数，未启用 stats feature 时为空操作
+#[inline(always)]
+pub(crate) fn count(update: impl FnOnce(&mut IntersectionStats)) {
+    #[cfg(feature = "stats")]
+    COUNTERS.with(|counters| {
+        let mut stats = counters.get();
+        update(&mut stats);
+        counters.set(stats);
+    });
+    #[cfg(not(feature = "stats"))]
+    let _ = update;
+}
+
+// 取出当前线程的计数并清零
+pub(crate) fn take() -> IntersectionStats {
+    #[cfg(feature = "stats")]
+    return COUNTERS.with(|counters| counters.take());
+    #[cfg(not(feature = "stats"))]
+    IntersectionStats::default()
+}
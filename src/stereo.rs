@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use crate::camera::Camera;
+use crate::render::{self, RenderOptions};
+use crate::scene::Scene;
+
+/// 立体图像的输出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StereoMode {
+    /// 左眼图像在左、右眼图像在右，宽度为单眼图像的两倍，适用于 VR 设备或平行眼观看
+    #[default]
+    SideBySide,
+    /// 红青立体图，红色通道取自左眼，绿色与蓝色通道取自右眼，配合红青眼镜观看
+    Anaglyph,
+}
+
+impl StereoMode {
+    /// 单眼图像大小为 `width` x `height` 时输出图像的宽和高
+    pub fn image_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            StereoMode::SideBySide => (width * 2, height),
+            StereoMode::Anaglyph => (width, height),
+        }
+    }
+}
+
+/// 立体渲染的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoOptions {
+    pub eye_separation: f32, // 两眼之间的距离（瞳距），与场景使用相同的单位，越大立体感越强
+    pub convergence: Option<f32>, // 零视差平面到摄像机的距离，为 None 时使用摄像机的焦距
+    pub mode: StereoMode,
+}
+
+impl Default for StereoOptions {
+    fn default() -> Self {
+        // Cornell Box 边长为 2，约相当于 5.5 米，人眼瞳距约 6.5 厘米
+        Self { eye_separation: 0.025, convergence: None, mode: StereoMode::SideBySide }
+    }
+}
+
+/// 以 `camera` 为两眼中点渲染一对立体图像并合成，返回 8 位 RGB 数据，
+/// 图像大小见 `StereoMode::image_size`，`image_width`、`image_height` 为单眼图像的大小。
+/// 场景需要已经构建好 BVH，两眼共用同一个 BVH
+#[allow(clippy::too_many_arguments)]
+pub fn render_stereo(
+    scene: Arc<Scene>,
+    camera: &Camera,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    render_options: &RenderOptions,
+    options: &StereoOptions,
+) -> Vec<u8> {
+    let convergence = options.convergence.unwrap_or(camera.focal_length);
+    let (left, right) = camera.stereo_pair(options.eye_separation, convergence);
+    let images = render::render_views(
+        scene,
+        &[Arc::new(left), Arc::new(right)],
+        image_width,
+        image_height,
+        max_depth,
+        samples_per_pixel,
+        render_options,
+    );
+    let (left, right) = (&images[0], &images[1]);
+
+    let row = (image_width * 3) as usize;
+    match options.mode {
+        StereoMode::SideBySide => left.chunks_exact(row)
+            .zip(right.chunks_exact(row))
+            .flat_map(|(left, right)| left.iter().chain(right))
+            .copied()
+            .collect(),
+        StereoMode::Anaglyph => left.chunks_exact(3)
+            .zip(right.chunks_exact(3))
+            .flat_map(|(left, right)| [left[0], right[1], right[2]])
+            .collect(),
+    }
+}
@@ -1,50 +1,485 @@
-use std::collections::HashMap;
-use image::{DynamicImage, GenericImageView, Pixel};
-use glam::Vec3;
-use once_cell::unsync::Lazy;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Pixel, Rgba};
+use glam::{Vec2, Vec3};
 
-static mut TEXTURE_STORAGE: Lazy<HashMap<u32, DynamicImage>> =
-    Lazy::new(|| HashMap::new());
+// 下一张贴图的 ID，用于区分分块存储的临时文件
+static NEXT_TEXTURE_ID: AtomicU32 = AtomicU32::new(0);
 
-static mut NEXT_TEXTURE_ID: u32 = 0;
+// load_from_file 是否使用分块存储
+static TILED_LOADING: AtomicBool = AtomicBool::new(false);
 
-#[derive(Debug, Copy, Clone)]
+const TILE_SIZE: u32 = 64; // 分块存储时每块的边长（像素）
+const MISSING_TEXEL: [u8; 4] = [255, 0, 255, 255]; // 无法读取的块使用的颜色（品红），在渲染结果中容易发现
+
+/// 贴图在内存中的存储方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextureStorage {
+    /// 加载时解码整张图像并常驻内存
+    #[default]
+    Decoded,
+    /// 加载时解码一次，按 64 x 64 像素分块写入临时文件后释放，采样时按需读入用到的块，
+    /// 只有被访问的区域占用内存，适合大量高分辨率照片贴图的场景；首次访问每块时需要读取文件。
+    /// 加载时仍会解码整张图像并生成所有 mipmap 层级，写完临时文件后才释放，因此加载期间的峰值内存
+    /// 与 `Decoded` 相同，节省的是加载之后常驻的内存。临时文件无法写入时给出警告并改为 `Decoded`
+    Tiled,
+}
+
+/// 从球外看单位方向 `direction` 所在点的贴图坐标：经度对应 u，+z 方向位于贴图中央，向 +x 增大；
+/// 纬度对应 v，v = 1 为正上方。与其他贴图坐标的约定一致，贴图从外侧看是正立、不镜像的
+pub fn spherical_uv(direction: Vec3) -> Vec2 {
+    let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * std::f32::consts::PI);
+    let v = 0.5 + direction.y.clamp(-1.0, 1.0).asin() / std::f32::consts::PI;
+    Vec2::new(u, v)
+}
+
+/// 设置之后 `Texture::load_from_file` 与 `Texture::load_from_memory` 加载贴图
+/// （包括 `Scene::add_obj` 从 MTL 加载、`Scene::add_gltf` 从 glTF 加载的贴图）使用的存储方式
+pub fn set_storage(storage: TextureStorage) {
+    TILED_LOADING.store(storage == TextureStorage::Tiled, Ordering::Relaxed);
+}
+
+// set_storage 设置的存储方式，默认解码后常驻内存
+fn default_storage() -> TextureStorage {
+    if TILED_LOADING.load(Ordering::Relaxed) {
+        TextureStorage::Tiled
+    } else {
+        TextureStorage::Decoded
+    }
+}
+
+// 一张贴图的所有 mipmap 层级，第 0 层为原始图像，之后每层的宽高减半，直到 1 x 1
+enum Storage {
+    Decoded(Vec<DynamicImage>),
+    Tiled(TiledImage),
+}
+
+impl Storage {
+    fn level_count(&self) -> usize {
+        match self {
+            Storage::Decoded(levels) => levels.len(),
+            Storage::Tiled(image) => image.levels.len(),
+        }
+    }
+
+    fn dimensions(&self, level: usize) -> (u32, u32) {
+        match self {
+            Storage::Decoded(levels) => levels[level].dimensions(),
+            Storage::Tiled(image) => (image.levels[level].width, image.levels[level].height),
+        }
+    }
+
+    fn has_alpha(&self) -> bool {
+        match self {
+            Storage::Decoded(levels) => levels[0].color().has_alpha(),
+            Storage::Tiled(image) => image.has_alpha,
+        }
+    }
+
+    fn pixel(&self, level: usize, x: u32, y: u32) -> Rgba<u8> {
+        match self {
+            Storage::Decoded(levels) => levels[level].get_pixel(x, y),
+            Storage::Tiled(image) => image.pixel(level, x, y),
+        }
+    }
+
+    // 读入所有尚未读入的块，返回常驻内存的字节数
+    fn preload(&self) -> usize {
+        match self {
+            Storage::Decoded(levels) => levels.iter().map(|level| level.as_bytes().len()).sum(),
+            Storage::Tiled(image) => image.preload(),
+        }
+    }
+}
+
+// 分块存储的贴图，各层的块按行优先顺序依次存放在临时文件中，每块为 TILE_SIZE x TILE_SIZE 个 RGBA 像素，
+// 边缘不足一块的部分同样占据整块
+struct TiledImage {
+    file: Mutex<File>,
+    levels: Vec<TiledLevel>,
+    has_alpha: bool,
+    _temp: Option<TempFile>, // 打开后未能删除的临时文件，在 file 关闭之后删除，因此声明在 file 之后
+}
+
+// 临时文件的路径，释放时删除该文件
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+struct TiledLevel {
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    offset: u64, // 该层第一块在文件中的位置
+    tiles: Vec<OnceLock<Box<[u8]>>>, // 已经读入内存的块
+}
+
+impl TiledImage {
+    const TILE_BYTES: usize = (TILE_SIZE * TILE_SIZE * 4) as usize;
+
+    // 临时文件写在 directory 中
+    fn new(directory: &Path, levels: &[DynamicImage], has_alpha: bool, id: u32) -> std::io::Result<Self> {
+        let path = directory.join(format!("nebula-texture-{}-{id}.tiles", std::process::id()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        // 写入失败返回错误时同样删除临时文件
+        let temp = TempFile(path);
+
+        let mut offset = 0;
+        let mut tiled_levels = vec![];
+        for level in levels {
+            let level = level.to_rgba8();
+            let (width, height) = level.dimensions();
+            let (tiles_x, tiles_y) = (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE));
+            for tile_y in 0..tiles_y {
+                for tile_x in 0..tiles_x {
+                    let mut tile = vec![0; Self::TILE_BYTES];
+                    for y in 0..TILE_SIZE.min(height - tile_y * TILE_SIZE) {
+                        for x in 0..TILE_SIZE.min(width - tile_x * TILE_SIZE) {
+                            let pixel = level.get_pixel(tile_x * TILE_SIZE + x, tile_y * TILE_SIZE + y);
+                            let index = ((y * TILE_SIZE + x) * 4) as usize;
+                            tile[index..index + 4].copy_from_slice(&pixel.0);
+                        }
+                    }
+                    writer.write_all(&tile)?;
+                }
+            }
+            let count = (tiles_x * tiles_y) as usize;
+            tiled_levels.push(TiledLevel {
+                width,
+                height,
+                tiles_x,
+                offset,
+                tiles: (0..count).map(|_| OnceLock::new()).collect(),
+            });
+            offset += (count * Self::TILE_BYTES) as u64;
+        }
+
+        writer.flush()?;
+        drop(writer);
+        let file = File::open(&temp.0)?;
+        // 已经打开的文件在删除后仍然可以读取（Unix）；不支持时（Windows）在贴图释放、文件关闭之后删除
+        let temp = fs::remove_file(&temp.0).is_err().then_some(temp);
+        Ok(Self { file: Mutex::new(file), levels: tiled_levels, has_alpha, _temp: temp })
+    }
+
+    fn pixel(&self, level: usize, x: u32, y: u32) -> Rgba<u8> {
+        let tiled_level = &self.levels[level];
+        let index = ((y / TILE_SIZE) * tiled_level.tiles_x + x / TILE_SIZE) as usize;
+        let tile = self.tile(tiled_level, index);
+        let offset = (((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE) * 4) as usize;
+        Rgba(tile[offset..offset + 4].try_into().unwrap())
+    }
+
+    // 第 index 块，第一次访问时从临时文件读入；读取失败（例如临时文件被清理）时警告，
+    // 该块使用 MISSING_TEXEL 填充，不会使渲染线程 panic
+    fn tile<'a>(&self, tiled_level: &'a TiledLevel, index: usize) -> &'a [u8] {
+        tiled_level.tiles[index].get_or_init(|| {
+            let mut tile = vec![0; Self::TILE_BYTES].into_boxed_slice();
+            let mut file = self.file.lock().unwrap();
+            let read = file.seek(SeekFrom::Start(tiled_level.offset + (index * Self::TILE_BYTES) as u64))
+                .and_then(|_| file.read_exact(&mut tile));
+            if let Err(error) = read {
+                eprintln!("Warning: failed to read texture tile {index} ({error}), using magenta");
+                tile.chunks_exact_mut(4).for_each(|texel| texel.copy_from_slice(&MISSING_TEXEL));
+            }
+            tile
+        })
+    }
+
+    // 读入所有层的所有块，返回块占用的字节数
+    fn preload(&self) -> usize {
+        let mut tiles = 0;
+        for tiled_level in &self.levels {
+            for index in 0..tiled_level.tiles.len() {
+                self.tile(tiled_level, index);
+            }
+            tiles += tiled_level.tiles.len();
+        }
+        tiles * Self::TILE_BYTES
+    }
+}
+
+/// 贴图，复制的贴图共享同一份数据，最后一个副本释放时数据随之释放，可以在多个线程中并发加载与采样
+#[derive(Clone)]
 pub struct Texture {
     id: u32, // 全局的贴图 ID
+    storage: Arc<Storage>,
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Texture").field("id", &self.id).finish_non_exhaustive()
+    }
 }
 
 impl Texture {
-    // 从文件加载贴图，不支持并发加载
+    // 从文件加载贴图，存储方式由 set_storage 决定，默认解码后常驻内存
     pub fn load_from_file(file_path: &str) -> Self {
+        Self::load_from_file_with(file_path, default_storage())
+    }
+
+    // 以指定的存储方式从文件加载贴图
+    pub fn load_from_file_with(file_path: &str, storage: TextureStorage) -> Self {
         let image = image::open(file_path).expect("Failed to load texture image");
+        Self::from_image(image, storage)
+    }
 
-        let id = unsafe {
-            let id = NEXT_TEXTURE_ID;
-            TEXTURE_STORAGE.insert(id, image);
-            NEXT_TEXTURE_ID += 1;
-            id
+    /// 从内存中的已编码图像（PNG、JPEG 等）加载贴图，例如 glTF 中内嵌的图像，存储方式与 `load_from_file` 相同
+    pub fn load_from_memory(bytes: &[u8]) -> image::ImageResult<Self> {
+        Ok(Self::from_image(image::load_from_memory(bytes)?, default_storage()))
+    }
+
+    // 由解码后的图像生成各层 mipmap 并按存储方式保存
+    fn from_image(image: DynamicImage, storage: TextureStorage) -> Self {
+        Self::from_image_in(image, storage, &std::env::temp_dir())
+    }
+
+    // 分块存储的临时文件写在 directory 中，无法写入时给出警告并改为解码存储
+    fn from_image_in(image: DynamicImage, storage: TextureStorage, directory: &Path) -> Self {
+        let has_alpha = image.color().has_alpha();
+
+        let mut levels = vec![image];
+        loop {
+            let (width, height) = levels.last().unwrap().dimensions();
+            if width <= 1 && height <= 1 {
+                break;
+            }
+            let level = levels.last().unwrap()
+                .resize_exact((width / 2).max(1), (height / 2).max(1), FilterType::Triangle);
+            levels.push(level);
+        }
+
+        let id = NEXT_TEXTURE_ID.fetch_add(1, Ordering::Relaxed);
+        let storage = match storage {
+            TextureStorage::Decoded => Storage::Decoded(levels),
+            TextureStorage::Tiled => match TiledImage::new(directory, &levels, has_alpha, id) {
+                Ok(tiled) => Storage::Tiled(tiled),
+                Err(error) => {
+                    eprintln!("Warning: failed to write texture tiles ({error}), keeping the texture decoded");
+                    Storage::Decoded(levels)
+                }
+            },
         };
 
-        Texture { id }
+        Texture { id, storage: Arc::new(storage) }
+    }
+
+    /// 全局的贴图 ID，每次加载得到的贴图各不相同，复制的贴图共享同一个 ID，可以用于去重
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// 使贴图的所有数据常驻内存：分块存储的贴图读入所有尚未读入的块，之后采样不再读取临时文件，也不再加锁；
+    /// 解码存储的贴图已经常驻内存。返回贴图所有 mipmap 层级占用的内存（字节）
+    pub fn preload(&self) -> usize {
+        self.storage().preload()
+    }
+
+    /// 原始图像的宽和高（像素）
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.storage().dimensions(0)
+    }
+
+    /// 是否为灰度图，按整张图的平均颜色（最小一层 mipmap）判断，RGB 三个分量接近时为真；
+    /// 用于区分灰度高度图与整体偏蓝的切线空间法线贴图
+    pub fn is_grayscale(&self) -> bool {
+        let storage = self.storage();
+        let rgb = storage.pixel(storage.level_count() - 1, 0, 0).to_rgb();
+        let (min, max) = (rgb.0.iter().min().unwrap(), rgb.0.iter().max().unwrap());
+        max - min < 26
     }
 
-    /// 通过 uv 坐标获取颜色值，其中 u，v 属于 [0.0, 1.0]
+    /// 通过 uv 坐标获取颜色值，超出 [0.0, 1.0] 的坐标按重复方式环绕，贴图平铺在表面上。
+    /// 贴图坐标与 OBJ 的 vt 一致：v = 0 为图像最下面一行，v = 1 为最上面一行，只在采样时翻转一次，
+    /// 三角面、球面、高度场与环境贴图都按此约定生成坐标
     pub fn sample(&self, u: f32, v: f32) -> Vec3 {
-        unsafe {
-            let image = TEXTURE_STORAGE.get(&self.id).unwrap();
-            let (width, height) = image.dimensions();
+        let rgb = self.texel(u, v).to_rgb();
 
-            let x = (u * width as f32) as u32;
-            let y = ((1.0 - v) * height as f32) as u32; // v 轴需要翻转
+        Vec3::new(
+            rgb[0] as f32 / 255.0,
+            rgb[1] as f32 / 255.0,
+            rgb[2] as f32 / 255.0,
+        )
+    }
 
-            let pixel = image.get_pixel(x.min(width - 1), y.min(height - 1));
-            let rgb = pixel.to_rgb();
+    /// 通过 uv 坐标获取 alpha 值，贴图不含 alpha 通道时使用灰度值
+    pub fn sample_alpha(&self, u: f32, v: f32) -> f32 {
+        let has_alpha = self.storage().has_alpha();
+        let texel = self.texel(u, v);
+        if has_alpha {
+            texel[3] as f32 / 255.0
+        } else {
+            texel.to_luma()[0] as f32 / 255.0
+        }
+    }
 
-            Vec3::new(
-                rgb[0] as f32 / 255.0,
-                rgb[1] as f32 / 255.0,
-                rgb[2] as f32 / 255.0,
-            )
+    /// 三线性过滤采样，`footprint` 为像素在贴图坐标中覆盖的宽度，据此在 mipmap 的相邻两层之间
+    /// 进行双线性采样并插值，远处的细节不会产生闪烁与摩尔纹
+    pub fn sample_filtered(&self, u: f32, v: f32, footprint: f32) -> Vec3 {
+        let storage = self.storage();
+        let (width, height) = storage.dimensions(0);
+        let level = (footprint * width.max(height) as f32).max(1.0).log2()
+            .min((storage.level_count() - 1) as f32);
+        let lower = level.floor() as usize;
+        let t = level - lower as f32;
+
+        let color = Self::bilinear(storage, lower, u, v);
+        if t > 0.0 {
+            color.lerp(Self::bilinear(storage, lower + 1, u, v), t)
+        } else {
+            color
         }
     }
+
+    // 在一层 mipmap 上进行双线性采样，超出范围的坐标按重复方式环绕
+    fn bilinear(storage: &Storage, level: usize, u: f32, v: f32) -> Vec3 {
+        let (width, height) = storage.dimensions(level);
+        let x = u * width as f32 - 0.5;
+        let y = (1.0 - v) * height as f32 - 0.5; // v 轴需要翻转
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let texel = |x: f32, y: f32| {
+            let (x, y) = (wrap(x, width), wrap(y, height));
+            let rgb = storage.pixel(level, x, y).to_rgb();
+            Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32) / 255.0
+        };
+        let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), tx);
+        let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), tx);
+        top.lerp(bottom, ty)
+    }
+
+    // 获取 uv 坐标处的像素
+    fn texel(&self, u: f32, v: f32) -> Rgba<u8> {
+        let storage = self.storage();
+        let (width, height) = storage.dimensions(0);
+
+        let x = wrap((u * width as f32).floor(), width);
+        let y = wrap(((1.0 - v) * height as f32).floor(), height); // v 轴需要翻转
+
+        storage.pixel(0, x, y)
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+}
+
+/// 预加载的贴图数量与占用的内存，见 `Scene::preload_textures`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextureMemory {
+    pub textures: usize, // 贴图数量，同一张贴图被多个材质引用时只计一次
+    pub bytes: usize, // 所有贴图及其 mipmap 占用的内存（字节）
+}
+
+impl fmt::Display for TextureMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} textures, {:.1} MiB", self.textures, self.bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+// 将像素坐标环绕到 [0, size) 内，非有限值取 0
+fn wrap(x: f32, size: u32) -> u32 {
+    if !x.is_finite() {
+        return 0;
+    }
+    (x as i64).rem_euclid(size as i64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiled(width: u32, height: u32) -> TiledImage {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255])));
+        TiledImage::new(&std::env::temp_dir(), &[image], true, NEXT_TEXTURE_ID.fetch_add(1, Ordering::Relaxed)).unwrap()
+    }
+
+    #[test]
+    fn tiles_are_read_back() {
+        let image = tiled(100, 70);
+        assert_eq!(image.pixel(0, 99, 69), Rgba([10, 20, 30, 255]));
+        assert_eq!(image.preload(), 4 * TiledImage::TILE_BYTES);
+    }
+
+    #[test]
+    fn unreadable_tiles_fall_back_to_magenta() {
+        let image = tiled(100, 70);
+        assert_eq!(image.pixel(0, 0, 0), Rgba([10, 20, 30, 255]));
+        // 换成空文件，模拟临时文件被截断或清理
+        let empty = std::env::temp_dir().join(format!("nebula-texture-test-{}.tiles", std::process::id()));
+        *image.file.lock().unwrap() = File::create(&empty).and_then(|_| File::open(&empty)).unwrap();
+        let _ = fs::remove_file(&empty);
+        // 已经读入的块不受影响，之后读入的块为品红
+        assert_eq!(image.pixel(0, 0, 0), Rgba([10, 20, 30, 255]));
+        assert_eq!(image.pixel(0, 99, 69), Rgba(MISSING_TEXEL));
+        image.preload();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temporary_tile_files_are_removed() {
+        let id = NEXT_TEXTURE_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("nebula-texture-{}-{id}.tiles", std::process::id()));
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(8, 8));
+        let tiled = TiledImage::new(&std::env::temp_dir(), &[image], false, id).unwrap();
+        assert!(!path.exists());
+        assert!(tiled._temp.is_none());
+    }
+
+    #[test]
+    fn spherical_uv_at_known_directions() {
+        let close = |direction: Vec3, u: f32, v: f32| spherical_uv(direction).abs_diff_eq(Vec2::new(u, v), 1e-6);
+        assert!(close(Vec3::Z, 0.5, 0.5));
+        // 从球外看，+x 在 +z 的右侧，u 向右增大
+        assert!(close(Vec3::X, 0.75, 0.5));
+        assert!(close(Vec3::NEG_X, 0.25, 0.5));
+        assert!(close(Vec3::Y, 0.5, 1.0));
+        assert!(close(Vec3::NEG_Y, 0.5, 0.0));
+    }
+
+    #[test]
+    fn v_one_is_the_top_row() {
+        // 左上红、右上绿、左下蓝、右下白
+        let mut image = image::RgbImage::new(2, 2);
+        image.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        image.put_pixel(1, 0, image::Rgb([0, 255, 0]));
+        image.put_pixel(0, 1, image::Rgb([0, 0, 255]));
+        image.put_pixel(1, 1, image::Rgb([255, 255, 255]));
+        let texture = Texture::from_image(DynamicImage::ImageRgb8(image), TextureStorage::Decoded);
+        assert_eq!(texture.sample(0.25, 0.75), Vec3::X);
+        assert_eq!(texture.sample(0.75, 0.75), Vec3::Y);
+        assert_eq!(texture.sample(0.25, 0.25), Vec3::Z);
+        assert_eq!(texture.sample(0.75, 0.25), Vec3::ONE);
+    }
+
+    #[test]
+    fn unwritable_tiles_fall_back_to_decoded() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(8, 8, Rgba([10, 20, 30, 255])));
+        let missing = std::env::temp_dir().join(format!("nebula-missing-{}", std::process::id()));
+        let texture = Texture::from_image_in(image, TextureStorage::Tiled, &missing);
+        assert!(matches!(*texture.storage, Storage::Decoded(_)));
+        assert_eq!(texture.sample(0.5, 0.5), Vec3::new(10.0, 20.0, 30.0) / 255.0);
+    }
+
+    #[test]
+    fn storage_is_freed_with_the_last_copy() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(8, 8));
+        let texture = Texture::from_image(image, TextureStorage::Tiled);
+        let copy = texture.clone();
+        let storage = Arc::downgrade(&texture.storage);
+        drop(texture);
+        assert_eq!(copy.sample(0.5, 0.5), Vec3::ZERO);
+        drop(copy);
+        assert!(storage.upgrade().is_none());
+    }
 }
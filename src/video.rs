@@ -0,0 +1,71 @@
+use std::io::{self, ErrorKind};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use crate::camera::Camera;
+use crate::render;
+use crate::scene::Scene;
+
+/// 逐帧渲染动画，并通过管道将 PPM 帧交给 ffmpeg 编码为视频，避免写出大量中间图片
+///
+/// `frame` 根据帧序号返回该帧使用的场景与摄像机，场景需要已经构建好 BVH。
+/// 没有安装 ffmpeg 时返回 `ErrorKind::NotFound` 错误
+#[allow(clippy::too_many_arguments)]
+pub fn render_video<F>(
+    filename: &str,
+    image_width: u32,
+    image_height: u32,
+    max_depth: u32,
+    samples_per_pixel: u32,
+    fps: u32,
+    frame_count: u32,
+    mut frame: F,
+) -> io::Result<()>
+where
+    F: FnMut(u32) -> (Arc<Scene>, Arc<Camera>),
+{
+    let ffmpeg = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error"])
+        .args(["-f", "image2pipe", "-vcodec", "ppm", "-framerate", &fps.to_string(), "-i", "-"])
+        // yuv420p 要求宽高均为偶数
+        .args(["-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2", "-pix_fmt", "yuv420p", filename])
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut ffmpeg = match ffmpeg {
+        Ok(ffmpeg) => ffmpeg,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "ffmpeg not found, install it and make sure it is in PATH",
+            ));
+        }
+        Err(error) => return Err(error),
+    };
+
+    let mut stdin = ffmpeg.stdin.take().unwrap();
+    for index in 0..frame_count {
+        let (scene, camera) = frame(index);
+        let image_data = render::render(
+            scene,
+            camera,
+            image_width,
+            image_height,
+            max_depth,
+            samples_per_pixel,
+        );
+        if let Err(error) = render::write_ppm(&mut stdin, &image_data, image_width, image_height) {
+            // ffmpeg 提前退出时管道会断开，等待其退出后再返回错误
+            drop(stdin);
+            ffmpeg.wait()?;
+            return Err(error);
+        }
+    }
+
+    // 关闭管道，通知 ffmpeg 输入已经结束
+    drop(stdin);
+    let status = ffmpeg.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! 视觉回归测试：以固定种子在低分辨率下渲染示例场景，并与 tests/fixtures/reference 中的参考图像比较。
+//!
+//! 渲染结果有意改变时，设置环境变量 `NEBULA_UPDATE_REFERENCE=1` 运行本测试即可重新生成参考图像。
+
+use std::sync::Arc;
+use glam::{Mat4, Vec3};
+use nebula::camera::Camera;
+use nebula::render::{self, RenderOptions};
+use nebula::scene::Scene;
+
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 32;
+
+fn reference(name: &str) -> String {
+    format!("{}/tests/fixtures/reference/{name}.png", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn render_scene(obj: &str) -> Vec<u8> {
+    let mut scene = Scene::new();
+    scene.add_obj(&format!("{}/scenes/{obj}.obj", env!("CARGO_MANIFEST_DIR")), Mat4::IDENTITY);
+    scene.build_bvh();
+    let camera = Camera::new(
+        Vec3::new(0.0, 1.0, 3.0),
+        Vec3::new(0.0, 1.0, -1.0),
+        Vec3::Y,
+        60.0,
+        WIDTH as f32 / HEIGHT as f32,
+        4.0,
+        0.0,
+    );
+    let options = RenderOptions { seed: Some(1), ..Default::default() };
+    render::render_with_options(Arc::new(scene), Arc::new(camera), WIDTH, HEIGHT, 4, 64, &options)
+}
+
+// 与参考图像比较；不同平台上浮点运算的细微差别可能让个别路径走向不同分支，因此允许少量噪声
+fn check(name: &str, obj: &str) {
+    let image = render_scene(obj);
+    let filename = reference(name);
+    if std::env::var_os("NEBULA_UPDATE_REFERENCE").is_some() {
+        render::save_image_as_png(image, WIDTH, HEIGHT, &filename);
+        return;
+    }
+    let difference = render::compare_with_reference(&image, WIDTH, HEIGHT, &filename)
+        .expect("Failed to read reference image");
+    assert!(difference.within(0.005, 0.5), "{name} differs from reference: {difference:?}");
+}
+
+#[test]
+fn cornell_box_mirror_matches_reference() {
+    check("cornell_box_mirror", "CornellBoxMirror");
+}
+
+#[test]
+fn cornell_box_sphere_matches_reference() {
+    check("cornell_box_sphere", "CornellBoxSphere");
+}